@@ -2,79 +2,120 @@ import os
 import requests
 import uuid
 import asyncio
-import aio_pika
-import json
-from aio_pika.abc import AbstractIncomingMessage
+import threading
+import time
 from rich.console import Console
-from uuid import UUID
-from ..utils.coordinator import Coordinator
+from ..utils.coordinator import Coordinator, TLSConfig, stops_task
 from ..utils.extraction import Extraction
-from ..utils.types import ExtractionTask
+from ..utils.types import DocumentError, ExtractionTask, Worker
 
-QUEUE_NAME = "tasks"
 SLEEP = 5
 
+# Seconds between heartbeats to the coordinator.
+# This must be well below the worker and lease timeouts of the coordinator
+# since the lease of the current task is renewed with every heartbeat.
+HEARTBEAT_INTERVAL = 10
+
 console = Console()
 
 
-async def async_loop():
-    queue_url = os.getenv("DL_QUEUE_URL")
-    if not queue_url:
-        message = "ERROR: Please provide a valid RabbitMQ URL"
-        console.log(message, style="red")
-        return
+class LeaseLost(Exception):
+    """Raised when the lease of the task is lost during its extraction."""
+
 
+async def async_loop():
     host = os.getenv("DL_EXTRACTOR_HOST", external_ip_address())
     port = int(os.getenv("DL_EXTRACTOR_PORT", 2510))
-    worker = {
-        "id": str(uuid.uuid4()),
-        "address": f"{host}:{port}",
-    }
+    worker = Worker(id=str(uuid.uuid4()), address=f"{host}:{port}")
+
+    worker_token = os.getenv("DL_WORKER_TOKEN")
+    if not worker_token:
+        message = "ERROR: Please provide a valid worker token"
+        console.log(message, style="red")
+        return
 
     coordinator_addr = os.getenv("DL_COORDINATOR_ADDR", "0.0.0.0:2500")
-    coordinator: Coordinator
-    should_register = True
+    coordinator_tls = None
+    if os.getenv("DL_COORDINATOR_TLS_CA"):
+        coordinator_tls = TLSConfig(
+            ca=os.getenv("DL_COORDINATOR_TLS_CA"),
+            cert=os.getenv("DL_WORKER_TLS_CERT"),
+            key=os.getenv("DL_WORKER_TLS_KEY"),
+        )
+
+    coordinator = Coordinator(
+        address=coordinator_addr,
+        token=worker_token,
+        tls=coordinator_tls,
+    )
+
+    # Heartbeats are sent from a separate thread so that the worker stays
+    # registered while it is busy extracting a document.
+    heartbeat = threading.Thread(
+        target=heartbeat_loop,
+        args=(coordinator, worker),
+        daemon=True,
+    )
+
+    heartbeat.start()
 
     while True:
+        # Draining workers stay registered but take no more tasks.
+        if not worker.registered or worker.draining:
+            await asyncio.sleep(SLEEP)
+            continue
+
         try:
-            coordinator = Coordinator(address=coordinator_addr)
-            coordinator.heartbeat()
+            task = coordinator.claim_task(worker.id)
         except Exception:
-            message = "ERROR: Failed to connect to the coordinator server"
+            message = "ERROR: Failed to claim a task from the coordinator"
             console.log(message, style="red")
-            should_register = True
             await asyncio.sleep(SLEEP)
             continue
 
-        # When the worker starts or loses connection to the coordinator,
-        # it must register itself again.
-        if should_register:
-            coordinator.register_worker(**worker)
-            should_register = False
+        if task is None:
+            await asyncio.sleep(SLEEP)
+            continue
+
+        await process_task(coordinator, worker, task)
 
-            message = "INFO: Registered the worker to the coordinator server"
-            console.log(message, style="green")
 
-        # Attempt to connect to the RabbitMQ server.
-        # Putting this inside the loop allows the worker to recover from
-        # connection failures without stopping the process.
+def heartbeat_loop(coordinator: Coordinator, worker: Worker):
+    while True:
         try:
-            queue_connection = await aio_pika.connect_robust(queue_url)
+            response = coordinator.heartbeat(worker)
+
+            # The coordinator expires workers that miss heartbeats.
+            # When the worker starts or expires, it must register again.
+            if not response.registered:
+                coordinator.register_worker(worker.id, worker.address)
+                message = "INFO: Registered the worker to the coordinator"
+                console.log(message, style="green")
+
+            worker.registered = True
+            if response.draining and not worker.draining:
+                message = "INFO: The worker is draining its tasks"
+                console.log(message, style="green")
+
+            worker.draining = response.draining
         except Exception:
-            error = "ERROR: Failed to connect to the RabbitMQ server"
-            console.log(error, style="red")
-            await asyncio.sleep(SLEEP)
-            continue
+            message = "ERROR: Failed to connect to the coordinator server"
+            console.log(message, style="red")
+            worker.registered = False
 
-        async with queue_connection:
-            channel = await queue_connection.channel()
-            # We use get queue method since the queue is guaranteed to exist.
-            queue = await channel.get_queue(QUEUE_NAME)
-            message = await queue.get(no_ack=False, fail=False)
-            if message is not None:
-                await process_message(coordinator, message)
+        # The coordinator requeues the task if the lease expires, so the
+        # extraction is aborted once the lease can't be renewed since the
+        # task may be handed to another worker.
+        task = worker.task
+        if worker.registered and task is not None:
+            try:
+                coordinator.renew_lease(worker.id, str(task.document_id))
+            except Exception:
+                message = "ERROR: Failed to renew the lease of the task"
+                console.log(message, style="red")
+                worker.lease_lost = True
 
-        await asyncio.sleep(SLEEP)
+        time.sleep(HEARTBEAT_INTERVAL)
 
 
 def external_ip_address() -> str:
@@ -82,27 +123,111 @@ def external_ip_address() -> str:
     return response.json()["ip"]
 
 
-async def process_message(
+async def process_task(
+    coordinator: Coordinator,
+    worker: Worker,
+    task: ExtractionTask,
+):
+    namespace = task.namespace
+    document_id = str(task.document_id)
+
+    console.log(f"INFO: Processing document {document_id}...")
+    worker.task = task
+    worker.lease_lost = False
+
+    try:
+        coordinator.update_document(
+            worker.id,
+            namespace,
+            document_id,
+            "processing",
+        )
+
+        await extract_document(coordinator, worker, task)
+        worker.completed += 1
+    except LeaseLost:
+        message = f"ERROR: Aborted document {document_id} as its lease is lost"
+        console.log(message, style="red")
+    except Exception as e:
+        if stops_task(e):
+            message = f"ERROR: Stopped document {document_id}: {e.details()}"
+            console.log(message, style="red")
+            return
+
+        # The failure is reported to the coordinator which releases the
+        # lease. Otherwise, the task is requeued once the lease expires.
+        message = f"ERROR: Failed to process document {document_id}: {e}"
+        console.log(message, style="red")
+        worker.failed += 1
+    finally:
+        worker.task = None
+
+
+async def extract_document(
     coordinator: Coordinator,
-    message: AbstractIncomingMessage,
+    worker: Worker,
+    task: ExtractionTask,
 ):
-    async with message.process(ignore_processed=True):
-        # The message is a JSON string.
-        # Check server/src/types.rs for the ExtractionTask struct.
-        body = json.loads(message.body.decode())
-        namespace = body["namespace"]
-        document_key = body["document_key"]
-        document_id = body["document_id"]
-
-        console.log(f"INFO: Processing document {document_id}...")
-        coordinator.update_document(namespace, document_id, "processing")
-
-        task = ExtractionTask(namespace, document_key, UUID(document_id))
+    namespace = task.namespace
+    document_id = str(task.document_id)
+
+    try:
         path = task.download_document()
+    except Exception as e:
+        report_failure(coordinator, worker, task, "download_failed", e)
+        raise
 
-        extraction = Extraction(path)
-        results = extraction.extract()
-        console.log(f"INFO: Extracted {len(results)} chunks from the document")
+    # The document is removed however the task ends, including when the
+    # coordinator stops it.
+    try:
+        ensure_lease(worker)
 
-        coordinator.create_chunk(namespace, document_id, results)
+        try:
+            extraction = Extraction(path)
+            results = extraction.extract()
+        except Exception as e:
+            report_failure(coordinator, worker, task, "extraction_failed", e)
+            raise
+
+        message = f"INFO: Extracted {len(results)} chunks from the document"
+        console.log(message)
+
+        # The extraction can outlast the lease when the renewals fail, and
+        # the coordinator would reject the results of the lost lease anyway.
+        ensure_lease(worker)
+
+        coordinator.update_document(
+            worker.id,
+            namespace,
+            document_id,
+            "processing",
+            properties=extraction.properties,
+        )
+
+        coordinator.stream_chunks(worker.id, namespace, document_id, results)
+    finally:
         task.cleanup()
+
+
+def ensure_lease(worker: Worker):
+    if worker.lease_lost:
+        raise LeaseLost()
+
+
+def report_failure(
+    coordinator: Coordinator,
+    worker: Worker,
+    task: ExtractionTask,
+    code: str,
+    exception: Exception,
+):
+    # The failure reason is stored on the document by the coordinator so
+    # users don't have to check the worker logs.
+    error = DocumentError(code, str(exception))
+    coordinator.update_document(
+        worker.id,
+        task.namespace,
+        str(task.document_id),
+        "failed",
+        error=error,
+    )
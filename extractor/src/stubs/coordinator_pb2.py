@@ -22,7 +22,7 @@ from google.protobuf import empty_pb2 as google_dot_protobuf_dot_empty__pb2
 
 
 DESCRIPTOR = _descriptor_pool.Default().AddSerializedFile(
-    b'\n\x11\x63oordinator.proto\x12\x0b\x63oordinator\x1a\x1bgoogle/protobuf/empty.proto"$\n\x11HeartbeatResponse\x12\x0f\n\x07version\x18\x01 \x01(\t"4\n\x15RegisterWorkerRequest\x12\n\n\x02id\x18\x01 \x01(\t\x12\x0f\n\x07\x61\x64\x64ress\x18\x02 \x01(\t"l\n\x15UpdateDocumentRequest\x12\x11\n\tnamespace\x18\x01 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x02 \x01(\t\x12+\n\x06status\x18\x03 \x01(\x0e\x32\x1b.coordinator.DocumentStatus"`\n\x12\x43reateChunkRequest\x12\x11\n\tnamespace\x18\x01 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x02 \x01(\t\x12"\n\x06\x63hunks\x18\x03 \x03(\x0b\x32\x12.coordinator.Chunk"&\n\x05\x43hunk\x12\x0c\n\x04page\x18\x01 \x01(\r\x12\x0f\n\x07\x63ontent\x18\x03 \x01(\t*H\n\x0e\x44ocumentStatus\x12\x0b\n\x07PENDING\x10\x00\x12\x0e\n\nPROCESSING\x10\x01\x12\r\n\tCOMPLETED\x10\x02\x12\n\n\x06\x46\x41ILED\x10\x03\x32\xbe\x02\n\x0b\x43oordinator\x12\x45\n\tHeartbeat\x12\x16.google.protobuf.Empty\x1a\x1e.coordinator.HeartbeatResponse"\x00\x12N\n\x0eRegisterWorker\x12".coordinator.RegisterWorkerRequest\x1a\x16.google.protobuf.Empty"\x00\x12N\n\x0eUpdateDocument\x12".coordinator.UpdateDocumentRequest\x1a\x16.google.protobuf.Empty"\x00\x12H\n\x0b\x43reateChunk\x12\x1f.coordinator.CreateChunkRequest\x1a\x16.google.protobuf.Empty"\x00\x62\x06proto3'
+    b'\n\x11\x63oordinator.proto\x12\x0b\x63oordinator\x1a\x1bgoogle/protobuf/empty.proto"\xa8\x01\n\x10HeartbeatRequest\x12\x11\n\tworker_id\x18\x01 \x01(\t\x12)\n\x06status\x18\x02 \x01(\x0e\x32\x19.coordinator.WorkerStatus\x12*\n\x04task\x18\x03 \x01(\x0b\x32\x17.coordinator.WorkerTaskH\x00\x88\x01\x01\x12\x11\n\tcompleted\x18\x04 \x01(\x04\x12\x0e\n\x06\x66\x61iled\x18\x05 \x01(\x04\x42\x07\n\x05_task"4\n\nWorkerTask\x12\x11\n\tnamespace\x18\x01 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x02 \x01(\t"J\n\x11HeartbeatResponse\x12\x0f\n\x07version\x18\x01 \x01(\t\x12\x12\n\nregistered\x18\x02 \x01(\x08\x12\x10\n\x08\x64raining\x18\x03 \x01(\x08"4\n\x15RegisterWorkerRequest\x12\n\n\x02id\x18\x01 \x01(\t\x12\x0f\n\x07\x61\x64\x64ress\x18\x02 \x01(\t"%\n\x10\x43laimTaskRequest\x12\x11\n\tworker_id\x18\x01 \x01(\t"Z\n\x11\x43laimTaskResponse\x12$\n\x04task\x18\x01 \x01(\x0b\x32\x11.coordinator.TaskH\x00\x88\x01\x01\x12\x16\n\x0elease_duration\x18\x02 \x01(\rB\x07\n\x05_task"D\n\x04Task\x12\x11\n\tnamespace\x18\x01 \x01(\t\x12\x14\n\x0c\x64ocument_key\x18\x02 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x03 \x01(\t";\n\x11RenewLeaseRequest\x12\x11\n\tworker_id\x18\x01 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x02 \x01(\t"\x82\x02\n\x15UpdateDocumentRequest\x12\x11\n\tnamespace\x18\x01 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x02 \x01(\t\x12+\n\x06status\x18\x03 \x01(\x0e\x32\x1b.coordinator.DocumentStatus\x12\x38\n\nproperties\x18\x04 \x01(\x0b\x32\x1f.coordinator.DocumentPropertiesH\x00\x88\x01\x01\x12.\n\x05\x65rror\x18\x05 \x01(\x0b\x32\x1a.coordinator.DocumentErrorH\x01\x88\x01\x01\x12\x11\n\tworker_id\x18\x06 \x01(\tB\r\n\x0b_propertiesB\x08\n\x06_error".\n\rDocumentError\x12\x0c\n\x04\x63ode\x18\x01 \x01(\t\x12\x0f\n\x07message\x18\x02 \x01(\t"\xb8\x01\n\x12\x44ocumentProperties\x12\x12\n\x05title\x18\x01 \x01(\tH\x00\x88\x01\x01\x12\x13\n\x06\x61uthor\x18\x02 \x01(\tH\x01\x88\x01\x01\x12\x17\n\npage_count\x18\x03 \x01(\rH\x02\x88\x01\x01\x12\x15\n\x08language\x18\x04 \x01(\tH\x03\x88\x01\x01\x12\x10\n\x03ocr\x18\x05 \x01(\x08H\x04\x88\x01\x01\x42\x08\n\x06_titleB\t\n\x07_authorB\r\n\x0b_page_countB\x0b\n\t_languageB\x06\n\x04_ocr"s\n\x12\x43reateChunkRequest\x12\x11\n\tnamespace\x18\x01 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x02 \x01(\t\x12"\n\x06\x63hunks\x18\x03 \x03(\x0b\x32\x12.coordinator.Chunk\x12\x11\n\tworker_id\x18\x04 \x01(\t"\xd4\x01\n\x05\x43hunk\x12\x0c\n\x04page\x18\x01 \x01(\r\x12\x0f\n\x07\x63ontent\x18\x03 \x01(\t\x12\x15\n\x08page_end\x18\x04 \x01(\rH\x00\x88\x01\x01\x12\x14\n\x0csection_path\x18\x05 \x03(\t\x12.\n\x0c\x65lement_type\x18\x06 \x01(\x0e\x32\x18.coordinator.ElementType\x12\x30\n\x0e\x62ounding_boxes\x18\x07 \x03(\x0b\x32\x18.coordinator.BoundingBox\x12\x10\n\x08metadata\x18\x08 \x01(\tB\x0b\n\t_page_end"U\n\x0b\x42oundingBox\x12\x0c\n\x04page\x18\x01 \x01(\r\x12\x0c\n\x04left\x18\x02 \x01(\x02\x12\x0b\n\x03top\x18\x03 \x01(\x02\x12\r\n\x05right\x18\x04 \x01(\x02\x12\x0e\n\x06\x62ottom\x18\x05 \x01(\x02"v\n\x13StreamChunksRequest\x12(\n\x05\x62\x61tch\x18\x01 \x01(\x0b\x32\x17.coordinator.ChunkBatchH\x00\x12*\n\x06\x63ommit\x18\x02 \x01(\x0b\x32\x18.coordinator.ChunkCommitH\x00\x42\t\n\x07message"k\n\nChunkBatch\x12\x11\n\tnamespace\x18\x01 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x02 \x01(\t\x12"\n\x06\x63hunks\x18\x03 \x03(\x0b\x32\x12.coordinator.Chunk\x12\x11\n\tworker_id\x18\x04 \x01(\t"H\n\x0b\x43hunkCommit\x12\x11\n\tnamespace\x18\x01 \x01(\t\x12\x13\n\x0b\x64ocument_id\x18\x02 \x01(\t\x12\x11\n\tworker_id\x18\x03 \x01(\t"&\n\x14StreamChunksResponse\x12\x0e\n\x06\x63hunks\x18\x01 \x01(\r*"\n\x0cWorkerStatus\x12\x08\n\x04IDLE\x10\x00\x12\x08\n\x04\x42USY\x10\x01*H\n\x0e\x44ocumentStatus\x12\x0b\n\x07PENDING\x10\x00\x12\x0e\n\nPROCESSING\x10\x01\x12\r\n\tCOMPLETED\x10\x02\x12\n\n\x06\x46\x41ILED\x10\x03*]\n\x0b\x45lementType\x12\x08\n\x04TEXT\x10\x00\x12\x0b\n\x07HEADING\x10\x01\x12\x08\n\x04LIST\x10\x02\x12\t\n\x05TABLE\x10\x03\x12\x0b\n\x07\x43\x41PTION\x10\x04\x12\x08\n\x04\x43ODE\x10\x05\x12\x0b\n\x07\x46ORMULA\x10\x06\x32\xb4\x04\n\x0b\x43oordinator\x12L\n\tHeartbeat\x12\x1d.coordinator.HeartbeatRequest\x1a\x1e.coordinator.HeartbeatResponse"\x00\x12N\n\x0eRegisterWorker\x12".coordinator.RegisterWorkerRequest\x1a\x16.google.protobuf.Empty"\x00\x12L\n\tClaimTask\x12\x1d.coordinator.ClaimTaskRequest\x1a\x1e.coordinator.ClaimTaskResponse"\x00\x12\x46\n\nRenewLease\x12\x1e.coordinator.RenewLeaseRequest\x1a\x16.google.protobuf.Empty"\x00\x12N\n\x0eUpdateDocument\x12".coordinator.UpdateDocumentRequest\x1a\x16.google.protobuf.Empty"\x00\x12H\n\x0b\x43reateChunk\x12\x1f.coordinator.CreateChunkRequest\x1a\x16.google.protobuf.Empty"\x00\x12W\n\x0cStreamChunks\x12 .coordinator.StreamChunksRequest\x1a!.coordinator.StreamChunksResponse"\x00(\x01\x62\x06proto3'
 )
 
 _globals = globals()
@@ -30,18 +30,48 @@ _builder.BuildMessageAndEnumDescriptors(DESCRIPTOR, _globals)
 _builder.BuildTopDescriptorsAndMessages(DESCRIPTOR, "coordinator_pb2", _globals)
 if not _descriptor._USE_C_DESCRIPTORS:
     DESCRIPTOR._loaded_options = None
-    _globals["_DOCUMENTSTATUS"]._serialized_start = 403
-    _globals["_DOCUMENTSTATUS"]._serialized_end = 475
-    _globals["_HEARTBEATRESPONSE"]._serialized_start = 63
-    _globals["_HEARTBEATRESPONSE"]._serialized_end = 99
-    _globals["_REGISTERWORKERREQUEST"]._serialized_start = 101
-    _globals["_REGISTERWORKERREQUEST"]._serialized_end = 153
-    _globals["_UPDATEDOCUMENTREQUEST"]._serialized_start = 155
-    _globals["_UPDATEDOCUMENTREQUEST"]._serialized_end = 263
-    _globals["_CREATECHUNKREQUEST"]._serialized_start = 265
-    _globals["_CREATECHUNKREQUEST"]._serialized_end = 361
-    _globals["_CHUNK"]._serialized_start = 363
-    _globals["_CHUNK"]._serialized_end = 401
-    _globals["_COORDINATOR"]._serialized_start = 478
-    _globals["_COORDINATOR"]._serialized_end = 796
+    _globals["_WORKERSTATUS"]._serialized_start = 1938
+    _globals["_WORKERSTATUS"]._serialized_end = 1972
+    _globals["_DOCUMENTSTATUS"]._serialized_start = 1974
+    _globals["_DOCUMENTSTATUS"]._serialized_end = 2046
+    _globals["_ELEMENTTYPE"]._serialized_start = 2048
+    _globals["_ELEMENTTYPE"]._serialized_end = 2141
+    _globals["_HEARTBEATREQUEST"]._serialized_start = 64
+    _globals["_HEARTBEATREQUEST"]._serialized_end = 232
+    _globals["_WORKERTASK"]._serialized_start = 234
+    _globals["_WORKERTASK"]._serialized_end = 286
+    _globals["_HEARTBEATRESPONSE"]._serialized_start = 288
+    _globals["_HEARTBEATRESPONSE"]._serialized_end = 362
+    _globals["_REGISTERWORKERREQUEST"]._serialized_start = 364
+    _globals["_REGISTERWORKERREQUEST"]._serialized_end = 416
+    _globals["_CLAIMTASKREQUEST"]._serialized_start = 418
+    _globals["_CLAIMTASKREQUEST"]._serialized_end = 455
+    _globals["_CLAIMTASKRESPONSE"]._serialized_start = 457
+    _globals["_CLAIMTASKRESPONSE"]._serialized_end = 547
+    _globals["_TASK"]._serialized_start = 549
+    _globals["_TASK"]._serialized_end = 617
+    _globals["_RENEWLEASEREQUEST"]._serialized_start = 619
+    _globals["_RENEWLEASEREQUEST"]._serialized_end = 678
+    _globals["_UPDATEDOCUMENTREQUEST"]._serialized_start = 681
+    _globals["_UPDATEDOCUMENTREQUEST"]._serialized_end = 939
+    _globals["_DOCUMENTERROR"]._serialized_start = 941
+    _globals["_DOCUMENTERROR"]._serialized_end = 987
+    _globals["_DOCUMENTPROPERTIES"]._serialized_start = 990
+    _globals["_DOCUMENTPROPERTIES"]._serialized_end = 1174
+    _globals["_CREATECHUNKREQUEST"]._serialized_start = 1176
+    _globals["_CREATECHUNKREQUEST"]._serialized_end = 1291
+    _globals["_CHUNK"]._serialized_start = 1294
+    _globals["_CHUNK"]._serialized_end = 1506
+    _globals["_BOUNDINGBOX"]._serialized_start = 1508
+    _globals["_BOUNDINGBOX"]._serialized_end = 1593
+    _globals["_STREAMCHUNKSREQUEST"]._serialized_start = 1595
+    _globals["_STREAMCHUNKSREQUEST"]._serialized_end = 1713
+    _globals["_CHUNKBATCH"]._serialized_start = 1715
+    _globals["_CHUNKBATCH"]._serialized_end = 1822
+    _globals["_CHUNKCOMMIT"]._serialized_start = 1824
+    _globals["_CHUNKCOMMIT"]._serialized_end = 1896
+    _globals["_STREAMCHUNKSRESPONSE"]._serialized_start = 1898
+    _globals["_STREAMCHUNKSRESPONSE"]._serialized_end = 1936
+    _globals["_COORDINATOR"]._serialized_start = 2144
+    _globals["_COORDINATOR"]._serialized_end = 2708
 # @@protoc_insertion_point(module_scope)
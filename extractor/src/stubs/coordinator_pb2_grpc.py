@@ -46,7 +46,7 @@ class CoordinatorStub(object):
         """
         self.Heartbeat = channel.unary_unary(
             "/coordinator.Coordinator/Heartbeat",
-            request_serializer=google_dot_protobuf_dot_empty__pb2.Empty.SerializeToString,
+            request_serializer=coordinator__pb2.HeartbeatRequest.SerializeToString,
             response_deserializer=coordinator__pb2.HeartbeatResponse.FromString,
             _registered_method=True,
         )
@@ -56,6 +56,18 @@ class CoordinatorStub(object):
             response_deserializer=google_dot_protobuf_dot_empty__pb2.Empty.FromString,
             _registered_method=True,
         )
+        self.ClaimTask = channel.unary_unary(
+            "/coordinator.Coordinator/ClaimTask",
+            request_serializer=coordinator__pb2.ClaimTaskRequest.SerializeToString,
+            response_deserializer=coordinator__pb2.ClaimTaskResponse.FromString,
+            _registered_method=True,
+        )
+        self.RenewLease = channel.unary_unary(
+            "/coordinator.Coordinator/RenewLease",
+            request_serializer=coordinator__pb2.RenewLeaseRequest.SerializeToString,
+            response_deserializer=google_dot_protobuf_dot_empty__pb2.Empty.FromString,
+            _registered_method=True,
+        )
         self.UpdateDocument = channel.unary_unary(
             "/coordinator.Coordinator/UpdateDocument",
             request_serializer=coordinator__pb2.UpdateDocumentRequest.SerializeToString,
@@ -68,6 +80,12 @@ class CoordinatorStub(object):
             response_deserializer=google_dot_protobuf_dot_empty__pb2.Empty.FromString,
             _registered_method=True,
         )
+        self.StreamChunks = channel.stream_unary(
+            "/coordinator.Coordinator/StreamChunks",
+            request_serializer=coordinator__pb2.StreamChunksRequest.SerializeToString,
+            response_deserializer=coordinator__pb2.StreamChunksResponse.FromString,
+            _registered_method=True,
+        )
 
 
 class CoordinatorServicer(object):
@@ -80,7 +98,9 @@ class CoordinatorServicer(object):
     """
 
     def Heartbeat(self, request, context):
-        """Checks if the coordinator service is running."""
+        """Reports the status of a worker to the coordinator service.
+        Workers must send heartbeats regularly to stay registered.
+        """
         context.set_code(grpc.StatusCode.UNIMPLEMENTED)
         context.set_details("Method not implemented!")
         raise NotImplementedError("Method not implemented!")
@@ -91,6 +111,23 @@ class CoordinatorServicer(object):
         context.set_details("Method not implemented!")
         raise NotImplementedError("Method not implemented!")
 
+    def ClaimTask(self, request, context):
+        """Claims the next extraction task for a worker.
+        The task is leased to the worker until the lease expires. Workers must
+        renew the lease while processing, otherwise the task is requeued.
+        """
+        context.set_code(grpc.StatusCode.UNIMPLEMENTED)
+        context.set_details("Method not implemented!")
+        raise NotImplementedError("Method not implemented!")
+
+    def RenewLease(self, request, context):
+        """Extends the lease of a task claimed by the worker.
+        Fails if the lease expired and the task was requeued.
+        """
+        context.set_code(grpc.StatusCode.UNIMPLEMENTED)
+        context.set_details("Method not implemented!")
+        raise NotImplementedError("Method not implemented!")
+
     def UpdateDocument(self, request, context):
         """Updates the document record in the database."""
         context.set_code(grpc.StatusCode.UNIMPLEMENTED)
@@ -98,7 +135,18 @@ class CoordinatorServicer(object):
         raise NotImplementedError("Method not implemented!")
 
     def CreateChunk(self, request, context):
-        """Creates chunk records from the extracted document content."""
+        """Creates chunk records from the extracted document content.
+        The chunks replace the existing chunks of the document in order.
+        """
+        context.set_code(grpc.StatusCode.UNIMPLEMENTED)
+        context.set_details("Method not implemented!")
+        raise NotImplementedError("Method not implemented!")
+
+    def StreamChunks(self, request_iterator, context):
+        """Streams chunk records of a document in batches.
+        The stream must end with a commit to mark the document as completed.
+        Once committed, the chunks replace the existing chunks of the document.
+        """
         context.set_code(grpc.StatusCode.UNIMPLEMENTED)
         context.set_details("Method not implemented!")
         raise NotImplementedError("Method not implemented!")
@@ -108,7 +156,7 @@ def add_CoordinatorServicer_to_server(servicer, server):
     rpc_method_handlers = {
         "Heartbeat": grpc.unary_unary_rpc_method_handler(
             servicer.Heartbeat,
-            request_deserializer=google_dot_protobuf_dot_empty__pb2.Empty.FromString,
+            request_deserializer=coordinator__pb2.HeartbeatRequest.FromString,
             response_serializer=coordinator__pb2.HeartbeatResponse.SerializeToString,
         ),
         "RegisterWorker": grpc.unary_unary_rpc_method_handler(
@@ -116,6 +164,16 @@ def add_CoordinatorServicer_to_server(servicer, server):
             request_deserializer=coordinator__pb2.RegisterWorkerRequest.FromString,
             response_serializer=google_dot_protobuf_dot_empty__pb2.Empty.SerializeToString,
         ),
+        "ClaimTask": grpc.unary_unary_rpc_method_handler(
+            servicer.ClaimTask,
+            request_deserializer=coordinator__pb2.ClaimTaskRequest.FromString,
+            response_serializer=coordinator__pb2.ClaimTaskResponse.SerializeToString,
+        ),
+        "RenewLease": grpc.unary_unary_rpc_method_handler(
+            servicer.RenewLease,
+            request_deserializer=coordinator__pb2.RenewLeaseRequest.FromString,
+            response_serializer=google_dot_protobuf_dot_empty__pb2.Empty.SerializeToString,
+        ),
         "UpdateDocument": grpc.unary_unary_rpc_method_handler(
             servicer.UpdateDocument,
             request_deserializer=coordinator__pb2.UpdateDocumentRequest.FromString,
@@ -126,6 +184,11 @@ def add_CoordinatorServicer_to_server(servicer, server):
             request_deserializer=coordinator__pb2.CreateChunkRequest.FromString,
             response_serializer=google_dot_protobuf_dot_empty__pb2.Empty.SerializeToString,
         ),
+        "StreamChunks": grpc.stream_unary_rpc_method_handler(
+            servicer.StreamChunks,
+            request_deserializer=coordinator__pb2.StreamChunksRequest.FromString,
+            response_serializer=coordinator__pb2.StreamChunksResponse.SerializeToString,
+        ),
     }
     generic_handler = grpc.method_handlers_generic_handler(
         "coordinator.Coordinator", rpc_method_handlers
@@ -163,7 +226,7 @@ class Coordinator(object):
             request,
             target,
             "/coordinator.Coordinator/Heartbeat",
-            google_dot_protobuf_dot_empty__pb2.Empty.SerializeToString,
+            coordinator__pb2.HeartbeatRequest.SerializeToString,
             coordinator__pb2.HeartbeatResponse.FromString,
             options,
             channel_credentials,
@@ -206,6 +269,66 @@ class Coordinator(object):
             _registered_method=True,
         )
 
+    @staticmethod
+    def ClaimTask(
+        request,
+        target,
+        options=(),
+        channel_credentials=None,
+        call_credentials=None,
+        insecure=False,
+        compression=None,
+        wait_for_ready=None,
+        timeout=None,
+        metadata=None,
+    ):
+        return grpc.experimental.unary_unary(
+            request,
+            target,
+            "/coordinator.Coordinator/ClaimTask",
+            coordinator__pb2.ClaimTaskRequest.SerializeToString,
+            coordinator__pb2.ClaimTaskResponse.FromString,
+            options,
+            channel_credentials,
+            insecure,
+            call_credentials,
+            compression,
+            wait_for_ready,
+            timeout,
+            metadata,
+            _registered_method=True,
+        )
+
+    @staticmethod
+    def RenewLease(
+        request,
+        target,
+        options=(),
+        channel_credentials=None,
+        call_credentials=None,
+        insecure=False,
+        compression=None,
+        wait_for_ready=None,
+        timeout=None,
+        metadata=None,
+    ):
+        return grpc.experimental.unary_unary(
+            request,
+            target,
+            "/coordinator.Coordinator/RenewLease",
+            coordinator__pb2.RenewLeaseRequest.SerializeToString,
+            google_dot_protobuf_dot_empty__pb2.Empty.FromString,
+            options,
+            channel_credentials,
+            insecure,
+            call_credentials,
+            compression,
+            wait_for_ready,
+            timeout,
+            metadata,
+            _registered_method=True,
+        )
+
     @staticmethod
     def UpdateDocument(
         request,
@@ -265,3 +388,33 @@ class Coordinator(object):
             metadata,
             _registered_method=True,
         )
+
+    @staticmethod
+    def StreamChunks(
+        request_iterator,
+        target,
+        options=(),
+        channel_credentials=None,
+        call_credentials=None,
+        insecure=False,
+        compression=None,
+        wait_for_ready=None,
+        timeout=None,
+        metadata=None,
+    ):
+        return grpc.experimental.stream_unary(
+            request_iterator,
+            target,
+            "/coordinator.Coordinator/StreamChunks",
+            coordinator__pb2.StreamChunksRequest.SerializeToString,
+            coordinator__pb2.StreamChunksResponse.FromString,
+            options,
+            channel_credentials,
+            insecure,
+            call_credentials,
+            compression,
+            wait_for_ready,
+            timeout,
+            metadata,
+            _registered_method=True,
+        )
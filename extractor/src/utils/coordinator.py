@@ -1,37 +1,163 @@
 import grpc
-from google.protobuf.empty_pb2 import Empty
+from collections import namedtuple
 from ..stubs import coordinator_pb2 as protos
 from ..stubs.coordinator_pb2_grpc import CoordinatorStub
-from .types import HeartbeatResponse, Chunk
+from .types import HeartbeatResponse, Chunk, DocumentProperties, DocumentError
+from .types import ExtractionTask, Worker
+
+# Number of chunks to send per message when streaming chunks.
+# This keeps the messages below the gRPC message size limit.
+CHUNK_BATCH_SIZE = 64
+
+# Codes of the coordinator errors that end the current task. The lease is
+# lost, the worker may not work on the document, or the coordinator can't
+# take the results. The coordinator requeues the task once the lease expires.
+STOP_CODES = (
+    grpc.StatusCode.FAILED_PRECONDITION,
+    grpc.StatusCode.PERMISSION_DENIED,
+    grpc.StatusCode.UNAVAILABLE,
+)
+
+
+def stops_task(error: Exception) -> bool:
+    """Returns whether the coordinator error ends the current task."""
+    return isinstance(error, grpc.RpcError) and error.code() in STOP_CODES
+
+
+class TLSConfig:
+    """Paths to the PEM files to connect to the coordinator with TLS."""
+
+    def __init__(
+        self,
+        ca: str,
+        cert: str | None = None,
+        key: str | None = None,
+    ):
+        self.ca = ca
+        self.cert = cert
+        self.key = key
+
+    def credentials(self) -> grpc.ChannelCredentials:
+        def read(path: str | None) -> bytes | None:
+            if path is None:
+                return None
+
+            with open(path, "rb") as file:
+                return file.read()
+
+        return grpc.ssl_channel_credentials(
+            root_certificates=read(self.ca),
+            private_key=read(self.key),
+            certificate_chain=read(self.cert),
+        )
+
+
+class CallDetails(
+    namedtuple(
+        "CallDetails",
+        ("method", "timeout", "metadata", "credentials", "wait_for_ready"),
+    ),
+    grpc.ClientCallDetails,
+):
+    pass
+
+
+class TokenInterceptor(
+    grpc.UnaryUnaryClientInterceptor,
+    grpc.StreamUnaryClientInterceptor,
+):
+    """Attaches the worker token to every request to the coordinator."""
+
+    def __init__(self, token: str):
+        self.metadata = ("authorization", f"Bearer {token}")
+
+    def authorize(self, details: grpc.ClientCallDetails):
+        metadata = list(details.metadata or [])
+        metadata.append(self.metadata)
+        return CallDetails(
+            method=details.method,
+            timeout=details.timeout,
+            metadata=metadata,
+            credentials=details.credentials,
+            wait_for_ready=details.wait_for_ready,
+        )
+
+    def intercept_unary_unary(self, continuation, details, request):
+        return continuation(self.authorize(details), request)
+
+    def intercept_stream_unary(self, continuation, details, requests):
+        return continuation(self.authorize(details), requests)
 
 
 class Coordinator:
     connection: CoordinatorStub
 
-    def __init__(self, address: str):
-        channel = grpc.insecure_channel(address)
+    def __init__(
+        self,
+        address: str,
+        token: str,
+        tls: TLSConfig | None = None,
+    ):
+        if tls:
+            channel = grpc.secure_channel(address, tls.credentials())
+        else:
+            channel = grpc.insecure_channel(address)
+
+        channel = grpc.intercept_channel(channel, TokenInterceptor(token))
         self.connection = CoordinatorStub(channel)
 
-    def heartbeat(self) -> HeartbeatResponse:
-        response = self.connection.Heartbeat(Empty())
-        return HeartbeatResponse(version=response.version)
+    def heartbeat(self, worker: Worker) -> HeartbeatResponse:
+        response = self.connection.Heartbeat(worker.to_proto())
+        return HeartbeatResponse(
+            version=response.version,
+            registered=response.registered,
+            draining=response.draining,
+        )
 
     def register_worker(self, id: str, address: str):
         request = protos.RegisterWorkerRequest(id=id, address=address)
         self.connection.RegisterWorker(request=request)
 
-    def update_document(self, namespace: str, document_id: str, status: str):
+    def claim_task(self, worker_id: str) -> ExtractionTask | None:
+        request = protos.ClaimTaskRequest(worker_id=worker_id)
+        response = self.connection.ClaimTask(request=request)
+        if not response.HasField("task"):
+            return None
+
+        return ExtractionTask.from_proto(response.task)
+
+    def renew_lease(self, worker_id: str, document_id: str):
+        request = protos.RenewLeaseRequest(
+            worker_id=worker_id,
+            document_id=document_id,
+        )
+
+        self.connection.RenewLease(request=request)
+
+    def update_document(
+        self,
+        worker_id: str,
+        namespace: str,
+        document_id: str,
+        status: str,
+        properties: DocumentProperties | None = None,
+        error: DocumentError | None = None,
+    ):
         status = protos.DocumentStatus.Value(status.upper())
         request = protos.UpdateDocumentRequest(
             namespace=namespace,
             document_id=document_id,
             status=status,
+            properties=properties.to_proto() if properties else None,
+            error=error.to_proto() if error else None,
+            worker_id=worker_id,
         )
 
         self.connection.UpdateDocument(request=request)
 
     def create_chunk(
         self,
+        worker_id: str,
         namespace: str,
         document_id: str,
         chunks: list[Chunk],
@@ -41,6 +167,37 @@ class Coordinator:
             namespace=namespace,
             document_id=document_id,
             chunks=chunks,
+            worker_id=worker_id,
         )
 
         self.connection.CreateChunk(request=request)
+
+    def stream_chunks(
+        self,
+        worker_id: str,
+        namespace: str,
+        document_id: str,
+        chunks: list[Chunk],
+    ):
+        def requests():
+            for i in range(0, len(chunks), CHUNK_BATCH_SIZE):
+                batch = chunks[i : i + CHUNK_BATCH_SIZE]
+                yield protos.StreamChunksRequest(
+                    batch=protos.ChunkBatch(
+                        namespace=namespace,
+                        document_id=document_id,
+                        chunks=[chunk.to_proto() for chunk in batch],
+                        worker_id=worker_id,
+                    )
+                )
+
+            # The commit marks the document as completed.
+            commit = protos.ChunkCommit(
+                namespace=namespace,
+                document_id=document_id,
+                worker_id=worker_id,
+            )
+
+            yield protos.StreamChunksRequest(commit=commit)
+
+        self.connection.StreamChunks(requests())
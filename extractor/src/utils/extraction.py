@@ -1,33 +1,139 @@
-from docling.document_converter import DocumentConverter
+import ctypes
+import pypdfium2 as pdfium
+import pypdfium2.raw as pdfium_c
+from docling.datamodel.base_models import InputFormat
+from docling.datamodel.pipeline_options import PdfPipelineOptions
+from docling.document_converter import DocumentConverter, PdfFormatOption
 from docling_core.transforms.chunker.hybrid_chunker import HybridChunker
-from .types import Chunk
+from .types import BoundingBox, Chunk, DocumentProperties
+
+# Mapping of the docling item labels to the chunk element types.
+ELEMENT_TYPES = {
+    "title": "heading",
+    "section_header": "heading",
+    "list_item": "list",
+    "table": "table",
+    "caption": "caption",
+    "code": "code",
+    "formula": "formula",
+}
 
 
 class Extraction:
     path: str
     tokenizer: str
 
+    # Properties of the document available after the extraction.
+    properties: DocumentProperties
+
     def __init__(self, path: str, tokenizer: str = "BAAI/bge-small-en-v1.5"):
         self.path = path
         self.tokenizer = tokenizer
+        self.properties = DocumentProperties()
 
     def extract(self) -> list[Chunk]:
-        converter = DocumentConverter()
+        options = PdfPipelineOptions(do_ocr=True)
+        converter = DocumentConverter(
+            format_options={
+                InputFormat.PDF: PdfFormatOption(pipeline_options=options)
+            }
+        )
+
         chunker = HybridChunker(tokenizer=self.tokenizer)
 
         result = converter.convert(self.path)
         doc = result.document
 
+        title = next(
+            (item.text for item in doc.texts if item.label == "title"),
+            None,
+        )
+
+        # Docling doesn't keep the metadata of the PDF in the document, so
+        # the author and language are read from the PDF itself.
+        author, language = self.pdf_metadata()
+
+        self.properties = DocumentProperties(
+            title=title,
+            author=author,
+            page_count=len(doc.pages),
+            language=language,
+            ocr=options.do_ocr,
+        )
+
         chunks = []
         for chunk in list(chunker.chunk(doc)):
             meta = chunk.meta.export_json_dict()
-
-            page = 0
             doc_items = meta.get("doc_items", [])
-            if len(doc_items) > 0:
-                provs = doc_items[0].get("prov", [])
-                page = provs[0].get("page_no", 0) if len(provs) > 0 else 0
 
-            chunks.append(Chunk(page, chunk.text))
+            boxes = []
+            for item in doc_items:
+                for prov in item.get("prov", []):
+                    box = self.bounding_box(doc, prov)
+                    if box is not None:
+                        boxes.append(box)
+
+            pages = [box.page for box in boxes]
+            page = min(pages) if len(pages) > 0 else 0
+            page_end = max(pages) if len(pages) > 0 else None
+
+            # The element type of the chunk is taken from its first item.
+            labels = [item.get("label", "text") for item in doc_items]
+            element_type = "text"
+            if len(labels) > 0:
+                element_type = ELEMENT_TYPES.get(labels[0], "text")
+
+            chunks.append(
+                Chunk(
+                    page,
+                    chunk.text,
+                    page_end=page_end if page_end != page else None,
+                    section_path=meta.get("headings") or [],
+                    element_type=element_type,
+                    bounding_boxes=boxes,
+                    metadata={
+                        "labels": sorted(set(labels)),
+                        "captions": meta.get("captions") or [],
+                    },
+                )
+            )
 
         return chunks
+
+    def pdf_metadata(self) -> tuple[str | None, str | None]:
+        """Returns the author and the language declared in the PDF."""
+        pdf = pdfium.PdfDocument(self.path)
+        try:
+            metadata = pdf.get_metadata_dict(skip_empty=True)
+            author = metadata.get("Author")
+
+            # The language is declared in the catalog of the PDF, which is
+            # only exposed by recent versions of pdfium.
+            language = None
+            get_language = getattr(pdfium_c, "FPDFCatalog_GetLanguage", None)
+            if get_language is not None:
+                size = get_language(pdf.raw, None, 0)
+                if size > 2:
+                    buffer = ctypes.create_string_buffer(size)
+                    wchar = ctypes.POINTER(ctypes.c_ushort)
+                    get_language(pdf.raw, ctypes.cast(buffer, wchar), size)
+                    language = buffer.raw[: size - 2].decode("utf-16-le")
+
+            return author or None, language or None
+        finally:
+            pdf.close()
+
+    def bounding_box(self, doc, prov: dict) -> BoundingBox | None:
+        page = prov.get("page_no")
+        bbox = prov.get("bbox")
+        if page is None or bbox is None:
+            return None
+
+        # Docling may use the bottom-left corner of the page as the origin.
+        # We convert the box to be relative to the top-left corner.
+        top, bottom = bbox["t"], bbox["b"]
+        if bbox.get("coord_origin") == "BOTTOMLEFT":
+            height = doc.pages[page].size.height
+            top, bottom = height - top, height - bottom
+
+        return BoundingBox(page, bbox["l"], top, bbox["r"], bottom)
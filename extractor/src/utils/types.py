@@ -1,4 +1,5 @@
 import os
+import json
 import boto3
 from uuid import UUID
 from ..stubs import coordinator_pb2 as protos
@@ -8,26 +9,129 @@ TMP_PATH = "dl-tmp"
 
 class HeartbeatResponse:
     version: str
+    registered: bool
+    draining: bool
 
-    def __init__(self, version: str):
+    def __init__(self, version: str, registered: bool, draining: bool):
         self.version = version
+        self.registered = registered
+        self.draining = draining
+
+
+class BoundingBox:
+    page: int
+    left: float
+    top: float
+    right: float
+    bottom: float
+
+    def __init__(
+        self,
+        page: int,
+        left: float,
+        top: float,
+        right: float,
+        bottom: float,
+    ):
+        self.page = page
+        self.left = left
+        self.top = top
+        self.right = right
+        self.bottom = bottom
+
+    def to_proto(self) -> protos.BoundingBox:
+        return protos.BoundingBox(
+            page=self.page,
+            left=self.left,
+            top=self.top,
+            right=self.right,
+            bottom=self.bottom,
+        )
 
 
 class Chunk:
     page: int
     content: str
-
-    def __init__(self, page: int, content: str):
+    page_end: int | None
+    section_path: list[str]
+    element_type: str
+    bounding_boxes: list[BoundingBox]
+    metadata: dict
+
+    def __init__(
+        self,
+        page: int,
+        content: str,
+        page_end: int | None = None,
+        section_path: list[str] | None = None,
+        element_type: str = "text",
+        bounding_boxes: list[BoundingBox] | None = None,
+        metadata: dict | None = None,
+    ):
         self.page = page
         self.content = content
+        self.page_end = page_end
+        self.section_path = section_path or []
+        self.element_type = element_type
+        self.bounding_boxes = bounding_boxes or []
+        self.metadata = metadata or {}
 
     def to_proto(self) -> protos.Chunk:
+        element_type = protos.ElementType.Value(self.element_type.upper())
         return protos.Chunk(
             page=self.page,
             content=self.content,
+            page_end=self.page_end,
+            section_path=self.section_path,
+            element_type=element_type,
+            bounding_boxes=[box.to_proto() for box in self.bounding_boxes],
+            metadata=json.dumps(self.metadata),
+        )
+
+
+class DocumentProperties:
+    title: str | None
+    author: str | None
+    page_count: int | None
+    language: str | None
+    ocr: bool | None
+
+    def __init__(
+        self,
+        title: str | None = None,
+        author: str | None = None,
+        page_count: int | None = None,
+        language: str | None = None,
+        ocr: bool | None = None,
+    ):
+        self.title = title
+        self.author = author
+        self.page_count = page_count
+        self.language = language
+        self.ocr = ocr
+
+    def to_proto(self) -> protos.DocumentProperties:
+        return protos.DocumentProperties(
+            title=self.title,
+            author=self.author,
+            page_count=self.page_count,
+            language=self.language,
+            ocr=self.ocr,
         )
 
 
+class DocumentError:
+    code: str
+    message: str
+
+    def __init__(self, code: str, message: str):
+        self.code = code
+        self.message = message
+
+    def to_proto(self) -> protos.DocumentError:
+        return protos.DocumentError(code=self.code, message=self.message)
+
+
 class ExtractionTask:
     namespace: str
     document_key: str
@@ -38,6 +142,14 @@ class ExtractionTask:
         self.document_key = document_key
         self.document_id = document_id
 
+    @staticmethod
+    def from_proto(task: protos.Task) -> "ExtractionTask":
+        return ExtractionTask(
+            namespace=task.namespace,
+            document_key=task.document_key,
+            document_id=UUID(task.document_id),
+        )
+
     def download_document(self) -> str:
         os.makedirs(TMP_PATH, exist_ok=True)
         filename = self.document_key.split("/")[-1]
@@ -56,3 +168,50 @@ class ExtractionTask:
         # Remove the downloaded document.
         filename = self.document_key.split("/")[-1]
         os.remove(os.path.join(TMP_PATH, filename))
+
+
+class Worker:
+    """State of the worker reported to the coordinator with heartbeats."""
+
+    id: str
+    address: str
+    task: ExtractionTask | None
+    completed: int
+    failed: int
+    registered: bool
+    draining: bool
+
+    # Whether the lease of the current task couldn't be renewed.
+    lease_lost: bool
+
+    def __init__(self, id: str, address: str):
+        self.id = id
+        self.address = address
+        self.task = None
+        self.completed = 0
+        self.failed = 0
+        self.registered = False
+        self.draining = False
+        self.lease_lost = False
+
+    def to_proto(self) -> protos.HeartbeatRequest:
+        if self.task is None:
+            return protos.HeartbeatRequest(
+                worker_id=self.id,
+                status=protos.WorkerStatus.IDLE,
+                completed=self.completed,
+                failed=self.failed,
+            )
+
+        task = protos.WorkerTask(
+            namespace=self.task.namespace,
+            document_id=str(self.task.document_id),
+        )
+
+        return protos.HeartbeatRequest(
+            worker_id=self.id,
+            status=protos.WorkerStatus.BUSY,
+            task=task,
+            completed=self.completed,
+            failed=self.failed,
+        )
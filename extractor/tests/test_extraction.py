@@ -12,3 +12,6 @@ def test_extraction_extract():
     assert len(results) == 1
     assert results[0].page == 1
     assert "PQ" in results[0].content
+    assert extraction.properties.page_count == 1
+    assert extraction.properties.author is None
+    assert extraction.properties.ocr is True
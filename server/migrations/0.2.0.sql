@@ -0,0 +1,87 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- The index configuration became tagged by the index type.
+-- Existing namespaces are all indexed with HNSW.
+UPDATE namespaces
+SET config = jsonb_set(config, '{index,type}', '"Hnsw"')
+WHERE NOT config -> 'index' ? 'type';
+
+CREATE TYPE chunk_element
+AS ENUM ('text', 'heading', 'list', 'table', 'caption', 'code', 'formula');
+
+-- Workers are persisted so that coordinator replicas share the registry.
+-- Workers that stop sending heartbeats are expired by last-seen time.
+CREATE TYPE worker_status AS ENUM ('idle', 'busy');
+
+CREATE TABLE IF NOT EXISTS workers (
+    id UUID PRIMARY KEY,
+    address TEXT NOT NULL,
+    status worker_status NOT NULL DEFAULT 'idle',
+    namespace TEXT,
+    document_id UUID,
+    draining BOOLEAN NOT NULL DEFAULT false,
+    completed BIGINT NOT NULL DEFAULT 0,
+    failed BIGINT NOT NULL DEFAULT 0,
+    registered_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS workers_last_seen_at_idx ON workers (last_seen_at);
+
+-- Tasks claimed by workers are leased until the document is processed.
+-- Tasks of expired leases are requeued to be claimed by another worker.
+CREATE TABLE IF NOT EXISTS leases (
+    document_id UUID PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    document_key TEXT NOT NULL,
+    priority INTEGER NOT NULL DEFAULT 0,
+    worker_id UUID NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    claimed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS leases_expires_at_idx ON leases (expires_at);
+
+-- Extraction tasks of the queue backed by the database.
+-- Delivered tasks are hidden until visible_at instead of being removed and
+-- dead-lettered tasks are kept until they are replayed. The RabbitMQ
+-- backend keeps its dead-lettered tasks here as well.
+CREATE TABLE IF NOT EXISTS tasks (
+    id BIGSERIAL PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    document_key TEXT NOT NULL,
+    document_id UUID NOT NULL,
+    priority INTEGER NOT NULL DEFAULT 0,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    dead BOOLEAN NOT NULL DEFAULT false,
+    error_code TEXT,
+    error_message TEXT,
+    visible_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    dead_lettered_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS tasks_namespace_priority_idx
+    ON tasks (namespace, priority DESC, visible_at) WHERE NOT dead;
+
+CREATE INDEX IF NOT EXISTS tasks_dead_document_id_idx
+    ON tasks (document_id) WHERE dead;
+
+-- Tasks of uploaded documents are written to the outbox in the same
+-- transaction as the document and removed once published to the queue.
+-- Recovered tasks are written with their attempts to be retried, or with
+-- the error to be dead-lettered. A relay claims a task until claimed_until
+-- while it's sent, and the task is sent again once the claim expires.
+CREATE TABLE IF NOT EXISTS outbox (
+    document_id UUID PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    document_key TEXT NOT NULL,
+    priority INTEGER NOT NULL DEFAULT 0,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error_code TEXT,
+    error_message TEXT,
+    claimed_until TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS outbox_created_at_idx ON outbox (created_at);
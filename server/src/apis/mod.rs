@@ -1,7 +1,8 @@
 mod queue;
 mod storage;
 
-pub use queue::QueueAPI;
+pub use queue::{QueueAPI, QueueBackend, RetryPolicy};
+pub use queue::{QueuePostgres, QueueRabbitMQ};
 pub use storage::StorageAPI;
 
 use crate::services::interface::ErrorResponse;
@@ -0,0 +1,175 @@
+mod postgres;
+mod rabbitmq;
+
+pub use postgres::QueuePostgres;
+pub use rabbitmq::QueueRabbitMQ;
+
+use super::*;
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Backend storing the extraction tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueBackend {
+    RabbitMQ,
+    /// Tasks table in the database which removes the need for a broker.
+    Postgres,
+}
+
+impl FromStr for QueueBackend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rabbitmq" => Ok(QueueBackend::RabbitMQ),
+            "postgres" => Ok(QueueBackend::Postgres),
+            _ => Err(format!("Unsupported queue backend: {s}")),
+        }
+    }
+}
+
+/// Policy to retry the tasks that were not processed.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Number of processing attempts before a task is dead-lettered.
+    pub max_attempts: u32,
+    /// Seconds to wait before the first retry, doubled for each retry.
+    pub delay: u64,
+}
+
+impl RetryPolicy {
+    /// Returns the seconds to wait before retrying after the attempt.
+    pub fn backoff(&self, attempt: u32) -> u64 {
+        let exponent = attempt.saturating_sub(1).min(16);
+        self.delay.saturating_mul(1 << exponent)
+    }
+}
+
+#[async_trait]
+pub trait QueueAPI: Debug + Send + Sync {
+    /// Returns whether the queue is reachable.
+    async fn is_connected(&self) -> bool;
+
+    /// Reconnects to the queue if the connection was lost.
+    ///
+    /// Returns true if the queue was reconnected.
+    async fn recover(&self) -> Result<bool, ErrorResponse>;
+
+    /// Publishes an extraction task to the queue.
+    async fn publish(&self, task: &ExtractionTask)
+        -> Result<(), ErrorResponse>;
+
+    /// Publishes a task to be retried after a backoff delay.
+    ///
+    /// The delay doubles with each attempt the task has already made.
+    async fn retry(
+        &self,
+        task: &ExtractionTask,
+        attempts: u32,
+    ) -> Result<(), ErrorResponse>;
+
+    /// Moves a task that won't be retried to the dead-letter queue.
+    async fn dead_letter(
+        &self,
+        task: &ExtractionTask,
+        attempts: u32,
+        code: &str,
+        message: &str,
+    ) -> Result<(), ErrorResponse>;
+
+    /// Returns the namespaces with tasks ready to be delivered.
+    ///
+    /// Backends that can't list them cheaply return None, which makes every
+    /// namespace a candidate for the next get.
+    async fn ready_namespaces(
+        &self,
+    ) -> Result<Option<Vec<String>>, ErrorResponse> {
+        Ok(None)
+    }
+
+    /// Gets the next extraction task from the queue, if any.
+    ///
+    /// The namespaces are tried in the given order and the tasks of a
+    /// namespace are delivered by priority. The task stays in the queue
+    /// until the delivery is acknowledged.
+    async fn get(
+        &self,
+        namespaces: &[String],
+    ) -> Result<Option<Delivery>, ErrorResponse>;
+
+    /// Waits until a task may be available or the timeout elapses.
+    ///
+    /// Backends that can't notify about new tasks return immediately.
+    async fn wait(&self, _timeout: Duration) {}
+
+    /// Returns the dead-lettered tasks in the order they were dead-lettered.
+    async fn dead_letters(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, ErrorResponse>;
+
+    /// Returns the dead-lettered task of the document, if any.
+    async fn dead_letter_of(
+        &self,
+        id: &DocumentID,
+    ) -> Result<Option<DeadLetter>, ErrorResponse>;
+
+    /// Publishes the dead-lettered task of the document back to the queue.
+    ///
+    /// The task starts over with no attempts and is removed from the
+    /// dead-letter queue.
+    async fn replay(
+        &self,
+        id: &DocumentID,
+    ) -> Result<Option<DeadLetter>, ErrorResponse>;
+
+    /// Removes all tasks from the queue, including the dead-lettered ones.
+    #[cfg(test)]
+    async fn purge(&self) -> Result<(), ErrorResponse>;
+}
+
+/// Extraction task delivered from the queue.
+#[derive(Debug)]
+pub struct Delivery {
+    pub task: ExtractionTask,
+    acker: Box<dyn DeliveryAcker>,
+}
+
+impl Delivery {
+    /// Acknowledges the task to remove it from the queue.
+    pub async fn ack(self) -> Result<(), ErrorResponse> {
+        self.acker.ack().await
+    }
+
+    /// Returns the task to the queue to be delivered again.
+    pub async fn requeue(self) -> Result<(), ErrorResponse> {
+        self.acker.requeue().await
+    }
+}
+
+/// Settles a delivery with the backend it came from.
+#[async_trait]
+trait DeliveryAcker: Debug + Send + Sync {
+    async fn ack(self: Box<Self>) -> Result<(), ErrorResponse>;
+    async fn requeue(self: Box<Self>) -> Result<(), ErrorResponse>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            delay: 30,
+        };
+
+        assert_eq!(policy.backoff(1), 30);
+        assert_eq!(policy.backoff(2), 60);
+        assert_eq!(policy.backoff(3), 120);
+        assert_eq!(policy.backoff(u32::MAX), 30 << 16);
+    }
+}
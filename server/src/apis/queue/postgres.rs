@@ -0,0 +1,612 @@
+use super::*;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgListener;
+use sqlx::{FromRow, PgPool};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::time::{sleep, timeout};
+
+// Channel notified when a task becomes available.
+const CHANNEL: &str = "tasks";
+
+// Seconds a delivered task is hidden from other gets until it's settled.
+// The task is delivered again if the coordinator fails in between.
+const VISIBILITY_TIMEOUT: u64 = 60;
+
+// Time to wait for the database before the queue is reported unreachable.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Task queue stored in the tasks table of the database.
+///
+/// Tasks are delivered with `FOR UPDATE SKIP LOCKED` so replicas never get
+/// the same task, and hidden until the visibility timeout expires instead
+/// of being removed. Retries are tasks that become visible after the
+/// backoff delay and dead-lettered tasks are kept in the table.
+#[derive(Debug)]
+pub struct QueuePostgres {
+    pool: PgPool,
+    policy: RetryPolicy,
+    // Woken by the listener when a task is published.
+    notify: Arc<Notify>,
+}
+
+impl QueuePostgres {
+    /// Creates a new instance of the task queue.
+    pub async fn new(pool: PgPool, policy: RetryPolicy) -> Self {
+        let mut listener = PgListener::connect_with(&pool)
+            .await
+            .expect("Failed to connect the queue listener");
+
+        listener
+            .listen(CHANNEL)
+            .await
+            .expect("Failed to listen for tasks");
+
+        let notify = Arc::new(Notify::new());
+        tokio::spawn(listen(listener, notify.clone()));
+
+        QueuePostgres {
+            pool,
+            policy,
+            notify,
+        }
+    }
+
+    /// Inserts a task that becomes visible after the delay.
+    async fn insert(
+        &self,
+        task: &ExtractionTask,
+        attempts: u32,
+        delay: u64,
+    ) -> Result<(), ErrorResponse> {
+        sqlx::query(
+            "INSERT INTO tasks (
+                namespace, document_key, document_id, priority, attempts,
+                visible_at
+            )
+            VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(secs => $6));",
+        )
+        .bind(&task.namespace)
+        .bind(&task.document_key)
+        .bind(task.document_id)
+        .bind(task.priority)
+        .bind(attempts as i32)
+        .bind(delay as f64)
+        .execute(&self.pool)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to queue up the task: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to queue up the task."),
+                solution: None,
+            }
+        })?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QueueAPI for QueuePostgres {
+    async fn is_connected(&self) -> bool {
+        // The pool is only closed on shutdown, so the database is queried
+        // to know whether it's reachable.
+        let query = sqlx::query("SELECT 1;").execute(&self.pool);
+        matches!(timeout(PING_TIMEOUT, query).await, Ok(Ok(_)))
+    }
+
+    async fn recover(&self) -> Result<bool, ErrorResponse> {
+        // The pool reconnects on its own.
+        Ok(false)
+    }
+
+    async fn publish(
+        &self,
+        task: &ExtractionTask,
+    ) -> Result<(), ErrorResponse> {
+        self.insert(task, 0, 0).await?;
+        notify(&self.pool).await;
+        Ok(())
+    }
+
+    async fn retry(
+        &self,
+        task: &ExtractionTask,
+        attempts: u32,
+    ) -> Result<(), ErrorResponse> {
+        let delay = self.policy.backoff(attempts);
+        self.insert(task, attempts, delay).await
+    }
+
+    async fn dead_letter(
+        &self,
+        task: &ExtractionTask,
+        attempts: u32,
+        code: &str,
+        message: &str,
+    ) -> Result<(), ErrorResponse> {
+        insert_dead_letter(&self.pool, task, attempts, code, message).await
+    }
+
+    async fn ready_namespaces(
+        &self,
+    ) -> Result<Option<Vec<String>>, ErrorResponse> {
+        let namespaces = sqlx::query_scalar(
+            "SELECT DISTINCT namespace FROM tasks
+            WHERE NOT dead AND visible_at <= NOW();",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to list the ready namespaces: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to get a task."),
+                solution: None,
+            }
+        })?;
+
+        Ok(Some(namespaces))
+    }
+
+    async fn get(
+        &self,
+        namespaces: &[String],
+    ) -> Result<Option<Delivery>, ErrorResponse> {
+        let error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to get a task from the queue: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to get a task."),
+                solution: None,
+            }
+        };
+
+        // The first namespace in the order with a visible task is looked up
+        // without locking, then its next task is claimed. A namespace whose
+        // visible tasks are all being claimed by other replicas is skipped.
+        let mut namespaces = namespaces.to_vec();
+        loop {
+            let namespace: Option<String> = sqlx::query_scalar(
+                "SELECT ordered.namespace
+                FROM unnest($1::text[]) WITH ORDINALITY
+                    AS ordered (namespace, position)
+                WHERE EXISTS (
+                    SELECT 1 FROM tasks
+                    WHERE NOT dead
+                    AND namespace = ordered.namespace
+                    AND visible_at <= NOW()
+                )
+                ORDER BY ordered.position
+                LIMIT 1;",
+            )
+            .bind(&namespaces)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(error)?;
+
+            let namespace = match namespace {
+                Some(namespace) => namespace,
+                None => return Ok(None),
+            };
+
+            // Only the task returned by the subquery is locked.
+            let row: Option<TaskRow> = sqlx::query_as(
+                "UPDATE tasks
+                SET visible_at = NOW() + make_interval(secs => $1)
+                WHERE id = (
+                    SELECT id FROM tasks
+                    WHERE NOT dead
+                    AND namespace = $2
+                    AND visible_at <= NOW()
+                    ORDER BY priority DESC, visible_at
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING *;",
+            )
+            .bind(VISIBILITY_TIMEOUT as f64)
+            .bind(&namespace)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(error)?;
+
+            if let Some(row) = row {
+                return Ok(Some(Delivery {
+                    task: row.task,
+                    acker: Box::new(TaskAcker {
+                        pool: self.pool.clone(),
+                        id: row.id,
+                    }),
+                }));
+            }
+
+            namespaces.retain(|name| *name != namespace);
+        }
+    }
+
+    async fn wait(&self, duration: Duration) {
+        let _ = timeout(duration, self.notify.notified()).await;
+    }
+
+    async fn dead_letters(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, ErrorResponse> {
+        select_dead_letters(&self.pool, limit).await
+    }
+
+    async fn dead_letter_of(
+        &self,
+        id: &DocumentID,
+    ) -> Result<Option<DeadLetter>, ErrorResponse> {
+        select_dead_letter(&self.pool, id).await
+    }
+
+    async fn replay(
+        &self,
+        id: &DocumentID,
+    ) -> Result<Option<DeadLetter>, ErrorResponse> {
+        // The dead letter is returned as it was before the replay.
+        let row: Option<TaskRow> = sqlx::query_as(
+            "WITH letter AS (
+                SELECT * FROM tasks
+                WHERE dead
+                AND document_id = $1
+                ORDER BY dead_lettered_at, id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE tasks
+            SET dead = false,
+            attempts = 0,
+            error_code = NULL,
+            error_message = NULL,
+            dead_lettered_at = NULL,
+            visible_at = NOW()
+            FROM letter
+            WHERE tasks.id = letter.id
+            RETURNING letter.*;",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to replay the dead letter: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to replay the dead letter."),
+                solution: None,
+            }
+        })?;
+
+        if row.is_some() {
+            notify(&self.pool).await;
+        }
+
+        Ok(row.map(DeadLetter::from))
+    }
+
+    #[cfg(test)]
+    async fn purge(&self) -> Result<(), ErrorResponse> {
+        sqlx::query("DELETE FROM tasks;")
+            .execute(&self.pool)
+            .await
+            .map_err(|_e| {
+                #[cfg(test)]
+                eprintln!("Failed to purge the queue: {_e:?}");
+                ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: String::from("Failed to purge the queue."),
+                    solution: None,
+                }
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Keeps a task that won't be retried in the tasks table.
+///
+/// The dead letters of both backends are kept in the table so they can be
+/// looked up by document without going through a queue.
+pub(super) async fn insert_dead_letter(
+    pool: &PgPool,
+    task: &ExtractionTask,
+    attempts: u32,
+    code: &str,
+    message: &str,
+) -> Result<(), ErrorResponse> {
+    sqlx::query(
+        "INSERT INTO tasks (
+            namespace, document_key, document_id, priority, attempts,
+            dead, error_code, error_message, dead_lettered_at
+        )
+        VALUES ($1, $2, $3, $4, $5, true, $6, $7, NOW());",
+    )
+    .bind(&task.namespace)
+    .bind(&task.document_key)
+    .bind(task.document_id)
+    .bind(task.priority)
+    .bind(attempts as i32)
+    .bind(code)
+    .bind(message)
+    .execute(pool)
+    .await
+    .map_err(|_e| {
+        #[cfg(test)]
+        eprintln!("Failed to dead-letter the task: {_e:?}");
+        ErrorResponse {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: String::from("Failed to dead-letter the task."),
+            solution: None,
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Returns the dead letters in the order they were dead-lettered.
+pub(super) async fn select_dead_letters(
+    pool: &PgPool,
+    limit: usize,
+) -> Result<Vec<DeadLetter>, ErrorResponse> {
+    let rows: Vec<TaskRow> = sqlx::query_as(
+        "SELECT * FROM tasks
+        WHERE dead
+        ORDER BY dead_lettered_at, id
+        LIMIT $1;",
+    )
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await
+    .map_err(|_e| {
+        #[cfg(test)]
+        eprintln!("Failed to retrieve the dead letters: {_e:?}");
+        ErrorResponse {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: String::from("Failed to retrieve the dead letters."),
+            solution: None,
+        }
+    })?;
+
+    Ok(rows.into_iter().map(DeadLetter::from).collect())
+}
+
+/// Returns the oldest dead letter of the document, if any.
+pub(super) async fn select_dead_letter(
+    pool: &PgPool,
+    id: &DocumentID,
+) -> Result<Option<DeadLetter>, ErrorResponse> {
+    let row: Option<TaskRow> = sqlx::query_as(
+        "SELECT * FROM tasks
+        WHERE dead
+        AND document_id = $1
+        ORDER BY dead_lettered_at, id
+        LIMIT 1;",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|_e| {
+        #[cfg(test)]
+        eprintln!("Failed to retrieve the dead letter: {_e:?}");
+        ErrorResponse {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: String::from("Failed to retrieve the dead letter."),
+            solution: None,
+        }
+    })?;
+
+    Ok(row.map(DeadLetter::from))
+}
+
+#[derive(Debug, FromRow)]
+pub(super) struct TaskRow {
+    pub(super) id: i64,
+    #[sqlx(flatten)]
+    task: ExtractionTask,
+    attempts: i32,
+    error_code: Option<String>,
+    error_message: Option<String>,
+    dead_lettered_at: Option<DateTime<Utc>>,
+}
+
+impl From<TaskRow> for DeadLetter {
+    fn from(value: TaskRow) -> Self {
+        let error = match (value.error_code, value.dead_lettered_at) {
+            (Some(code), Some(failed_at)) => Some(DocumentError {
+                code,
+                message: value.error_message.unwrap_or_default(),
+                failed_at,
+            }),
+            _ => None,
+        };
+
+        DeadLetter {
+            task: value.task,
+            attempts: value.attempts as u32,
+            error,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TaskAcker {
+    pool: PgPool,
+    id: i64,
+}
+
+#[async_trait]
+impl DeliveryAcker for TaskAcker {
+    async fn ack(self: Box<Self>) -> Result<(), ErrorResponse> {
+        sqlx::query("DELETE FROM tasks WHERE id = $1;")
+            .bind(self.id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_e| {
+                #[cfg(test)]
+                eprintln!("Failed to acknowledge the task: {_e:?}");
+                ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: String::from("Failed to acknowledge the task."),
+                    solution: None,
+                }
+            })?;
+
+        Ok(())
+    }
+
+    async fn requeue(self: Box<Self>) -> Result<(), ErrorResponse> {
+        sqlx::query("UPDATE tasks SET visible_at = NOW() WHERE id = $1;")
+            .bind(self.id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_e| {
+                #[cfg(test)]
+                eprintln!("Failed to requeue the task: {_e:?}");
+                ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: String::from("Failed to requeue the task."),
+                    solution: None,
+                }
+            })?;
+
+        notify(&self.pool).await;
+        Ok(())
+    }
+}
+
+/// Notifies the listeners of every replica that a task is available.
+///
+/// A missed notification only delays the task until the next get.
+async fn notify(pool: &PgPool) {
+    let result = sqlx::query("SELECT pg_notify($1, '');")
+        .bind(CHANNEL)
+        .execute(pool)
+        .await;
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to notify about a task: {e}");
+    }
+}
+
+/// Wakes the waiting claims whenever a task is published.
+async fn listen(mut listener: PgListener, notify: Arc<Notify>) {
+    loop {
+        match listener.recv().await {
+            Ok(_) => notify.notify_waiters(),
+            Err(e) => {
+                // The listener reconnects on the next receive.
+                tracing::error!("Failed to receive task notifications: {e}");
+                sleep(Duration::from_secs(5)).await;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::Configuration;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_postgres_queue() {
+        let queue = setup().await;
+        let task = ExtractionTask {
+            namespace: "test_ns".to_string(),
+            document_key: "test_ns/document.pdf".to_string(),
+            document_id: Uuid::new_v4(),
+            priority: 0,
+        };
+
+        assert!(queue.is_connected().await);
+        let namespaces = vec![task.namespace.clone()];
+        queue.publish(&task).await.unwrap();
+        let ready = queue.ready_namespaces().await.unwrap();
+        assert_eq!(ready, Some(namespaces.clone()));
+
+        // A delivered task is hidden until it's settled.
+        let delivery = queue.get(&namespaces).await.unwrap().unwrap();
+        assert_eq!(delivery.task.document_id, task.document_id);
+        assert!(queue.get(&namespaces).await.unwrap().is_none());
+
+        delivery.requeue().await.unwrap();
+        let delivery = queue.get(&namespaces).await.unwrap().unwrap();
+        delivery.ack().await.unwrap();
+        assert!(queue.get(&namespaces).await.unwrap().is_none());
+
+        // Retried tasks are hidden for the backoff delay.
+        queue.retry(&task, 1).await.unwrap();
+        assert!(queue.get(&namespaces).await.unwrap().is_none());
+        let ready = queue.ready_namespaces().await.unwrap();
+        assert_eq!(ready, Some(vec![]));
+
+        let (code, message) = ("extraction_failed", "The PDF is encrypted.");
+        queue.dead_letter(&task, 3, code, message).await.unwrap();
+        let letters = queue.dead_letters(10).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].attempts, 3);
+        assert_eq!(letters[0].error.as_ref().unwrap().code, code);
+
+        let id = task.document_id;
+        let letter = queue.replay(&id).await.unwrap().unwrap();
+        assert_eq!(letter.task.document_id, id);
+        assert!(queue.dead_letter_of(&id).await.unwrap().is_none());
+
+        let delivery = queue.get(&namespaces).await.unwrap().unwrap();
+        assert_eq!(delivery.task.document_id, id);
+    }
+
+    #[tokio::test]
+    async fn test_postgres_queue_order() {
+        let queue = setup().await;
+        let task = |namespace: &str, priority: i32| ExtractionTask {
+            namespace: namespace.to_string(),
+            document_key: format!("{namespace}/document.pdf"),
+            document_id: Uuid::new_v4(),
+            priority,
+        };
+
+        let low = task("busy_ns", 0);
+        let high = task("busy_ns", MAX_PRIORITY);
+        let other = task("idle_ns", 0);
+        for task in [&low, &high, &other] {
+            queue.publish(task).await.unwrap();
+        }
+
+        // The namespace order comes first, then the priority.
+        let namespaces = vec!["idle_ns".to_string(), "busy_ns".to_string()];
+        for expected in [&other, &high, &low] {
+            let delivery = queue.get(&namespaces).await.unwrap().unwrap();
+            assert_eq!(delivery.task.document_id, expected.document_id);
+            delivery.ack().await.unwrap();
+        }
+    }
+
+    async fn setup() -> QueuePostgres {
+        dotenv().ok();
+        let config = Configuration::default();
+        let pool = PgPoolOptions::new()
+            .connect(config.database_url.as_str())
+            .await
+            .unwrap();
+
+        let policy = RetryPolicy {
+            max_attempts: config.max_attempts,
+            delay: config.retry_delay,
+        };
+
+        let queue = QueuePostgres::new(pool, policy).await;
+        queue.purge().await.unwrap();
+        queue
+    }
+}
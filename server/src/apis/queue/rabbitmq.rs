@@ -0,0 +1,854 @@
+use super::postgres::{insert_dead_letter, TaskRow};
+use super::postgres::{select_dead_letter, select_dead_letters};
+use super::*;
+use axum::http::StatusCode;
+use chrono::Utc;
+use lapin::acker::Acker;
+use lapin::options::{BasicAckOptions, BasicGetOptions, BasicNackOptions};
+use lapin::options::{BasicPublishOptions, QueueDeclareOptions};
+use lapin::options::{ConfirmSelectOptions, ExchangeDeclareOptions};
+use lapin::options::{QueueBindOptions, QueueDeleteOptions};
+use lapin::publisher_confirm::Confirmation;
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{BasicProperties, Channel, Connection, ConnectionProperties};
+use lapin::{ExchangeKind, Result as LapinResult};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+// Delivery mode of messages persisted to disk by the broker.
+const PERSISTENT: u8 = 2;
+
+// Header carrying the processing attempts of a task.
+const ATTEMPTS_HEADER: &str = "x-attempts";
+
+// Seconds a dead letter is hidden from other replays while it's published.
+const REPLAY_TIMEOUT: u64 = 60;
+
+// Version of the queues and exchanges, bumped when their arguments change.
+const TOPOLOGY_VERSION: u32 = 1;
+
+// Time after which the number of tasks of a namespace queue is checked
+// again, so the tasks published by other replicas are seen.
+const BACKLOG_TTL: Duration = Duration::from_secs(1);
+
+// Time after which an empty namespace queue is checked again. Every check
+// declares the queue, so idle namespaces are checked less often. The tasks
+// published by this replica are counted right away.
+const EMPTY_BACKLOG_TTL: Duration = Duration::from_secs(10);
+
+/// Task queue with delayed retries and a dead-letter queue.
+///
+/// The topology is derived from the queue name and the topology version,
+/// written `{name}` below as `tasks.v1` for example:
+/// - `{name}.namespace.{namespace}` holds the tasks of a namespace ordered
+///   by priority. It's declared the first time the namespace is used and
+///   only polled while it holds tasks.
+/// - `{name}.retry.{attempt}` holds the tasks waiting to be retried after
+///   the attempt and sends them back to the queue of their namespace when
+///   their delay expires.
+/// - `{name}.dlx` is the dead-letter exchange of the queues which routes
+///   the messages rejected as invalid to `{name}.dead`.
+///
+/// Tasks that won't be retried are kept in the tasks table of the database
+/// instead of a queue, so they can be looked up and replayed by document.
+///
+/// The queues are durable and the tasks are persisted so they survive a
+/// broker restart. The arguments of a queue can't be changed once declared,
+/// so changing them requires a new topology version which leaves the queues
+/// of the previous version to be drained. The retry delays are set on the
+/// messages, so the retry policy can change without changing the queues.
+#[derive(Debug)]
+pub struct QueueRabbitMQ {
+    name: String,
+    url: String,
+    policy: RetryPolicy,
+    link: RwLock<Link>,
+    // Database keeping the dead-lettered tasks.
+    pool: PgPool,
+}
+
+/// Connection to the broker with the channel used for the tasks.
+#[derive(Debug)]
+struct Link {
+    connection: Arc<Connection>,
+    channel: Channel,
+    // Namespace queues declared since the connection was opened.
+    declared: Arc<RwLock<HashMap<String, Backlog>>>,
+}
+
+/// Number of tasks in a namespace queue when it was last checked.
+#[derive(Debug, Clone, Copy)]
+struct Backlog {
+    tasks: u32,
+    checked_at: Instant,
+}
+
+impl Backlog {
+    /// Returns whether the number of tasks should be checked again.
+    fn is_stale(&self) -> bool {
+        let ttl = match self.tasks {
+            0 => EMPTY_BACKLOG_TTL,
+            _ => BACKLOG_TTL,
+        };
+
+        self.checked_at.elapsed() >= ttl
+    }
+}
+
+impl QueueRabbitMQ {
+    /// Creates a new instance of the task queue.
+    pub async fn new(
+        name: impl AsRef<str>,
+        url: impl AsRef<str>,
+        pool: PgPool,
+        policy: RetryPolicy,
+    ) -> Self {
+        let legacy = name.as_ref();
+        let name = format!("{legacy}.v{TOPOLOGY_VERSION}");
+        let url = url.as_ref();
+        let link = connect(url, &name, &policy)
+            .await
+            .expect("Failed to connect to the queue");
+
+        let queue = QueueRabbitMQ {
+            name,
+            url: url.to_string(),
+            policy,
+            link: RwLock::new(link),
+            pool,
+        };
+
+        // Tasks published before the queues were split by namespace are
+        // moved to the queues of their namespace.
+        match queue.drain(legacy).await {
+            Ok(0) => {},
+            Ok(count) => tracing::info!("Moved {count} task(s) from {legacy}"),
+            Err(e) => {
+                tracing::error!("Failed to drain {legacy}: {}", e.message)
+            },
+        }
+
+        queue
+    }
+
+    /// Moves the tasks of a queue from an older version to the queues of
+    /// their namespace and deletes the queue once it's empty.
+    ///
+    /// Returns the number of tasks moved.
+    async fn drain(&self, queue: &str) -> Result<usize, ErrorResponse> {
+        let error = |_e: lapin::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to drain the queue: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to drain the queue."),
+                solution: None,
+            }
+        };
+
+        // The broker closes the channel when a missing queue is declared
+        // passively, so the queue is drained on a channel of its own.
+        let connection = self.link.read().unwrap().connection.clone();
+        let channel = connection.create_channel().await.map_err(error)?;
+
+        let options = QueueDeclareOptions {
+            passive: true,
+            ..Default::default()
+        };
+
+        let table = FieldTable::default();
+        if channel.queue_declare(queue, options, table).await.is_err() {
+            return Ok(0);
+        }
+
+        // A task is removed from the old queue once it's queued again, and
+        // the tasks not removed are returned when the channel is closed.
+        let result = async {
+            let mut count = 0;
+            let options = BasicGetOptions { no_ack: false };
+            while let Some(message) =
+                channel.basic_get(queue, options).await.map_err(error)?
+            {
+                let delivery = message.delivery;
+                match serde_json::from_slice(&delivery.data) {
+                    Ok(task) => {
+                        self.publish(&task).await?;
+                        let options = BasicAckOptions::default();
+                        delivery.acker.ack(options).await.map_err(error)?;
+                        count += 1;
+                    },
+                    Err(e) => {
+                        tracing::error!("Discarding an invalid task: {e}");
+                        let options = BasicNackOptions::default();
+                        delivery.acker.nack(options).await.map_err(error)?;
+                    },
+                }
+            }
+
+            // Tasks published by older replicas in the meantime keep the
+            // queue until the next drain.
+            let options = QueueDeleteOptions {
+                if_empty: true,
+                ..Default::default()
+            };
+
+            channel.queue_delete(queue, options).await.map_err(error)?;
+            Ok(count)
+        }
+        .await;
+
+        let _ = channel.close(0, "Drained").await;
+        result
+    }
+
+    /// Publishes a task message to the exchange.
+    ///
+    /// The properties of the task are added to the given properties.
+    /// Returns once the broker confirms the task is routed to a queue.
+    async fn send(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        task: &ExtractionTask,
+        properties: BasicProperties,
+    ) -> Result<(), ErrorResponse> {
+        // Unroutable tasks are returned to fail the publish.
+        let options = BasicPublishOptions {
+            mandatory: true,
+            ..Default::default()
+        };
+
+        let properties = properties
+            .with_delivery_mode(PERSISTENT)
+            .with_priority(task.priority.clamp(0, MAX_PRIORITY) as u8)
+            .with_message_id(task.document_id.to_string().into())
+            .with_timestamp(Utc::now().timestamp() as u64);
+
+        // Unwrapping is safe because the task is serializable.
+        let payload = serde_json::to_vec(task).unwrap();
+
+        let error = |message: &str| ErrorResponse {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: message.to_string(),
+            solution: None,
+        };
+
+        let confirm = self
+            .channel()
+            .basic_publish(exchange, routing_key, options, &payload, properties)
+            .await
+            .map_err(|_e| {
+                #[cfg(test)]
+                eprintln!("Failed to queue up the task: {_e:?}");
+                error("Failed to queue up the task.")
+            })?;
+
+        let confirmation = confirm.await.map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to confirm the task: {_e:?}");
+            error("Failed to confirm the task.")
+        })?;
+
+        match confirmation {
+            Confirmation::Ack(None) => Ok(()),
+            Confirmation::Ack(Some(_)) => {
+                Err(error("The task was not routed to a queue."))
+            },
+            Confirmation::Nack(_) => Err(error("The queue rejected the task.")),
+            Confirmation::NotRequested => {
+                Err(error("The task was not confirmed by the queue."))
+            },
+        }
+    }
+
+    /// Declares the queue of the namespace and returns its name.
+    ///
+    /// The queue is only declared once per connection.
+    async fn declare_namespace(
+        &self,
+        namespace: &str,
+    ) -> Result<String, ErrorResponse> {
+        let queue = format!("{}.namespace.{namespace}", self.name);
+        let declared = self.link.read().unwrap().declared.clone();
+        if !declared.read().unwrap().contains_key(&queue) {
+            self.check_backlog(&queue).await?;
+        }
+
+        Ok(queue)
+    }
+
+    /// Returns the queue of the namespace with the number of tasks it held
+    /// when last checked.
+    ///
+    /// The number is checked again once it's older than the backlog TTL,
+    /// or the longer TTL of empty queues.
+    async fn backlog(
+        &self,
+        namespace: &str,
+    ) -> Result<(String, u32), ErrorResponse> {
+        let queue = format!("{}.namespace.{namespace}", self.name);
+        let declared = self.link.read().unwrap().declared.clone();
+        let backlog = declared.read().unwrap().get(&queue).copied();
+
+        let tasks = match backlog {
+            Some(backlog) if !backlog.is_stale() => backlog.tasks,
+            _ => self.check_backlog(&queue).await?,
+        };
+
+        Ok((queue, tasks))
+    }
+
+    /// Declares the namespace queue to get its number of tasks.
+    ///
+    /// Declaring a queue that exists with the same arguments only returns
+    /// its state, so the queue is declared again on every check.
+    async fn check_backlog(&self, queue: &str) -> Result<u32, ErrorResponse> {
+        let mut table = FieldTable::default();
+        let exchange = format!("{}.dlx", self.name);
+        let exchange = AMQPValue::LongString(exchange.into());
+        let priority = AMQPValue::LongInt(MAX_PRIORITY);
+        table.insert("x-dead-letter-exchange".into(), exchange);
+        table.insert("x-max-priority".into(), priority);
+
+        let options = QueueDeclareOptions {
+            durable: true,
+            ..Default::default()
+        };
+
+        let declared = self
+            .channel()
+            .queue_declare(queue, options, table)
+            .await
+            .map_err(|_e| {
+                #[cfg(test)]
+                eprintln!("Failed to declare the namespace queue: {_e:?}");
+                ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: String::from("Failed to declare the queue."),
+                    solution: None,
+                }
+            })?;
+
+        let tasks = declared.message_count();
+        self.set_backlog(queue, tasks);
+        Ok(tasks)
+    }
+
+    /// Records the number of tasks in the namespace queue.
+    fn set_backlog(&self, queue: &str, tasks: u32) {
+        let declared = self.link.read().unwrap().declared.clone();
+        let backlog = Backlog {
+            tasks,
+            checked_at: Instant::now(),
+        };
+
+        declared.write().unwrap().insert(queue.to_string(), backlog);
+    }
+
+    /// Returns the current channel to the queue.
+    fn channel(&self) -> Channel {
+        self.link.read().unwrap().channel.clone()
+    }
+
+    /// Returns the number of retry queues.
+    fn retry_levels(&self) -> u32 {
+        self.policy.max_attempts.saturating_sub(1).max(1)
+    }
+}
+
+#[async_trait]
+impl QueueAPI for QueueRabbitMQ {
+    async fn is_connected(&self) -> bool {
+        self.channel().status().connected()
+    }
+
+    async fn recover(&self) -> Result<bool, ErrorResponse> {
+        if self.is_connected().await {
+            return Ok(false);
+        }
+
+        let link = connect(&self.url, &self.name, &self.policy).await.map_err(
+            |_e| {
+                #[cfg(test)]
+                eprintln!("Failed to reconnect to the queue: {_e:?}");
+                ErrorResponse {
+                    code: StatusCode::SERVICE_UNAVAILABLE,
+                    message: String::from("Failed to reconnect to the queue."),
+                    solution: None,
+                }
+            },
+        )?;
+
+        let previous =
+            std::mem::replace(&mut *self.link.write().unwrap(), link);
+
+        // The previous connection may still be open if only the channel
+        // was closed by the broker.
+        let _ = previous.connection.close(0, "Reconnecting").await;
+        Ok(true)
+    }
+
+    async fn publish(
+        &self,
+        task: &ExtractionTask,
+    ) -> Result<(), ErrorResponse> {
+        let queue = self.declare_namespace(&task.namespace).await?;
+        let properties =
+            BasicProperties::default().with_headers(attempt_headers(0));
+        self.send("", &queue, task, properties).await?;
+
+        // The task is polled right away by the claims of this replica.
+        let declared = self.link.read().unwrap().declared.clone();
+        if let Some(backlog) = declared.write().unwrap().get_mut(&queue) {
+            backlog.tasks = backlog.tasks.saturating_add(1);
+        }
+
+        Ok(())
+    }
+
+    async fn retry(
+        &self,
+        task: &ExtractionTask,
+        attempts: u32,
+    ) -> Result<(), ErrorResponse> {
+        // Each attempt has its own retry queue and the task expires after
+        // the delay of the attempt. The tasks of a retry queue have the same
+        // delay, so they expire in order. The routing key sends the task
+        // back to its namespace queue.
+        let level = attempts.clamp(1, self.retry_levels());
+        let exchange = format!("{}.retry.{level}", self.name);
+        let queue = self.declare_namespace(&task.namespace).await?;
+
+        let delay = self.policy.backoff(level).saturating_mul(1000);
+        let properties = BasicProperties::default()
+            .with_headers(attempt_headers(attempts))
+            .with_expiration(delay.to_string().into());
+        self.send(&exchange, &queue, task, properties).await
+    }
+
+    async fn dead_letter(
+        &self,
+        task: &ExtractionTask,
+        attempts: u32,
+        code: &str,
+        message: &str,
+    ) -> Result<(), ErrorResponse> {
+        insert_dead_letter(&self.pool, task, attempts, code, message).await
+    }
+
+    async fn get(
+        &self,
+        namespaces: &[String],
+    ) -> Result<Option<Delivery>, ErrorResponse> {
+        let options = BasicGetOptions { no_ack: false };
+
+        // Only the namespaces with tasks are polled.
+        let mut message = None;
+        for namespace in namespaces {
+            let (queue, tasks) = self.backlog(namespace).await?;
+            if tasks == 0 {
+                continue;
+            }
+
+            message = self.channel().basic_get(&queue, options).await.map_err(
+                |_e| {
+                    #[cfg(test)]
+                    eprintln!("Failed to get a task from the queue: {_e:?}");
+                    ErrorResponse {
+                        code: StatusCode::INTERNAL_SERVER_ERROR,
+                        message: String::from("Failed to get a task."),
+                        solution: None,
+                    }
+                },
+            )?;
+
+            // The broker returns the number of tasks left with the task.
+            let tasks = message.as_ref().map_or(0, |m| m.message_count);
+            self.set_backlog(&queue, tasks);
+            if message.is_some() {
+                break;
+            }
+        }
+
+        let delivery = match message {
+            Some(message) => message.delivery,
+            None => return Ok(None),
+        };
+
+        match serde_json::from_slice(&delivery.data) {
+            Ok(task) => Ok(Some(Delivery {
+                task,
+                acker: Box::new(delivery.acker),
+            })),
+            // Messages that are not valid tasks are dead-lettered.
+            Err(e) => {
+                tracing::error!("Dead-lettering an invalid task: {e}");
+                let options = BasicNackOptions::default();
+                let _ = delivery.acker.nack(options).await;
+                Ok(None)
+            },
+        }
+    }
+
+    async fn dead_letters(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, ErrorResponse> {
+        select_dead_letters(&self.pool, limit).await
+    }
+
+    async fn dead_letter_of(
+        &self,
+        id: &DocumentID,
+    ) -> Result<Option<DeadLetter>, ErrorResponse> {
+        select_dead_letter(&self.pool, id).await
+    }
+
+    async fn replay(
+        &self,
+        id: &DocumentID,
+    ) -> Result<Option<DeadLetter>, ErrorResponse> {
+        let error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to replay the dead letter: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to replay the dead letter."),
+                solution: None,
+            }
+        };
+
+        // The dead letter is hidden from other replays while the task is
+        // published, so no connection is held while publishing. It's removed
+        // once the task is queued and shown again otherwise.
+        let row: Option<TaskRow> = sqlx::query_as(
+            "UPDATE tasks
+            SET visible_at = NOW() + make_interval(secs => $2)
+            WHERE id = (
+                SELECT id FROM tasks
+                WHERE dead
+                AND document_id = $1
+                AND visible_at <= NOW()
+                ORDER BY dead_lettered_at, id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *;",
+        )
+        .bind(id)
+        .bind(REPLAY_TIMEOUT as f64)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(error)?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let row_id = row.id;
+        let letter = DeadLetter::from(row);
+        let published = self.publish(&letter.task).await;
+
+        let settle = match published {
+            Ok(_) => "DELETE FROM tasks WHERE id = $1;",
+            Err(_) => "UPDATE tasks SET visible_at = NOW() WHERE id = $1;",
+        };
+
+        sqlx::query(settle)
+            .bind(row_id)
+            .execute(&self.pool)
+            .await
+            .map_err(error)?;
+
+        published?;
+        Ok(Some(letter))
+    }
+
+    #[cfg(test)]
+    async fn purge(&self) -> Result<(), ErrorResponse> {
+        use lapin::options::QueuePurgeOptions;
+
+        let declared = self.link.read().unwrap().declared.clone();
+        let mut queues: Vec<String> =
+            declared.read().unwrap().keys().cloned().collect();
+        queues.push(format!("{}.dead", self.name));
+        for level in 1..=self.retry_levels() {
+            queues.push(format!("{}.retry.{level}", self.name));
+        }
+
+        for queue in queues {
+            let options = QueuePurgeOptions::default();
+            self.channel().queue_purge(&queue, options).await.map_err(
+                |_e| {
+                    #[cfg(test)]
+                    eprintln!("Failed to purge the queue: {_e:?}");
+                    ErrorResponse {
+                        code: StatusCode::INTERNAL_SERVER_ERROR,
+                        message: String::from("Failed to purge the queue."),
+                        solution: None,
+                    }
+                },
+            )?;
+        }
+
+        sqlx::query("DELETE FROM tasks WHERE dead;")
+            .execute(&self.pool)
+            .await
+            .map_err(|_e| {
+                #[cfg(test)]
+                eprintln!("Failed to purge the dead letters: {_e:?}");
+                ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: String::from("Failed to purge the dead letters."),
+                    solution: None,
+                }
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Opens a channel to the queue with publisher confirms and declares the
+/// queues and exchanges of the task queue.
+async fn connect(
+    url: &str,
+    name: &str,
+    policy: &RetryPolicy,
+) -> LapinResult<Link> {
+    let property = ConnectionProperties::default();
+    let connection = Connection::connect(url, property).await?;
+    let channel = connection.create_channel().await?;
+
+    // Publishing waits for the broker to confirm the task is queued.
+    let options = ConfirmSelectOptions::default();
+    channel.confirm_select(options).await?;
+
+    declare_topology(&channel, name, policy).await?;
+    Ok(Link {
+        connection: Arc::new(connection),
+        channel,
+        declared: Arc::default(),
+    })
+}
+
+/// Declares the queues and exchanges of the task queue.
+async fn declare_topology(
+    channel: &Channel,
+    name: &str,
+    policy: &RetryPolicy,
+) -> LapinResult<()> {
+    let dlx = format!("{name}.dlx");
+    let dead = format!("{name}.dead");
+
+    let options = ExchangeDeclareOptions {
+        durable: true,
+        ..Default::default()
+    };
+
+    let kind = ExchangeKind::Fanout;
+    let table = FieldTable::default();
+    channel.exchange_declare(&dlx, kind, options, table).await?;
+
+    let options = QueueDeclareOptions {
+        durable: true,
+        ..Default::default()
+    };
+
+    let table = FieldTable::default();
+    channel.queue_declare(&dead, options, table).await?;
+
+    let options = QueueBindOptions::default();
+    let table = FieldTable::default();
+    channel
+        .queue_bind(&dead, &dlx, name, options, table)
+        .await?;
+
+    // The retry queues have no consumers and send the tasks back to the
+    // default exchange when their delay expires. The tasks keep the routing
+    // key of their namespace queue they were published with.
+    let levels = policy.max_attempts.saturating_sub(1).max(1);
+    for level in 1..=levels {
+        let retry = format!("{name}.retry.{level}");
+        let options = ExchangeDeclareOptions {
+            durable: true,
+            ..Default::default()
+        };
+
+        let kind = ExchangeKind::Fanout;
+        let table = FieldTable::default();
+        channel
+            .exchange_declare(&retry, kind, options, table)
+            .await?;
+
+        let mut table = FieldTable::default();
+        let exchange = AMQPValue::LongString("".into());
+        table.insert("x-dead-letter-exchange".into(), exchange);
+
+        let options = QueueDeclareOptions {
+            durable: true,
+            ..Default::default()
+        };
+
+        channel.queue_declare(&retry, options, table).await?;
+
+        let options = QueueBindOptions::default();
+        let table = FieldTable::default();
+        channel
+            .queue_bind(&retry, &retry, "", options, table)
+            .await?;
+    }
+
+    Ok(())
+}
+
+fn attempt_headers(attempts: u32) -> FieldTable {
+    let mut headers = FieldTable::default();
+    let attempts = AMQPValue::LongLongInt(attempts as i64);
+    headers.insert(ATTEMPTS_HEADER.into(), attempts);
+    headers
+}
+
+#[async_trait]
+impl DeliveryAcker for Acker {
+    async fn ack(self: Box<Self>) -> Result<(), ErrorResponse> {
+        let options = BasicAckOptions::default();
+        Acker::ack(&self, options).await.map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to acknowledge the task: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to acknowledge the task."),
+                solution: None,
+            }
+        })
+    }
+
+    async fn requeue(self: Box<Self>) -> Result<(), ErrorResponse> {
+        let options = BasicNackOptions {
+            requeue: true,
+            ..Default::default()
+        };
+
+        self.nack(options).await.map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to requeue the task: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to requeue the task."),
+                solution: None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::Configuration;
+    use sqlx::postgres::PgPoolOptions;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_recover() {
+        let queue = setup().await;
+        assert!(!queue.recover().await.unwrap());
+
+        queue
+            .channel()
+            .close(0, "Closed by the test")
+            .await
+            .unwrap();
+        assert!(!queue.is_connected().await);
+
+        assert!(queue.recover().await.unwrap());
+        assert!(queue.is_connected().await);
+    }
+
+    #[tokio::test]
+    async fn test_send_unroutable() {
+        let queue = setup().await;
+        let task = ExtractionTask {
+            namespace: "test_ns".to_string(),
+            document_key: "test_ns/document.pdf".to_string(),
+            document_id: Uuid::new_v4(),
+            priority: 0,
+        };
+
+        // Tasks that no queue accepts are returned and fail the publish.
+        let properties = BasicProperties::default();
+        let result = queue.send("", "missing_queue", &task, properties).await;
+        assert!(result.is_err());
+
+        queue.publish(&task).await.unwrap();
+        queue.purge().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let config = Configuration::default();
+        let url = config.queue_url.unwrap();
+        let property = ConnectionProperties::default();
+        let connection = Connection::connect(url.as_str(), property).await;
+        let channel = connection.unwrap().create_channel().await.unwrap();
+
+        // Tasks left in the queue of an older version are moved over.
+        let legacy = "legacy_tasks";
+        let options = QueueDeclareOptions::default();
+        let table = FieldTable::default();
+        channel.queue_declare(legacy, options, table).await.unwrap();
+
+        let task = ExtractionTask {
+            namespace: "test_ns".to_string(),
+            document_key: "test_ns/document.pdf".to_string(),
+            document_id: Uuid::new_v4(),
+            priority: 0,
+        };
+
+        let payload = serde_json::to_vec(&task).unwrap();
+        let options = BasicPublishOptions::default();
+        let properties = BasicProperties::default();
+        channel
+            .basic_publish("", legacy, options, &payload, properties)
+            .await
+            .unwrap();
+
+        let pool = PgPoolOptions::new()
+            .connect(config.database_url.as_str())
+            .await
+            .unwrap();
+
+        let policy = RetryPolicy {
+            max_attempts: config.max_attempts,
+            delay: config.retry_delay,
+        };
+
+        let queue = QueueRabbitMQ::new(legacy, url, pool, policy).await;
+        let namespaces = vec![task.namespace.clone()];
+        let delivery = queue.get(&namespaces).await.unwrap().unwrap();
+        assert_eq!(delivery.task.document_id, task.document_id);
+        delivery.ack().await.unwrap();
+
+        // The old queue is deleted once drained.
+        assert_eq!(queue.drain(legacy).await.unwrap(), 0);
+        assert!(queue.get(&namespaces).await.unwrap().is_none());
+    }
+
+    async fn setup() -> QueueRabbitMQ {
+        let config = Configuration::default();
+        let url = config.queue_url.unwrap();
+        let pool = PgPoolOptions::new()
+            .connect(config.database_url.as_str())
+            .await
+            .unwrap();
+
+        let policy = RetryPolicy {
+            max_attempts: config.max_attempts,
+            delay: config.retry_delay,
+        };
+
+        QueueRabbitMQ::new("tasks", url, pool, policy).await
+    }
+}
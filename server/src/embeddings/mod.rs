@@ -16,4 +16,12 @@ pub type DenseVector = Vec<f32>;
 pub trait EmbeddingModel: Send + Sync {
     /// Generates a vector embedding for the given text.
     async fn generate(&self, text: &str) -> Result<DenseVector, ErrorResponse>;
+
+    /// Generates vector embeddings for the texts in a single request.
+    ///
+    /// The embeddings are returned in the same order as the texts.
+    async fn generate_batch(
+        &self,
+        texts: &[String],
+    ) -> Result<Vec<DenseVector>, ErrorResponse>;
 }
@@ -6,6 +6,9 @@ const MODELS: [&str; 3] = [
     "text-embedding-3-large",
 ];
 
+// Maximum number of texts OpenAI embeds in a single request.
+const MAX_INPUTS: usize = 2048;
+
 pub struct EmbeddingOpenAI {
     model: String,
     secret: String,
@@ -71,6 +74,63 @@ impl EmbeddingModel for EmbeddingOpenAI {
             solution: None,
         })
     }
+
+    async fn generate_batch(
+        &self,
+        texts: &[String],
+    ) -> Result<Vec<DenseVector>, ErrorResponse> {
+        let error = || ErrorResponse {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: "Failed to generate embeddings with OpenAI.".to_string(),
+            solution: None,
+        };
+
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for texts in texts.chunks(MAX_INPUTS) {
+            let body = json!({
+                "model": self.model,
+                "input": texts,
+            });
+
+            let response = Client::new()
+                .post("https://api.openai.com/v1/embeddings")
+                .header("Authorization", &format!("Bearer {}", &self.secret))
+                .json(&body)
+                .send()
+                .await
+                .map_err(|_| ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: "Failed to send the request to OpenAI."
+                        .to_string(),
+                    solution: None,
+                })?;
+
+            let json: Value = response.json().await.map_err(|_| error())?;
+            let data = json["data"].as_array().ok_or_else(error)?;
+
+            // The embeddings are placed by their index in case OpenAI
+            // doesn't return them in the order of the input.
+            let mut batch = vec![DenseVector::new(); texts.len()];
+            for item in data {
+                let index = item["index"].as_u64().ok_or_else(error)?;
+                let embedding =
+                    item["embedding"].as_array().ok_or_else(error)?;
+                let slot = batch.get_mut(index as usize).ok_or_else(error)?;
+                *slot = embedding
+                    .iter()
+                    .map(|value| value.as_f64().unwrap_or_default() as f32)
+                    .collect();
+            }
+
+            if batch.iter().any(|embedding| embedding.is_empty()) {
+                return Err(error());
+            }
+
+            embeddings.extend(batch);
+        }
+
+        Ok(embeddings)
+    }
 }
 
 #[cfg(test)]
@@ -85,4 +145,14 @@ mod tests {
         let embedding = model.generate("Hello, world!").await.unwrap();
         assert_eq!(embedding.len(), 1536);
     }
+
+    #[tokio::test]
+    async fn test_generate_batch() {
+        dotenv().ok();
+        let model = EmbeddingOpenAI::new("text-embedding-ada-002").unwrap();
+        let texts = vec!["Hello".to_string(), "World".to_string()];
+        let embeddings = model.generate_batch(&texts).await.unwrap();
+        assert_eq!(embeddings.len(), 2);
+        assert!(embeddings.iter().all(|e| e.len() == 1536));
+    }
 }
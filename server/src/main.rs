@@ -5,9 +5,10 @@ mod services;
 mod types;
 mod utils;
 
+use apis::QueueBackend;
+use axum_server::tls_rustls::RustlsConfig;
 use clap::{ArgMatches, Command};
 use protos::coordinator_server::CoordinatorServer;
-use reqwest::ClientBuilder;
 use semver::Version;
 use services::interface::create_router;
 use services::{Configuration, Service};
@@ -17,9 +18,10 @@ use std::sync::Arc;
 use std::{env, fs};
 use tokio::net::TcpListener;
 use tokio::time::{sleep, Duration};
-use tonic::transport::Server;
-use types::WorkerID;
+use tonic::transport::{Server, ServerTlsConfig};
+use types::{DocumentStatus, Namespace};
 use url::Url;
+use utils::TlsFiles;
 
 // List of commands.
 // We do this to avoid using string literals in the code.
@@ -31,6 +33,12 @@ async fn main() {
     dotenv::dotenv().ok();
     tracing_subscriber::fmt::init();
 
+    // Multiple crypto providers are enabled by the dependencies so we need
+    // to pick one explicitly for the TLS servers.
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("Failed to install the crypto provider");
+
     let cli = Command::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .about("Interface to setup and manage the server")
@@ -58,9 +66,19 @@ fn configuration() -> Configuration {
         env::var(name).expect(&error)
     };
 
-    let queue_url = getenv("DL_QUEUE_URL")
-        .parse::<Url>()
-        .expect("Please provide a valid RabbitMQ URL");
+    let queue_backend = match env::var("DL_QUEUE_BACKEND").ok() {
+        Some(backend) => backend.parse().expect("Invalid queue backend"),
+        None => QueueBackend::RabbitMQ,
+    };
+
+    let queue_url = env::var("DL_QUEUE_URL").ok().map(|url| {
+        url.parse::<Url>()
+            .expect("Please provide a valid RabbitMQ URL")
+    });
+
+    if queue_backend == QueueBackend::RabbitMQ && queue_url.is_none() {
+        panic!("Please set the DL_QUEUE_URL environment variable");
+    }
 
     let database_url = getenv("DL_DATABASE_URL")
         .parse::<Url>()
@@ -71,12 +89,50 @@ fn configuration() -> Configuration {
         None => 8,
     };
 
+    let max_query_k = match env::var("DL_MAX_QUERY_K").ok() {
+        Some(max_query_k) => max_query_k.parse().expect("Invalid maximum k"),
+        None => 100,
+    };
+
+    let worker_timeout = match env::var("DL_WORKER_TIMEOUT").ok() {
+        Some(timeout) => timeout.parse().expect("Invalid worker timeout"),
+        None => 60,
+    };
+
+    let lease_timeout = match env::var("DL_LEASE_TIMEOUT").ok() {
+        Some(timeout) => timeout.parse().expect("Invalid lease timeout"),
+        None => 300,
+    };
+
+    let processing_timeout = match env::var("DL_PROCESSING_TIMEOUT").ok() {
+        Some(timeout) => timeout.parse().expect("Invalid processing timeout"),
+        None => 3600,
+    };
+
+    let max_attempts = match env::var("DL_MAX_ATTEMPTS").ok() {
+        Some(attempts) => attempts.parse().expect("Invalid maximum attempts"),
+        None => 3,
+    };
+
+    let retry_delay = match env::var("DL_RETRY_DELAY").ok() {
+        Some(delay) => delay.parse().expect("Invalid retry delay"),
+        None => 30,
+    };
+
     Configuration {
         secret: getenv("DL_SECRET_KEY"),
+        worker_token: getenv("DL_WORKER_TOKEN"),
         bucket: getenv("DL_BUCKET_NAME"),
+        queue_backend,
         queue_url,
         database_url,
         pool_size,
+        max_query_k,
+        worker_timeout,
+        lease_timeout,
+        processing_timeout,
+        max_attempts,
+        retry_delay,
     }
 }
 
@@ -103,15 +159,39 @@ async fn start_handler(_args: &ArgMatches) {
         start_interface_server(interface_service).await;
     });
 
-    let worker_validator_service = service.clone();
-    let worker_validator_loop = tokio::spawn(async move {
-        start_worker_validator_loop(worker_validator_service).await;
+    let worker_expiry_service = service.clone();
+    let worker_expiry_loop = tokio::spawn(async move {
+        start_worker_expiry_loop(worker_expiry_service).await;
+    });
+
+    let lease_expiry_service = service.clone();
+    let lease_expiry_loop = tokio::spawn(async move {
+        start_lease_expiry_loop(lease_expiry_service).await;
+    });
+
+    let document_recovery_service = service.clone();
+    let document_recovery_loop = tokio::spawn(async move {
+        start_document_recovery_loop(document_recovery_service).await;
+    });
+
+    let outbox_relay_service = service.clone();
+    let outbox_relay_loop = tokio::spawn(async move {
+        start_outbox_relay_loop(outbox_relay_service).await;
+    });
+
+    let queue_recovery_service = service.clone();
+    let queue_recovery_loop = tokio::spawn(async move {
+        start_queue_recovery_loop(queue_recovery_service).await;
     });
 
     let _ = tokio::join!(
         coordinator_server,
         interface_server,
-        worker_validator_loop
+        worker_expiry_loop,
+        lease_expiry_loop,
+        document_recovery_loop,
+        outbox_relay_loop,
+        queue_recovery_loop
     );
 }
 
@@ -121,27 +201,65 @@ async fn start_coordinator_server(service: Arc<Service>) {
         None => 2500,
     };
 
+    let mut server = Server::builder();
+    if let Some(tls) = coordinator_tls_config() {
+        server = server.tls_config(tls).expect("Invalid coordinator TLS");
+        tracing::info!("The coordinator server is secured with TLS");
+    }
+
     let addr = format!("[::]:{port}").parse().unwrap();
     tracing::info!("The coordinator server is ready on port {port}");
 
-    Server::builder()
-        .add_service(CoordinatorServer::new(service))
+    let interceptor = service.worker_interceptor();
+    server
+        .add_service(CoordinatorServer::with_interceptor(service, interceptor))
         .serve(addr)
         .await
         .expect("Failed to start the coordinator server");
 }
 
+/// Reads the TLS configuration of the coordinator server, if any.
+///
+/// TLS is enabled when a certificate is provided. Setting a client CA
+/// enables mutual TLS, requiring workers to present a certificate.
+fn coordinator_tls_config() -> Option<ServerTlsConfig> {
+    let cert = env::var("DL_COORDINATOR_TLS_CERT").ok()?;
+    let key = env::var("DL_COORDINATOR_TLS_KEY")
+        .expect("Please set the DL_COORDINATOR_TLS_KEY environment variable");
+
+    let client_ca = env::var("DL_COORDINATOR_TLS_CLIENT_CA").ok();
+    let config = utils::coordinator_tls_config(cert, key, client_ca)
+        .expect("Failed to read the coordinator TLS files");
+
+    Some(config)
+}
+
 async fn start_interface_server(service: Arc<Service>) {
     let port = match env::var("DL_INTERFACE_PORT").ok() {
         Some(port) => port.parse().expect("Invalid interface port"),
         None => 2505,
     };
 
+    let app = create_router(service);
+    if let Some(files) = interface_tls_files() {
+        let config = files.load().await.expect("Invalid interface TLS");
+        tokio::spawn(start_tls_reload_loop(files, config.clone()));
+
+        let addr = format!("[::]:{port}").parse().unwrap();
+        tracing::info!("The interface server is ready on port {port} (TLS)");
+
+        axum_server::bind_rustls(addr, config)
+            .serve(app.into_make_service())
+            .await
+            .expect("Failed to start the interface server");
+
+        return;
+    }
+
     let listener = TcpListener::bind(format!("[::]:{port}"))
         .await
         .expect("Failed to bind a listener");
 
-    let app = create_router(service);
     tracing::info!("The interface server is ready on port {port}");
 
     axum::serve(listener, app)
@@ -149,36 +267,150 @@ async fn start_interface_server(service: Arc<Service>) {
         .expect("Failed to start the interface server");
 }
 
-/// Starts a loop that validates connected workers.
+/// Reads the TLS certificate and key files of the interface server, if any.
+fn interface_tls_files() -> Option<TlsFiles> {
+    let cert = env::var("DL_INTERFACE_TLS_CERT").ok()?;
+    let key = env::var("DL_INTERFACE_TLS_KEY")
+        .expect("Please set the DL_INTERFACE_TLS_KEY environment variable");
+
+    Some(TlsFiles::new(cert, key))
+}
+
+/// Starts a loop that reloads the interface TLS configuration.
+///
+/// The files are checked on a regular interval so that renewed certificates
+/// are picked up without restarting the server. If the new files are
+/// invalid, the server keeps using the current configuration.
+async fn start_tls_reload_loop(mut files: TlsFiles, config: RustlsConfig) {
+    loop {
+        sleep(Duration::from_secs(10)).await;
+        if !files.changed() {
+            continue;
+        }
+
+        match files.reload(&config).await {
+            Ok(_) => tracing::info!("Reloaded the interface TLS configuration"),
+            Err(e) => {
+                tracing::error!("Failed to reload the interface TLS: {e}")
+            },
+        }
+    }
+}
+
+/// Starts a loop that expires workers that stopped sending heartbeats.
 ///
-/// This loop will fetch the list of workers from the service on a regular
-/// interval and validate the connection to each worker by calling a health
-/// check endpoint on the worker.
-async fn start_worker_validator_loop(service: Arc<Service>) {
+/// The registry is shared by the coordinator replicas so any replica can
+/// expire the workers. Removing the same worker twice is harmless.
+async fn start_worker_expiry_loop(service: Arc<Service>) {
     loop {
         sleep(Duration::from_secs(30)).await;
 
-        let mut missing_workers: Vec<WorkerID> = Vec::new();
-        let workers = service.workers().await;
+        let workers = match service.expire_workers().await {
+            Ok(workers) => workers,
+            Err(e) => {
+                tracing::error!("Failed to expire workers: {}", e.message);
+                continue;
+            },
+        };
+
+        if !workers.is_empty() {
+            let n = workers.len();
+            tracing::warn!("Expired {n} worker(s) that missed heartbeats");
+        }
+    }
+}
 
-        for worker in workers.iter() {
-            let client = ClientBuilder::new()
-                .timeout(Duration::from_secs(3))
-                .build()
-                .expect("Failed to create a HTTP client");
+/// Starts a loop that requeues the tasks of expired leases.
+///
+/// A lease expires when the worker stops renewing it, for example because
+/// the worker crashed, so the document is processed by another worker.
+async fn start_lease_expiry_loop(service: Arc<Service>) {
+    loop {
+        sleep(Duration::from_secs(10)).await;
+
+        let leases = match service.requeue_expired_leases().await {
+            Ok(leases) => leases,
+            Err(e) => {
+                tracing::error!("Failed to requeue tasks: {}", e.message);
+                continue;
+            },
+        };
+
+        for lease in leases.iter() {
+            let id = lease.task.document_id;
+            let worker = lease.worker_id;
+            tracing::warn!("Requeued task {id} of expired lease from {worker}");
+        }
+    }
+}
 
-            let url = format!("http://{}", worker.address);
-            let response = client.get(url).send().await;
-            if response.is_err() {
-                missing_workers.push(worker.id);
+/// Starts a loop that recovers documents stuck in processing.
+///
+/// Documents get stuck when a worker stops after marking them as processing
+/// without holding a lease. They are requeued until they reach the maximum
+/// attempts, after which they are marked as failed.
+async fn start_document_recovery_loop(service: Arc<Service>) {
+    loop {
+        sleep(Duration::from_secs(60)).await;
+
+        let documents = match service.recover_stuck_documents().await {
+            Ok(documents) => documents,
+            Err(e) => {
+                tracing::error!("Failed to recover documents: {}", e.message);
+                continue;
+            },
+        };
+
+        for document in documents.iter() {
+            let id = document.id;
+            match document.status {
+                DocumentStatus::Pending => {
+                    tracing::warn!("Requeued stuck document {id}")
+                },
+                _ => tracing::warn!("Failed stuck document {id}"),
             }
         }
+    }
+}
+
+/// Starts a loop that publishes the tasks left in the outbox.
+///
+/// Tasks are left in the outbox when the queue was unavailable while the
+/// document was uploaded, or when they were recovered from expired leases
+/// and stuck documents.
+async fn start_outbox_relay_loop(service: Arc<Service>) {
+    loop {
+        sleep(Duration::from_secs(5)).await;
+
+        match service.relay_outbox(None).await {
+            Ok(0) => {},
+            Ok(count) => tracing::info!("Relayed {count} task(s) from outbox"),
+            Err(e) => tracing::error!("Failed to relay tasks: {}", e.message),
+        }
+    }
+}
 
-        if !missing_workers.is_empty() {
-            let n = missing_workers.len();
-            tracing::warn!("Detected {n} missing worker(s). Removing...");
-            service.remove_workers(&missing_workers).await;
-            tracing::info!("Successfully removed {n} missing worker(s)");
+/// Starts a loop that reconnects to the queue when the connection is lost.
+///
+/// The delay between failed reconnections doubles up to a minute so a
+/// broker that is down isn't flooded with connection attempts.
+async fn start_queue_recovery_loop(service: Arc<Service>) {
+    let mut delay = 5;
+    loop {
+        sleep(Duration::from_secs(delay)).await;
+
+        match service.recover_queue().await {
+            Ok(reconnected) => {
+                if reconnected {
+                    tracing::info!("Reconnected to the queue");
+                }
+
+                delay = 5;
+            },
+            Err(e) => {
+                delay = (delay * 2).min(60);
+                tracing::error!("{} Retrying in {delay}s.", e.message);
+            },
         }
     }
 }
@@ -201,9 +433,9 @@ async fn migrate_handler(_args: &ArgMatches) {
         .await
         .unwrap_or("0.0.0".parse::<Version>().unwrap());
 
+    // The namespaces are upgraded even if the schema is up-to-date.
     if schema_version == target_version {
         tracing::info!("The database schema is up-to-date");
-        return;
     }
 
     // List the migration scripts that need to be applied.
@@ -255,6 +487,24 @@ async fn migrate_handler(_args: &ArgMatches) {
 
         tracing::info!("Migrated the database schema to version {}", migration);
     }
+
+    // The existing namespaces are upgraded to apply new columns and indexes
+    // from the latest version without locking out writes. The upgrades are
+    // idempotent and always run, so running the migration again resumes an
+    // upgrade that failed partway.
+    let namespaces: Vec<Namespace> = sqlx::query_as("SELECT * FROM namespaces")
+        .fetch_all(&pool)
+        .await
+        .expect("Failed to retrieve the namespaces");
+
+    for namespace in namespaces.iter() {
+        namespace
+            .upgrade(&pool)
+            .await
+            .expect("Failed to upgrade the namespace");
+
+        tracing::info!("Upgraded the namespace: {}", namespace.name);
+    }
 }
 
 async fn schema_version(url: &Url) -> Option<Version> {
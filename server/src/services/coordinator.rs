@@ -1,15 +1,22 @@
 use super::*;
 use protos::coordinator_server::Coordinator;
-use tonic::{Request, Response, Status};
+use sqlx::{Executor, Postgres, Transaction};
+use subtle::ConstantTimeEq;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
 
 #[tonic::async_trait]
 impl Coordinator for Arc<Service> {
     async fn heartbeat(
         &self,
-        _request: Request<()>,
+        request: Request<protos::HeartbeatRequest>,
     ) -> Result<Response<protos::HeartbeatResponse>, Status> {
+        let heartbeat: WorkerHeartbeat = request.into_inner().try_into()?;
+        let worker = self.record_heartbeat(&heartbeat).await?;
         Ok(Response::new(protos::HeartbeatResponse {
             version: env!("CARGO_PKG_VERSION").to_string(),
+            registered: worker.is_some(),
+            draining: worker.is_some_and(|worker| worker.draining),
         }))
     }
 
@@ -18,11 +25,42 @@ impl Coordinator for Arc<Service> {
         request: Request<protos::RegisterWorkerRequest>,
     ) -> Result<Response<()>, Status> {
         let worker: Worker = request.into_inner().try_into()?;
-        self.add_worker(&worker).await;
+        self.add_worker(&worker).await?;
         tracing::info!("A worker is registered: {}", worker.id);
         Ok(Response::new(()))
     }
 
+    async fn claim_task(
+        &self,
+        request: Request<protos::ClaimTaskRequest>,
+    ) -> Result<Response<protos::ClaimTaskResponse>, Status> {
+        let request = request.into_inner();
+        let worker_id = self.validate_uuid(&request.worker_id)?;
+
+        let lease = self.lease_task(&worker_id).await?;
+        if let Some(lease) = &lease {
+            let id = lease.task.document_id;
+            tracing::info!("A task is leased to worker {worker_id}: {id}");
+        }
+
+        Ok(Response::new(protos::ClaimTaskResponse {
+            task: lease.map(|lease| lease.task.into()),
+            lease_duration: self.config.lease_timeout as u32,
+        }))
+    }
+
+    async fn renew_lease(
+        &self,
+        request: Request<protos::RenewLeaseRequest>,
+    ) -> Result<Response<()>, Status> {
+        let request = request.into_inner();
+        let worker_id = self.validate_uuid(&request.worker_id)?;
+        let document_id = self.validate_uuid(&request.document_id)?;
+
+        self.extend_lease(&worker_id, &document_id).await?;
+        Ok(Response::new(()))
+    }
+
     async fn update_document(
         &self,
         request: Request<protos::UpdateDocumentRequest>,
@@ -30,17 +68,72 @@ impl Coordinator for Arc<Service> {
         let request = request.into_inner();
         let namespace = self.get_namespace(&request.namespace).await?;
         let id = self.validate_uuid(&request.document_id)?;
+        let worker_id = self.validate_uuid(&request.worker_id)?;
         let status = DocumentStatus::from(request.status());
+        let properties = request
+            .properties
+            .map(DocumentProperties::from)
+            .unwrap_or_default();
 
+        let error = match (&status, request.error) {
+            (DocumentStatus::Failed, Some(error)) => Some(error),
+            (DocumentStatus::Failed, None) => Some(protos::DocumentError {
+                code: "unknown".to_string(),
+                message: "The worker didn't report the reason.".to_string(),
+            }),
+            _ => None,
+        };
+
+        // Only the worker holding the lease can update the document, so a
+        // worker whose task was handed to another worker can't overwrite it.
+        let mut tx = self.begin_transaction().await?;
+        self.hold_lease(&mut tx, &id, &worker_id).await?;
+
+        // The properties that are not provided are left unchanged.
+        // An attempt is counted when the document starts processing and the
+        // failure reason is kept until the document is completed. Failures
+        // are retried until the document reaches the maximum attempts.
         let schema = namespace.schema();
-        sqlx::query(&format!(
+        let document: Option<Document> = sqlx::query_as(&format!(
             "UPDATE {schema}.documents
-            SET status = $2, updated_at = NOW()
-            WHERE id = $1;",
+            SET status = CASE
+                WHEN $2 = 'failed' AND attempts < $10 THEN 'pending'
+                ELSE $2 END::doc_status,
+            title = COALESCE($3, title),
+            author = COALESCE($4, author),
+            page_count = COALESCE($5, page_count),
+            language = COALESCE($6, language),
+            ocr = COALESCE($7, ocr),
+            attempts = CASE
+                WHEN $2 = 'processing' AND status <> 'processing'
+                THEN attempts + 1 ELSE attempts END,
+            started_at = CASE
+                WHEN $2 = 'processing' AND status <> 'processing'
+                THEN NOW() ELSE started_at END,
+            error_code = CASE
+                WHEN $2 = 'failed' THEN $8
+                WHEN $2 = 'completed' THEN NULL ELSE error_code END,
+            error_message = CASE
+                WHEN $2 = 'failed' THEN $9
+                WHEN $2 = 'completed' THEN NULL ELSE error_message END,
+            failed_at = CASE
+                WHEN $2 = 'failed' AND attempts >= $10 THEN NOW()
+                WHEN $2 = 'completed' THEN NULL ELSE failed_at END,
+            updated_at = NOW()
+            WHERE id = $1
+            RETURNING *;",
         ))
         .bind(id)
         .bind(&status)
-        .execute(&self.database)
+        .bind(&properties.title)
+        .bind(&properties.author)
+        .bind(properties.page_count)
+        .bind(&properties.language)
+        .bind(properties.ocr)
+        .bind(error.as_ref().map(|error| &error.code))
+        .bind(error.as_ref().map(|error| &error.message))
+        .bind(self.config.max_attempts as i32)
+        .fetch_optional(&mut *tx)
         .await
         .map_err(|_e| {
             #[cfg(test)]
@@ -48,6 +141,44 @@ impl Coordinator for Arc<Service> {
             Status::internal("Failed to update the document.")
         })?;
 
+        if matches!(status, DocumentStatus::Completed | DocumentStatus::Failed)
+        {
+            self.release_lease(&mut *tx, &id, &worker_id).await?;
+        }
+
+        // Failed tasks are retried, or dead-lettered once the document is
+        // failed so the task can be replayed after the cause is fixed.
+        let failed = matches!((&document, &error), (Some(_), Some(_)));
+        if let (Some(document), Some(error)) = (document, &error) {
+            let task = ExtractionTask {
+                namespace: namespace.name.clone(),
+                document_key: document.key(&namespace),
+                document_id: document.id,
+                priority: document.priority,
+            };
+
+            let error = match document.status {
+                DocumentStatus::Failed => {
+                    Some((error.code.as_str(), error.message.as_str()))
+                },
+                _ => None,
+            };
+
+            let attempts = document.attempts as u32;
+            self.write_outbox(&mut *tx, &task, attempts, error).await?;
+        }
+
+        self.commit_transaction(tx).await?;
+
+        if failed {
+            if let Err(e) = self.relay_outbox(Some(&id)).await {
+                tracing::warn!(
+                    "Task of {id} is left to the relay: {}",
+                    e.message
+                );
+            }
+        }
+
         Ok(Response::new(()))
     }
 
@@ -58,13 +189,11 @@ impl Coordinator for Arc<Service> {
         let request = request.into_inner();
         let namespace = self.get_namespace(&request.namespace).await?;
         let document_id = self.validate_uuid(&request.document_id)?;
+        let worker_id = self.validate_uuid(&request.worker_id)?;
 
-        let model = namespace.config.embedding.model()?;
-        let mut embeddings = Vec::new();
-        for chunk in &request.chunks {
-            let embedding = model.generate(&chunk.content).await?;
-            embeddings.push(embedding);
-        }
+        // The chunks are embedded before starting the transaction so that
+        // the connection isn't held while waiting for the embeddings.
+        let embeddings = self.embed_chunks(&namespace, &request.chunks).await?;
 
         let mut tx = self.database.begin().await.map_err(|_e| {
             #[cfg(test)]
@@ -72,19 +201,392 @@ impl Coordinator for Arc<Service> {
             Status::internal("Failed to start a transaction.")
         })?;
 
+        self.hold_lease(&mut tx, &document_id, &worker_id).await?;
+
+        // The chunks replace the existing chunks of the document so that
+        // retrying the request doesn't duplicate the chunks.
+        self.remove_chunks(&mut tx, &namespace, &document_id)
+            .await?;
+        self.insert_chunks(
+            &mut tx,
+            &format!("{}.chunks", namespace.schema()),
+            &document_id,
+            &request.chunks,
+            embeddings,
+            0,
+        )
+        .await?;
+        self.complete_document(&mut tx, &namespace, &document_id, &worker_id)
+            .await?;
+
+        tx.commit().await.map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to commit the transaction: {_e:?}");
+            Status::internal("Failed to commit the transaction.")
+        })?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn stream_chunks(
+        &self,
+        request: Request<Streaming<protos::StreamChunksRequest>>,
+    ) -> Result<Response<protos::StreamChunksResponse>, Status> {
+        let chunks = self.receive_chunks(request.into_inner()).await?;
+        Ok(Response::new(protos::StreamChunksResponse {
+            chunks: chunks as u32,
+        }))
+    }
+}
+
+impl Service {
+    /// Creates an interceptor that authenticates workers by their token.
+    ///
+    /// Workers must provide the worker token as a bearer token in the
+    /// authorization metadata of every request to the coordinator server.
+    #[allow(clippy::result_large_err)]
+    pub fn worker_interceptor(
+        &self,
+    ) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+        let expected = format!("Bearer {}", self.config.worker_token);
+        move |request: Request<()>| {
+            let token = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok());
+
+            // The token is compared in constant time so the comparison
+            // doesn't leak how much of the token is correct.
+            let valid = token.is_some_and(|token| {
+                token.as_bytes().ct_eq(expected.as_bytes()).into()
+            });
+
+            match valid {
+                true => Ok(request),
+                false => Err(Status::unauthenticated(
+                    "Please provide a valid worker token.",
+                )),
+            }
+        }
+    }
+
+    /// Receives chunk batches of a document from the stream.
+    ///
+    /// Each batch is embedded and then written to the staged chunks of the
+    /// document in its own transaction, so no connection is held while
+    /// waiting for the worker or the embeddings. The staged chunks replace
+    /// the chunks of the document when the commit message arrives. If the
+    /// stream ends without a commit, the staged chunks are left unused and
+    /// discarded by the next stream of the document.
+    ///
+    /// Every message is checked against the lease of the document, so a
+    /// worker whose task was handed to another worker can't write chunks.
+    async fn receive_chunks(
+        &self,
+        mut stream: impl Stream<Item = Result<protos::StreamChunksRequest, Status>>
+            + Unpin,
+    ) -> Result<usize, Status> {
+        type Message = protos::stream_chunks_request::Message;
+
+        let mut document: Option<(Namespace, DocumentID, WorkerID)> = None;
+        let mut count = 0;
+
+        while let Some(request) = stream.next().await {
+            let (namespace, document_id, worker_id, chunks) =
+                match request?.message {
+                    Some(Message::Batch(batch)) => (
+                        batch.namespace,
+                        batch.document_id,
+                        batch.worker_id,
+                        Some(batch.chunks),
+                    ),
+                    Some(Message::Commit(commit)) => (
+                        commit.namespace,
+                        commit.document_id,
+                        commit.worker_id,
+                        None,
+                    ),
+                    None => {
+                        return Err(Status::invalid_argument(
+                            "The stream message must be a batch or a commit.",
+                        ));
+                    },
+                };
+
+            // Every message in the stream must belong to the same document
+            // and come from the same worker.
+            let id = self.validate_uuid(&document_id)?;
+            let worker_id = self.validate_uuid(&worker_id)?;
+            let (namespace, id, worker_id) = match &document {
+                Some((ns, doc_id, worker))
+                    if ns.name == namespace
+                        && *doc_id == id
+                        && *worker == worker_id =>
+                {
+                    (ns, doc_id, worker)
+                },
+                Some(_) => {
+                    return Err(Status::invalid_argument(
+                        "The stream must only contain chunks of one document.",
+                    ));
+                },
+                None => {
+                    // Chunks staged by a previous attempt are discarded.
+                    let namespace = self.get_namespace(&namespace).await?;
+                    let mut tx = self.begin_transaction().await?;
+                    self.hold_lease(&mut tx, &id, &worker_id).await?;
+                    self.unstage_chunks(&mut *tx, &namespace, &id).await?;
+                    self.commit_transaction(tx).await?;
+
+                    let (ns, doc_id, worker) =
+                        document.insert((namespace, id, worker_id));
+                    (&*ns, &*doc_id, &*worker)
+                },
+            };
+
+            match chunks {
+                Some(chunks) => {
+                    let embeddings =
+                        self.embed_chunks(namespace, &chunks).await?;
+
+                    let mut tx = self.begin_transaction().await?;
+                    self.hold_lease(&mut tx, id, worker_id).await?;
+                    self.insert_chunks(
+                        &mut tx,
+                        &format!("{}.staged_chunks", namespace.schema()),
+                        id,
+                        &chunks,
+                        embeddings,
+                        count,
+                    )
+                    .await?;
+                    self.commit_transaction(tx).await?;
+                    count += chunks.len();
+                },
+                None => {
+                    let mut tx = self.begin_transaction().await?;
+                    self.hold_lease(&mut tx, id, worker_id).await?;
+                    self.remove_chunks(&mut tx, namespace, id).await?;
+                    self.promote_chunks(&mut tx, namespace, id).await?;
+                    self.complete_document(&mut tx, namespace, id, worker_id)
+                        .await?;
+                    self.commit_transaction(tx).await?;
+                    return Ok(count);
+                },
+            }
+        }
+
+        Err(Status::aborted(
+            "The stream ended without a commit message.",
+        ))
+    }
+
+    /// Checks that the worker holds the lease of the document.
+    ///
+    /// The lease is locked until the transaction ends so the task can't be
+    /// handed to another worker while the chunks of this worker are written.
+    async fn hold_lease(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        document_id: &DocumentID,
+        worker_id: &WorkerID,
+    ) -> Result<(), Status> {
+        let holder: Option<WorkerID> = sqlx::query_scalar(
+            "SELECT worker_id FROM leases
+            WHERE document_id = $1
+            FOR SHARE;",
+        )
+        .bind(document_id)
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to check the lease: {_e:?}");
+            Status::internal("Failed to check the lease.")
+        })?;
+
+        match holder {
+            Some(holder) if holder == *worker_id => Ok(()),
+            _ => Err(Status::failed_precondition(
+                "The worker doesn't hold the lease of the document.",
+            )),
+        }
+    }
+
+    async fn begin_transaction(
+        &self,
+    ) -> Result<Transaction<'static, Postgres>, Status> {
+        self.database.begin().await.map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to start a transaction: {_e:?}");
+            Status::internal("Failed to start a transaction.")
+        })
+    }
+
+    async fn commit_transaction(
+        &self,
+        tx: Transaction<'_, Postgres>,
+    ) -> Result<(), Status> {
+        tx.commit().await.map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to commit the transaction: {_e:?}");
+            Status::internal("Failed to commit the transaction.")
+        })
+    }
+
+    /// Removes all chunks of a document within the transaction.
+    async fn remove_chunks(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        namespace: &Namespace,
+        document_id: &DocumentID,
+    ) -> Result<(), Status> {
         let schema = namespace.schema();
-        let language = "english";
-        for (chunk, embedding) in request.chunks.iter().zip(embeddings) {
+        sqlx::query(&format!(
+            "DELETE FROM {schema}.chunks
+            WHERE document_id = $1;",
+        ))
+        .bind(document_id)
+        .execute(&mut **tx)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to remove the chunks: {_e:?}");
+            Status::internal("Failed to remove the chunks.")
+        })?;
+
+        Ok(())
+    }
+
+    /// Removes the staged chunks of a document.
+    async fn unstage_chunks(
+        &self,
+        executor: impl Executor<'_, Database = Postgres>,
+        namespace: &Namespace,
+        document_id: &DocumentID,
+    ) -> Result<(), Status> {
+        let schema = namespace.schema();
+        sqlx::query(&format!(
+            "DELETE FROM {schema}.staged_chunks
+            WHERE document_id = $1;",
+        ))
+        .bind(document_id)
+        .execute(executor)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to remove the staged chunks: {_e:?}");
+            Status::internal("Failed to remove the staged chunks.")
+        })?;
+
+        Ok(())
+    }
+
+    /// Moves the staged chunks of a document to the chunks within the
+    /// transaction.
+    async fn promote_chunks(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        namespace: &Namespace,
+        document_id: &DocumentID,
+    ) -> Result<(), Status> {
+        let schema = namespace.schema();
+        let columns = "document_id, page, page_end, content, section_path, \
+            element_type, bounding_boxes, metadata, semantic_vector, \
+            text_vector, sequence";
+
+        sqlx::query(&format!(
+            "INSERT INTO {schema}.chunks ({columns})
+            SELECT {columns} FROM {schema}.staged_chunks
+            WHERE document_id = $1;",
+        ))
+        .bind(document_id)
+        .execute(&mut **tx)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to promote the staged chunks: {_e:?}");
+            Status::internal("Failed to promote the staged chunks.")
+        })?;
+
+        self.unstage_chunks(&mut **tx, namespace, document_id).await
+    }
+
+    /// Generates the embeddings of the chunks with the namespace model.
+    async fn embed_chunks(
+        &self,
+        namespace: &Namespace,
+        chunks: &[protos::Chunk],
+    ) -> Result<Vec<DenseVector>, Status> {
+        let model = namespace.config.embedding.model()?;
+        let texts: Vec<String> =
+            chunks.iter().map(|chunk| chunk.content.clone()).collect();
+
+        Ok(model.generate_batch(&texts).await?)
+    }
+
+    /// Inserts the embedded chunks of a document within the transaction.
+    /// - table: Qualified table to insert the chunks into.
+    /// - offset: Sequence of the first chunk within the document.
+    ///
+    /// The existing chunks of the document must be removed beforehand since
+    /// the sequences of the chunks are unique within the document.
+    async fn insert_chunks(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        table: &str,
+        document_id: &DocumentID,
+        chunks: &[protos::Chunk],
+        embeddings: Vec<DenseVector>,
+        offset: usize,
+    ) -> Result<(), Status> {
+        for (i, (chunk, embedding)) in chunks.iter().zip(embeddings).enumerate()
+        {
+            let boxes: Vec<BoundingBox> =
+                chunk.bounding_boxes.iter().map(BoundingBox::from).collect();
+
+            let metadata: Value = match chunk.metadata.is_empty() {
+                true => Value::Object(Default::default()),
+                false => serde_json::from_str(&chunk.metadata)
+                    .ok()
+                    .filter(Value::is_object)
+                    .ok_or_else(|| {
+                        Status::invalid_argument(
+                            "The chunk metadata must be a JSON object.",
+                        )
+                    })?,
+            };
+
             sqlx::query(&format!(
-                "INSERT INTO {schema}.chunks
-                (document_id, page, content, semantic_vector, text_vector) VALUES ($1, $2, $3, $4, to_tsvector('{language}', $5));",
+                "INSERT INTO {table} (
+                    document_id,
+                    page,
+                    page_end,
+                    content,
+                    section_path,
+                    element_type,
+                    bounding_boxes,
+                    metadata,
+                    semantic_vector,
+                    text_vector,
+                    sequence
+                )
+                VALUES (
+                    $1, $2, $3, $4, $5, $6, $7, $8, $9,
+                    to_tsvector('{LANGUAGE}', $4), $10
+                );",
             ))
             .bind(document_id)
             .bind(chunk.page as i32)
+            .bind(chunk.page_end.map(|page| page as i32))
             .bind(&chunk.content)
+            .bind(&chunk.section_path)
+            .bind(ElementType::from(chunk.element_type()))
+            .bind(sqlx::types::Json(boxes))
+            .bind(metadata)
             .bind(embedding)
-            .bind(&chunk.content)
-            .execute(&mut *tx)
+            .bind((offset + i) as i32)
+            .execute(&mut **tx)
             .await
             .map_err(|_e| {
                 #[cfg(test)]
@@ -93,28 +595,69 @@ impl Coordinator for Arc<Service> {
             })?;
         }
 
+        Ok(())
+    }
+
+    /// Marks the document as completed within the transaction.
+    async fn complete_document(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        namespace: &Namespace,
+        document_id: &DocumentID,
+        worker_id: &WorkerID,
+    ) -> Result<(), Status> {
+        let schema = namespace.schema();
         sqlx::query(&format!(
             "UPDATE {schema}.documents
-            SET status = $2, updated_at = NOW()
+            SET status = $2,
+            error_code = NULL,
+            error_message = NULL,
+            failed_at = NULL,
+            updated_at = NOW()
             WHERE id = $1;",
         ))
         .bind(document_id)
         .bind(DocumentStatus::Completed)
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|_e| {
             #[cfg(test)]
-            eprintln!("Failed to create a chunk: {_e:?}");
-            Status::internal("Failed to create a chunk.")
+            eprintln!("Failed to complete the document: {_e:?}");
+            Status::internal("Failed to complete the document.")
         })?;
 
-        tx.commit().await.map_err(|_e| {
+        self.release_lease(&mut **tx, document_id, worker_id).await
+    }
+
+    /// Releases the lease of the document once it's processed.
+    ///
+    /// Only the worker holding the lease can release it.
+    async fn release_lease(
+        &self,
+        executor: impl Executor<'_, Database = Postgres>,
+        document_id: &DocumentID,
+        worker_id: &WorkerID,
+    ) -> Result<(), Status> {
+        let result = sqlx::query(
+            "DELETE FROM leases
+            WHERE document_id = $1 AND worker_id = $2;",
+        )
+        .bind(document_id)
+        .bind(worker_id)
+        .execute(executor)
+        .await
+        .map_err(|_e| {
             #[cfg(test)]
-            eprintln!("Failed to commit the transaction: {_e:?}");
-            Status::internal("Failed to commit the transaction.")
+            eprintln!("Failed to release the lease: {_e:?}");
+            Status::internal("Failed to release the lease.")
         })?;
 
-        Ok(Response::new(()))
+        match result.rows_affected() {
+            0 => Err(Status::failed_precondition(
+                "The worker doesn't hold the lease of the document.",
+            )),
+            _ => Ok(()),
+        }
     }
 }
 
@@ -123,6 +666,9 @@ impl From<ErrorResponse> for Status {
         let message = error.message;
         match error.code {
             StatusCode::NOT_FOUND => Status::not_found(message),
+            StatusCode::CONFLICT => Status::failed_precondition(message),
+            StatusCode::FORBIDDEN => Status::permission_denied(message),
+            StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
             StatusCode::INTERNAL_SERVER_ERROR => Status::internal(message),
             _ => Status::invalid_argument(message),
         }
@@ -132,28 +678,269 @@ impl From<ErrorResponse> for Status {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils;
     use dotenv::dotenv;
+    use protos::coordinator_client::CoordinatorClient;
+    use protos::coordinator_server::CoordinatorServer;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use std::path::PathBuf;
+    use std::{env, fs};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+    use tonic::transport::{Identity, Server};
+    use tonic::Code;
     use uuid::Uuid;
 
     #[tokio::test]
     async fn test_heartbeat() {
         let service = setup().await;
-        let request = Request::new(());
+        let id = Uuid::new_v4();
+        let heartbeat = heartbeat_request(id);
+
+        // Unknown workers are asked to register.
+        let request = Request::new(heartbeat.clone());
         let response = service.heartbeat(request).await.unwrap();
         assert_eq!(response.get_ref().version, env!("CARGO_PKG_VERSION"));
+        assert!(!response.get_ref().registered);
+
+        let request = Request::new(protos::RegisterWorkerRequest {
+            id: id.to_string(),
+            address: "[::]:2510".to_string(),
+        });
+
+        service.register_worker(request).await.unwrap();
+
+        let request = Request::new(heartbeat);
+        let response = service.heartbeat(request).await.unwrap();
+        assert!(response.get_ref().registered);
+        assert!(!response.get_ref().draining);
+
+        let worker = fetch_worker(&service, id).await.unwrap();
+        assert_eq!(worker.status, WorkerStatus::Busy);
+        assert_eq!(worker.completed, 3);
+        assert!(worker.task.is_some());
+
+        // Draining workers are notified with the heartbeat response.
+        service.drain_worker(&id).await.unwrap();
+        let request = Request::new(heartbeat_request(id));
+        let response = service.heartbeat(request).await.unwrap();
+        assert!(response.get_ref().draining);
+        cleanup_worker(&service, id).await;
+    }
+
+    #[tokio::test]
+    async fn test_worker_interceptor() {
+        let service = setup().await;
+        let mut interceptor = service.worker_interceptor();
+
+        let request = worker_request((), "Bearer workertoken");
+        assert!(interceptor(request).is_ok());
+
+        let request = worker_request((), "Bearer secretkey");
+        let status = interceptor(request).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let status = interceptor(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_worker_mutual_tls() {
+        let service = setup().await;
+        let certs = setup_certificates();
+        let tls = utils::coordinator_tls_config(
+            certs.join("server.pem"),
+            certs.join("server.key"),
+            Some(certs.join("ca.pem")),
+        )
+        .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}", listener.local_addr().unwrap());
+
+        let interceptor = service.worker_interceptor();
+        let server = Server::builder()
+            .tls_config(tls)
+            .unwrap()
+            .add_service(CoordinatorServer::with_interceptor(
+                service,
+                interceptor,
+            ))
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        tokio::spawn(server);
+
+        let ca = Certificate::from_pem(fs::read(certs.join("ca.pem")).unwrap());
+        let identity = Identity::from_pem(
+            fs::read(certs.join("worker.pem")).unwrap(),
+            fs::read(certs.join("worker.key")).unwrap(),
+        );
+
+        let tls = ClientTlsConfig::new()
+            .domain_name("localhost")
+            .ca_certificate(ca);
+
+        // Workers with a client certificate and a token are accepted.
+        let channel = Channel::from_shared(url.clone())
+            .unwrap()
+            .tls_config(tls.clone().identity(identity))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+
+        let heartbeat = protos::HeartbeatRequest {
+            worker_id: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+
+        let mut client = CoordinatorClient::new(channel);
+        let request = worker_request(heartbeat.clone(), "Bearer workertoken");
+        assert!(client.heartbeat(request).await.is_ok());
+
+        let request = Request::new(heartbeat.clone());
+        let status = client.heartbeat(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        // Workers without a client certificate are rejected.
+        let channel = Channel::from_shared(url).unwrap().tls_config(tls);
+        let result = match channel.unwrap().connect().await {
+            Ok(channel) => {
+                let mut client = CoordinatorClient::new(channel);
+                let token = "Bearer workertoken";
+                let request = worker_request(heartbeat, token);
+                client.heartbeat(request).await.map(|_| ())
+            },
+            Err(error) => Err(Status::unavailable(error.to_string())),
+        };
+
+        assert!(result.is_err());
+        fs::remove_dir_all(certs).unwrap();
     }
 
     #[tokio::test]
     async fn test_register_worker() {
         let service = setup().await;
+        let id = Uuid::new_v4();
         let request = Request::new(protos::RegisterWorkerRequest {
-            id: Uuid::new_v4().to_string(),
+            id: id.to_string(),
             address: "[::]:2510".to_string(),
         });
 
         service.register_worker(request).await.unwrap();
-        let workers = service.workers().await;
-        assert_eq!(workers.len(), 1);
+        let worker = fetch_worker(&service, id).await.unwrap();
+        assert_eq!(worker.status, WorkerStatus::Idle);
+        cleanup_worker(&service, id).await;
+    }
+
+    #[tokio::test]
+    async fn test_expire_workers() {
+        let service = setup().await;
+        let id = Uuid::new_v4();
+        let request = Request::new(protos::RegisterWorkerRequest {
+            id: id.to_string(),
+            address: "[::]:2510".to_string(),
+        });
+
+        service.register_worker(request).await.unwrap();
+        let expired = service.expire_workers().await.unwrap();
+        assert!(!expired.contains(&id));
+
+        sqlx::query(
+            "UPDATE workers
+            SET last_seen_at = now() - interval '5 minutes'
+            WHERE id = $1;",
+        )
+        .bind(id)
+        .execute(&service.database)
+        .await
+        .unwrap();
+
+        let expired = service.expire_workers().await.unwrap();
+        assert!(expired.contains(&id));
+
+        assert!(fetch_worker(&service, id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_claim_task() {
+        let service = setup().await;
+        let namespace = setup_namespace(service.clone()).await;
+        let worker_id = Uuid::new_v4();
+        let request = Request::new(protos::ClaimTaskRequest {
+            worker_id: worker_id.to_string(),
+        });
+
+        // Unknown workers can't claim tasks.
+        let status = service.claim_task(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let request = Request::new(protos::RegisterWorkerRequest {
+            id: worker_id.to_string(),
+            address: "[::]:2510".to_string(),
+        });
+
+        service.register_worker(request).await.unwrap();
+
+        let metadata = serde_json::json!({});
+        let document = service
+            .create_document(&namespace, &metadata)
+            .await
+            .unwrap();
+
+        let task = ExtractionTask {
+            namespace: namespace.name.clone(),
+            document_key: document.key(&namespace),
+            document_id: document.id,
+            priority: document.priority,
+        };
+
+        service.queue.publish(&task).await.unwrap();
+
+        let request = Request::new(protos::ClaimTaskRequest {
+            worker_id: worker_id.to_string(),
+        });
+
+        let response = service.claim_task(request).await.unwrap();
+        let task = response.into_inner().task.unwrap();
+        let document_id = Uuid::parse_str(&task.document_id).unwrap();
+
+        // Only the worker holding the lease can renew it.
+        service
+            .extend_lease(&worker_id, &document_id)
+            .await
+            .unwrap();
+        let other_id = Uuid::new_v4();
+        let result = service.extend_lease(&other_id, &document_id).await;
+        assert!(result.is_err());
+
+        // Expired leases are requeued and can't be renewed anymore.
+        sqlx::query(
+            "UPDATE leases
+            SET expires_at = now() - interval '1 minute'
+            WHERE document_id = $1;",
+        )
+        .bind(document_id)
+        .execute(&service.database)
+        .await
+        .unwrap();
+
+        let leases = service.requeue_expired_leases().await.unwrap();
+        assert!(leases.iter().any(|l| l.task.document_id == document_id));
+
+        // The task is retried through the outbox.
+        let query = "SELECT attempts FROM outbox WHERE document_id = $1;";
+        let attempts: i32 = sqlx::query_scalar(query)
+            .bind(document_id)
+            .fetch_one(&service.database)
+            .await
+            .unwrap();
+
+        assert!(attempts > 0);
+
+        let result = service.extend_lease(&worker_id, &document_id).await;
+        assert!(result.is_err());
+        cleanup_worker(&service, worker_id).await;
     }
 
     #[tokio::test]
@@ -167,25 +954,210 @@ mod tests {
             .await
             .unwrap();
 
-        let request = Request::new(protos::UpdateDocumentRequest {
+        let worker_id = setup_lease(&service, &namespace, &document).await;
+        let request = protos::UpdateDocumentRequest {
             namespace: namespace.name.clone(),
             document_id: document.id.to_string(),
             status: protos::DocumentStatus::Processing as i32,
-        });
+            properties: Some(protos::DocumentProperties {
+                title: Some("DocuLens".to_string()),
+                page_count: Some(12),
+                ..Default::default()
+            }),
+            error: None,
+            worker_id: worker_id.to_string(),
+        };
 
-        service.update_document(request).await.unwrap();
+        // Workers that don't hold the lease can't update the document.
+        let stale = protos::UpdateDocumentRequest {
+            worker_id: Uuid::new_v4().to_string(),
+            ..request.clone()
+        };
+
+        let result = service.update_document(Request::new(stale)).await;
+        assert_eq!(result.unwrap_err().code(), Code::FailedPrecondition);
+
+        service
+            .update_document(Request::new(request))
+            .await
+            .unwrap();
 
         let schema = namespace.schema();
         let _document: Document = sqlx::query_as(&format!(
             "SELECT * FROM {schema}.documents
             WHERE id = $1;",
         ))
-        .bind(&document.id)
+        .bind(document.id)
         .fetch_one(&service.database)
         .await
         .unwrap();
 
         assert_eq!(_document.status, DocumentStatus::Processing);
+        assert_eq!(_document.properties.title.as_deref(), Some("DocuLens"));
+        assert_eq!(_document.properties.page_count, Some(12));
+        assert_eq!(_document.properties.author, None);
+        assert_eq!(_document.attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_document_failed() {
+        let service = setup().await;
+        let namespace = setup_namespace(service.clone()).await;
+
+        let metadata = serde_json::json!({});
+        let document = service
+            .create_document(&namespace, &metadata)
+            .await
+            .unwrap();
+
+        let fail = |worker_id: WorkerID| {
+            Request::new(protos::UpdateDocumentRequest {
+                namespace: namespace.name.clone(),
+                document_id: document.id.to_string(),
+                status: protos::DocumentStatus::Failed as i32,
+                error: Some(protos::DocumentError {
+                    code: "extraction_failed".to_string(),
+                    message: "The document is encrypted.".to_string(),
+                }),
+                worker_id: worker_id.to_string(),
+                ..Default::default()
+            })
+        };
+
+        // Failures are retried while the document has attempts left.
+        let worker_id = setup_lease(&service, &namespace, &document).await;
+        service.update_document(fail(worker_id)).await.unwrap();
+
+        let id = document.id;
+        let retried = service.get_document(&namespace, &id).await.unwrap();
+        assert_eq!(retried.status, DocumentStatus::Pending);
+        assert!(service.get_dead_letter(&id).await.is_err());
+
+        // The failed task is dead-lettered with the reason.
+        let schema = namespace.schema();
+        sqlx::query(&format!(
+            "UPDATE {schema}.documents SET attempts = $2 WHERE id = $1;"
+        ))
+        .bind(id)
+        .bind(service.config.max_attempts as i32)
+        .execute(&service.database)
+        .await
+        .unwrap();
+
+        let worker_id = setup_lease(&service, &namespace, &document).await;
+        service.update_document(fail(worker_id)).await.unwrap();
+
+        let failed = service.get_document(&namespace, &id).await.unwrap();
+        assert_eq!(failed.status, DocumentStatus::Failed);
+
+        let error = failed.error.unwrap();
+        assert_eq!(error.code, "extraction_failed");
+        assert_eq!(error.message, "The document is encrypted.");
+
+        let letter = service.get_dead_letter(&id).await.unwrap();
+        assert_eq!(letter.error.unwrap().code, "extraction_failed");
+    }
+
+    #[tokio::test]
+    async fn test_relay_outbox() {
+        let service = setup().await;
+        let namespace = setup_namespace(service.clone()).await;
+
+        let id = Uuid::new_v4();
+        let metadata = serde_json::json!({});
+        service
+            .insert_document(&namespace, &id, &metadata, 0)
+            .await
+            .unwrap();
+
+        // The task is kept in the outbox until it's published.
+        let query = "SELECT COUNT(*) FROM outbox WHERE document_id = $1;";
+        let count: i64 = sqlx::query_scalar(query)
+            .bind(id)
+            .fetch_one(&service.database)
+            .await
+            .unwrap();
+
+        assert_eq!(count, 1);
+
+        // Tasks claimed by another relay are skipped until the claim expires.
+        let claim = "UPDATE outbox
+            SET claimed_until = now() + make_interval(secs => $2)
+            WHERE document_id = $1;";
+
+        for (seconds, relayed) in [(60.0, 0), (-1.0, 1)] {
+            sqlx::query(claim)
+                .bind(id)
+                .bind(seconds)
+                .execute(&service.database)
+                .await
+                .unwrap();
+
+            let result = service.relay_outbox(Some(&id)).await.unwrap();
+            assert_eq!(result, relayed);
+        }
+
+        assert_eq!(service.relay_outbox(Some(&id)).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_recover_stuck_documents() {
+        let service = setup().await;
+        let namespace = setup_namespace(service.clone()).await;
+
+        let metadata = serde_json::json!({});
+        let document = service
+            .create_document(&namespace, &metadata)
+            .await
+            .unwrap();
+
+        let worker_id = setup_lease(&service, &namespace, &document).await;
+        let request = Request::new(protos::UpdateDocumentRequest {
+            namespace: namespace.name.clone(),
+            document_id: document.id.to_string(),
+            status: protos::DocumentStatus::Processing as i32,
+            worker_id: worker_id.to_string(),
+            ..Default::default()
+        });
+
+        service.update_document(request).await.unwrap();
+
+        // Documents stuck in processing are requeued.
+        let schema = namespace.schema();
+        let stuck = format!(
+            "UPDATE {schema}.documents
+            SET status = 'processing',
+            attempts = $2,
+            updated_at = NOW() - interval '2 hours'
+            WHERE id = $1;"
+        );
+
+        sqlx::query(&stuck)
+            .bind(document.id)
+            .bind(1)
+            .execute(&service.database)
+            .await
+            .unwrap();
+
+        let documents = service.recover_stuck_documents().await.unwrap();
+        let recovered = documents.iter().find(|d| d.id == document.id);
+        assert_eq!(recovered.unwrap().status, DocumentStatus::Pending);
+
+        // Documents that reached the maximum attempts are failed instead.
+        sqlx::query(&stuck)
+            .bind(document.id)
+            .bind(service.config.max_attempts as i32)
+            .execute(&service.database)
+            .await
+            .unwrap();
+
+        let documents = service.recover_stuck_documents().await.unwrap();
+        let recovered = documents.iter().find(|d| d.id == document.id);
+        let recovered = recovered.unwrap();
+        assert_eq!(recovered.status, DocumentStatus::Failed);
+
+        let error = recovered.error.as_ref().unwrap();
+        assert_eq!(error.code, "processing_timeout");
     }
 
     #[tokio::test]
@@ -199,29 +1171,160 @@ mod tests {
             .await
             .unwrap();
 
-        let request = Request::new(protos::CreateChunkRequest {
+        let mut request = protos::CreateChunkRequest {
             namespace: namespace.name.clone(),
             document_id: document.id.to_string(),
+            worker_id: String::new(),
             chunks: vec![protos::Chunk {
                 page: 1,
+                page_end: Some(2),
                 content: "DocuLens is a robust search API platform for PDFs."
                     .to_string(),
+                section_path: vec!["Introduction".to_string()],
+                element_type: protos::ElementType::Heading as i32,
+                bounding_boxes: vec![protos::BoundingBox {
+                    page: 1,
+                    left: 72.0,
+                    top: 96.0,
+                    right: 540.0,
+                    bottom: 120.0,
+                }],
+                metadata: r#"{"label": "title"}"#.to_string(),
             }],
-        });
+        };
 
-        service.create_chunk(request).await.unwrap();
+        // Retrying the request replaces the chunks instead of appending.
+        // Each attempt is made by the worker holding the lease at the time.
+        for _ in 0..2 {
+            let worker_id = setup_lease(&service, &namespace, &document).await;
+            request.worker_id = worker_id.to_string();
+
+            let request = Request::new(request.clone());
+            service.create_chunk(request).await.unwrap();
+        }
 
         let schema = namespace.schema();
         let chunks: Vec<Chunk> = sqlx::query_as(&format!(
-            "SELECT id, document_id, page, content FROM {schema}.chunks
+            "SELECT {} FROM {schema}.chunks
             WHERE document_id = $1;",
+            Chunk::COLUMNS,
         ))
-        .bind(&document.id)
+        .bind(document.id)
         .fetch_all(&service.database)
         .await
         .unwrap();
 
         assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].element_type, ElementType::Heading);
+        assert_eq!(chunks[0].bounding_boxes[0].right, 540.0);
+        assert_eq!(chunks[0].metadata["label"], "title");
+    }
+
+    #[tokio::test]
+    async fn test_receive_chunks() {
+        type Message = protos::stream_chunks_request::Message;
+
+        let service = setup().await;
+        let namespace = setup_namespace(service.clone()).await;
+
+        let metadata = serde_json::json!({});
+        let document = service
+            .create_document(&namespace, &metadata)
+            .await
+            .unwrap();
+
+        let worker_id = setup_lease(&service, &namespace, &document).await;
+        let batch =
+            |content: &str, worker_id: WorkerID| protos::StreamChunksRequest {
+                message: Some(Message::Batch(protos::ChunkBatch {
+                    namespace: namespace.name.clone(),
+                    document_id: document.id.to_string(),
+                    chunks: vec![protos::Chunk {
+                        page: 1,
+                        content: content.to_string(),
+                        ..Default::default()
+                    }],
+                    worker_id: worker_id.to_string(),
+                })),
+            };
+
+        let commit = protos::StreamChunksRequest {
+            message: Some(Message::Commit(protos::ChunkCommit {
+                namespace: namespace.name.clone(),
+                document_id: document.id.to_string(),
+                worker_id: worker_id.to_string(),
+            })),
+        };
+
+        // Workers that don't hold the lease can't stream chunks.
+        let stale = batch("Apples are red.", Uuid::new_v4());
+        let stream = tokio_stream::iter(vec![Ok(stale)]);
+        let status = service.receive_chunks(stream).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        // The chunks are discarded when the stream ends without a commit.
+        let stream = tokio_stream::iter(vec![Ok(batch(
+            "Bananas are yellow.",
+            worker_id,
+        ))]);
+        assert!(service.receive_chunks(stream).await.is_err());
+
+        let stream = tokio_stream::iter(vec![
+            Ok(batch("Bananas are packed with potassium.", worker_id)),
+            Ok(batch("Oranges are full of vitamin C.", worker_id)),
+            Ok(commit),
+        ]);
+
+        let count = service.receive_chunks(stream).await.unwrap();
+        assert_eq!(count, 2);
+
+        let schema = namespace.schema();
+        let _document: Document = sqlx::query_as(&format!(
+            "SELECT * FROM {schema}.documents
+            WHERE id = $1;",
+        ))
+        .bind(document.id)
+        .fetch_one(&service.database)
+        .await
+        .unwrap();
+
+        assert_eq!(_document.status, DocumentStatus::Completed);
+
+        // Only the committed chunks are kept and nothing remains staged.
+        let counts: (i64, i64) = sqlx::query_as(&format!(
+            "SELECT
+                (SELECT COUNT(*) FROM {schema}.chunks WHERE document_id = $1),
+                (SELECT COUNT(*) FROM {schema}.staged_chunks
+                WHERE document_id = $1);",
+        ))
+        .bind(document.id)
+        .fetch_one(&service.database)
+        .await
+        .unwrap();
+
+        assert_eq!(counts, (2, 0));
+    }
+
+    #[test]
+    fn test_status_from_error_response() {
+        let cases = [
+            (StatusCode::NOT_FOUND, Code::NotFound),
+            (StatusCode::CONFLICT, Code::FailedPrecondition),
+            (StatusCode::FORBIDDEN, Code::PermissionDenied),
+            (StatusCode::SERVICE_UNAVAILABLE, Code::Unavailable),
+            (StatusCode::INTERNAL_SERVER_ERROR, Code::Internal),
+            (StatusCode::BAD_REQUEST, Code::InvalidArgument),
+        ];
+
+        for (code, expected) in cases {
+            let error = ErrorResponse {
+                code,
+                message: String::from("Error"),
+                solution: None,
+            };
+
+            assert_eq!(Status::from(error).code(), expected);
+        }
     }
 
     async fn setup() -> Arc<Service> {
@@ -230,6 +1333,87 @@ mod tests {
         Arc::new(Service::new(&config).await)
     }
 
+    async fn setup_lease(
+        service: &Arc<Service>,
+        namespace: &Namespace,
+        document: &Document,
+    ) -> WorkerID {
+        let worker_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO leases (
+                document_id, namespace, document_key, worker_id, expires_at
+            )
+            VALUES ($1, $2, $3, $4, now() + interval '1 minute');",
+        )
+        .bind(document.id)
+        .bind(&namespace.name)
+        .bind(document.key(namespace))
+        .bind(worker_id)
+        .execute(&service.database)
+        .await
+        .unwrap();
+
+        worker_id
+    }
+
+    fn worker_request<T>(message: T, authorization: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        let value = authorization.parse().unwrap();
+        request.metadata_mut().insert("authorization", value);
+        request
+    }
+
+    /// Generates a CA with a server and a worker certificate signed by it.
+    fn setup_certificates() -> PathBuf {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let id = Uuid::new_v4().simple().to_string();
+        let dir = env::temp_dir().join(format!("dl-certs-{id}"));
+        fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        for (name, subject) in [("server", "localhost"), ("worker", "worker")] {
+            let key = KeyPair::generate().unwrap();
+            let params = CertificateParams::new(vec![subject.into()]).unwrap();
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            fs::write(dir.join(format!("{name}.pem")), cert.pem()).unwrap();
+            fs::write(dir.join(format!("{name}.key")), key.serialize_pem())
+                .unwrap();
+        }
+
+        dir
+    }
+
+    async fn cleanup_worker(service: &Service, id: WorkerID) {
+        sqlx::query("DELETE FROM workers WHERE id = $1;")
+            .bind(id)
+            .execute(&service.database)
+            .await
+            .unwrap();
+    }
+
+    async fn fetch_worker(service: &Service, id: WorkerID) -> Option<Worker> {
+        let workers = service.workers().await.unwrap();
+        workers.into_iter().find(|worker| worker.id == id)
+    }
+
+    fn heartbeat_request(id: WorkerID) -> protos::HeartbeatRequest {
+        protos::HeartbeatRequest {
+            worker_id: id.to_string(),
+            status: protos::WorkerStatus::Busy as i32,
+            task: Some(protos::WorkerTask {
+                namespace: "coordinator_ns".to_string(),
+                document_id: Uuid::new_v4().to_string(),
+            }),
+            completed: 3,
+            failed: 1,
+        }
+    }
+
     async fn setup_namespace(service: Arc<Service>) -> Namespace {
         let name = "coordinator_ns";
         let config = NamespaceConfig::default();
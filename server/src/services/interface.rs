@@ -1,6 +1,6 @@
 use super::*;
 use axum::body::Body;
-use axum::extract::{DefaultBodyLimit, Json, Multipart, Path, State};
+use axum::extract::{DefaultBodyLimit, Json, Multipart, Path, Query, State};
 use axum::http::Response;
 use axum::response::IntoResponse;
 use axum::routing::{delete, get, post};
@@ -13,11 +13,32 @@ use serde_json::json;
 pub fn create_router(service: Arc<Service>) -> Router {
     Router::new()
         .route("/", get(heartbeat))
+        .route("/health", get(health))
+        .route("/workers", get(list_workers))
+        .route("/workers/:id", delete(remove_worker))
+        .route("/workers/:id/drain", post(drain_worker))
+        .route("/dead-letters", get(list_dead_letters))
+        .route("/dead-letters/:id", get(get_dead_letter))
+        .route("/dead-letters/:id/replay", post(replay_dead_letter))
         .route("/namespaces", post(create_namespace))
         .route("/namespaces/:name", delete(remove_namespace))
-        .route("/namespaces/:name/documents", post(upload_document))
-        .route("/namespaces/:name/documents/:id", delete(remove_document))
+        .route(
+            "/namespaces/:name/maintenance",
+            post(maintain_namespace).get(get_maintenance_progress),
+        )
+        .route(
+            "/namespaces/:name/documents",
+            post(upload_document).get(list_documents),
+        )
+        .route(
+            "/namespaces/:name/documents/:id",
+            delete(remove_document).get(get_document),
+        )
         .route("/namespaces/:name/queries", post(create_query))
+        .route(
+            "/namespaces/:name/queries/documents",
+            post(create_document_query),
+        )
         .layer(DefaultBodyLimit::max(64 * 1024 * 1024))
         .with_state(service)
 }
@@ -57,16 +78,51 @@ struct HeartbeatResponse {
     pub version: String,
 }
 
+#[derive(Serialize, Deserialize)]
+struct HealthResponse {
+    /// Whether the server is connected to the task queue.
+    pub queue: bool,
+}
+
 #[derive(Deserialize)]
 struct CreateNamespacePayload {
     pub name: String,
     pub config: Option<Value>,
 }
 
+#[derive(Deserialize)]
+struct ListDocumentsParams {
+    pub status: Option<DocumentStatus>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ListDeadLettersParams {
+    pub limit: Option<usize>,
+}
+
 #[derive(Deserialize)]
 struct CreateQueryPayload {
     pub query: String,
     pub k: Option<usize>,
+    pub cursor: Option<String>,
+    pub query_syntax: Option<QuerySyntax>,
+    pub prefix: Option<bool>,
+    pub fuzzy: Option<bool>,
+    pub ef_search: Option<u16>,
+    pub probes: Option<u32>,
+    pub iterative_scan: Option<IterativeScan>,
+    pub highlight: Option<HighlightOptions>,
+    pub filter: Option<ChunkFilter>,
+}
+
+#[derive(Deserialize)]
+struct CreateDocumentQueryPayload {
+    #[serde(flatten)]
+    pub query: CreateQueryPayload,
+    pub passages: Option<usize>,
+    pub aggregation: Option<ScoreAggregation>,
 }
 
 async fn heartbeat() -> SuccessResponse<HeartbeatResponse> {
@@ -78,6 +134,121 @@ async fn heartbeat() -> SuccessResponse<HeartbeatResponse> {
     }
 }
 
+async fn health(
+    State(service): State<Arc<Service>>,
+) -> SuccessResponse<HealthResponse> {
+    let queue = service.queue.is_connected().await;
+    let code = match queue {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    SuccessResponse {
+        code,
+        data: HealthResponse { queue },
+    }
+}
+
+async fn list_workers(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+) -> Result<SuccessResponse<Vec<Worker>>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let workers = service.workers().await?;
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: workers,
+    })
+}
+
+async fn drain_worker(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path(id): Path<String>,
+) -> Result<SuccessResponse<Worker>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let id = service.validate_uuid(&id)?;
+
+    let worker = service.drain_worker(&id).await?;
+    tracing::info!("WorkerDraining: {}", worker.id);
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: worker,
+    })
+}
+
+async fn remove_worker(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path(id): Path<String>,
+) -> Result<SuccessResponse<Worker>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let id = service.validate_uuid(&id)?;
+
+    let worker = service.remove_worker(&id).await?;
+    tracing::info!("WorkerRemoved: {}", worker.id);
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: worker,
+    })
+}
+
+async fn list_dead_letters(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Query(params): Query<ListDeadLettersParams>,
+) -> Result<SuccessResponse<Vec<DeadLetter>>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+
+    let limit = params.limit.unwrap_or(DEFAULT_DEAD_LETTER_LIMIT);
+    if !(1..=MAX_DEAD_LETTER_LIMIT).contains(&limit) {
+        return Err(ErrorResponse {
+            code: StatusCode::BAD_REQUEST,
+            message: format!(
+                "The limit must be between 1 and {MAX_DEAD_LETTER_LIMIT}."
+            ),
+            solution: None,
+        });
+    }
+
+    let letters = service.dead_letters(limit).await?;
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: letters,
+    })
+}
+
+async fn get_dead_letter(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path(id): Path<String>,
+) -> Result<SuccessResponse<DeadLetter>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let id = service.validate_uuid(&id)?;
+
+    let letter = service.get_dead_letter(&id).await?;
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: letter,
+    })
+}
+
+async fn replay_dead_letter(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path(id): Path<String>,
+) -> Result<SuccessResponse<DeadLetter>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let id = service.validate_uuid(&id)?;
+
+    let letter = service.replay_dead_letter(&id).await?;
+    tracing::info!("DeadLetterReplayed: {}", letter.task.document_id);
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: letter,
+    })
+}
+
 async fn create_namespace(
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
     State(service): State<Arc<Service>>,
@@ -111,8 +282,8 @@ async fn create_namespace(
             }
         })?;
 
-        // This validates the provided embedding model to be valid.
-        config.embedding.model()?;
+        // This validates the embedding model and the index to be valid.
+        config.validate()?;
     }
 
     let namespace = service.create_namespace(&payload.name, &config).await?;
@@ -142,6 +313,60 @@ async fn remove_namespace(
     })
 }
 
+async fn maintain_namespace(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path(namespace): Path<String>,
+    Json(maintenance): Json<Maintenance>,
+) -> Result<SuccessResponse<Maintenance>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let namespace = service.get_namespace(namespace).await?;
+
+    if let Maintenance::Rebuild { index } = &maintenance {
+        let mut config = namespace.config.clone();
+        config.index = index.clone();
+        config.validate()?;
+    }
+
+    // The lock is taken before responding so concurrent requests are
+    // rejected instead of rebuilding the same indexes at the same time.
+    let connection = service.lock_maintenance(&namespace).await?;
+
+    // The operation runs in the background and its progress can be tracked
+    // with the maintenance progress endpoint.
+    let operation = maintenance.clone();
+    tokio::spawn(async move {
+        tracing::info!("MaintenanceStarted: {operation:?}");
+        let maintenance =
+            service.maintain_namespace(connection, &namespace, &operation);
+
+        match maintenance.await {
+            Ok(_) => tracing::info!("MaintenanceCompleted: {operation:?}"),
+            Err(e) => tracing::error!("MaintenanceFailed: {}", e.message),
+        }
+    });
+
+    Ok(SuccessResponse {
+        code: StatusCode::ACCEPTED,
+        data: maintenance,
+    })
+}
+
+async fn get_maintenance_progress(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path(namespace): Path<String>,
+) -> Result<SuccessResponse<Vec<IndexProgress>>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let namespace = service.get_namespace(namespace).await?;
+
+    let progress = service.maintenance_progress(&namespace).await?;
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: progress,
+    })
+}
+
 async fn upload_document(
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
     State(service): State<Arc<Service>>,
@@ -153,6 +378,7 @@ async fn upload_document(
 
     let mut data: Vec<u8> = Vec::new();
     let mut metadata: Value = Value::Null;
+    let mut priority = 0;
 
     while let Ok(Some(field)) = multipart.next_field().await {
         if let Some(name) = field.name() {
@@ -165,6 +391,22 @@ async fn upload_document(
                         solution: None,
                     }
                 })?;
+            } else if name == "priority" {
+                let text = field.text().await.unwrap_or_default();
+                priority = match text.trim().parse() {
+                    Ok(priority) if (0..=MAX_PRIORITY).contains(&priority) => {
+                        priority
+                    },
+                    _ => {
+                        return Err(ErrorResponse {
+                            code: StatusCode::BAD_REQUEST,
+                            message: format!(
+                                "Priority must be between 0 and {MAX_PRIORITY}."
+                            ),
+                            solution: None,
+                        })
+                    },
+                };
             } else if name == "file" {
                 data = field.bytes().await.unwrap().to_vec();
             }
@@ -179,25 +421,66 @@ async fn upload_document(
         });
     }
 
-    let document = service.create_document(&namespace, &metadata).await?;
-    let key = document.key(&namespace);
-    service.storage.upload(&key, data).await?;
-    tracing::info!("DocumentCreated: {document:?}");
+    let document = service
+        .upload_document(&namespace, &metadata, priority, data)
+        .await?;
 
-    let task = ExtractionTask {
-        namespace: namespace.name,
-        document_id: document.id,
-        document_key: key,
-    };
+    tracing::info!("DocumentCreated: {document:?}");
+    Ok(SuccessResponse {
+        code: StatusCode::CREATED,
+        data: document,
+    })
+}
 
-    service.queue.publish(&task).await?;
+async fn get_document(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path((namespace, id)): Path<(String, String)>,
+) -> Result<SuccessResponse<Document>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let namespace = service.get_namespace(namespace).await?;
+    let id = service.validate_uuid(&id)?;
 
+    let document = service.get_document(&namespace, &id).await?;
     Ok(SuccessResponse {
-        code: StatusCode::CREATED,
+        code: StatusCode::OK,
         data: document,
     })
 }
 
+async fn list_documents(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path(namespace): Path<String>,
+    Query(params): Query<ListDocumentsParams>,
+) -> Result<SuccessResponse<Vec<Document>>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let namespace = service.get_namespace(namespace).await?;
+
+    let limit = params.limit.unwrap_or(DEFAULT_DOCUMENT_LIMIT);
+    if !(1..=MAX_DOCUMENT_LIMIT).contains(&limit) {
+        return Err(ErrorResponse {
+            code: StatusCode::BAD_REQUEST,
+            message: format!(
+                "The limit must be between 1 and {MAX_DOCUMENT_LIMIT}."
+            ),
+            solution: Some(String::from(
+                "Use the offset to list the following documents.",
+            )),
+        });
+    }
+
+    let offset = params.offset.unwrap_or(0);
+    let documents = service
+        .list_documents(&namespace, params.status, limit, offset)
+        .await?;
+
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: documents,
+    })
+}
+
 async fn remove_document(
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
     State(service): State<Arc<Service>>,
@@ -225,20 +508,99 @@ async fn create_query(
     State(service): State<Arc<Service>>,
     Path(namespace): Path<String>,
     Json(payload): Json<CreateQueryPayload>,
-) -> Result<SuccessResponse<Vec<Chunk>>, ErrorResponse> {
+) -> Result<SuccessResponse<QueryResults<Chunk>>, ErrorResponse> {
+    service.validate_secret(bearer.token())?;
+    let namespace = service.get_namespace(namespace).await?;
+
+    let (options, cursor) =
+        query_options(&service, &namespace, &payload, CHUNK_SCOPE)?;
+    let results = service
+        .create_query(&namespace, payload.query, &options, cursor)
+        .await?;
+
+    Ok(SuccessResponse {
+        code: StatusCode::OK,
+        data: results,
+    })
+}
+
+async fn create_document_query(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    State(service): State<Arc<Service>>,
+    Path(namespace): Path<String>,
+    Json(payload): Json<CreateDocumentQueryPayload>,
+) -> Result<SuccessResponse<QueryResults<DocumentResult>>, ErrorResponse> {
     service.validate_secret(bearer.token())?;
     let namespace = service.get_namespace(namespace).await?;
 
-    let CreateQueryPayload { query, k } = payload;
-    let k = k.unwrap_or(10);
+    let default = DocumentQueryOptions::default();
+    let document_options = DocumentQueryOptions {
+        passages: payload.passages.unwrap_or(default.passages),
+        aggregation: payload.aggregation.unwrap_or(default.aggregation),
+    };
+
+    let scope = document_scope(&document_options);
+    let (options, cursor) =
+        query_options(&service, &namespace, &payload.query, &scope)?;
+
+    let query = payload.query.query;
+    let results = service
+        .create_document_query(
+            &namespace,
+            query,
+            &options,
+            &document_options,
+            cursor,
+        )
+        .await?;
 
-    let results = service.create_query(&namespace, query, k as u8).await?;
     Ok(SuccessResponse {
         code: StatusCode::OK,
         data: results,
     })
 }
 
+/// Validates the query payload and returns the query options and cursor.
+fn query_options(
+    service: &Service,
+    namespace: &Namespace,
+    payload: &CreateQueryPayload,
+    scope: &str,
+) -> Result<(QueryOptions, Option<Cursor>), ErrorResponse> {
+    let default = QueryOptions::default();
+    let k = service.validate_k(payload.k.unwrap_or(default.k))?;
+
+    if let Some(highlight) = &payload.highlight {
+        highlight.validate()?;
+    }
+
+    if let Some(filter) = &payload.filter {
+        filter.validate()?;
+    }
+
+    let options = QueryOptions {
+        k,
+        syntax: payload.query_syntax.unwrap_or(default.syntax),
+        prefix: payload.prefix.unwrap_or(default.prefix),
+        fuzzy: payload.fuzzy.unwrap_or(default.fuzzy),
+        ef_search: payload.ef_search,
+        probes: payload.probes,
+        iterative_scan: payload.iterative_scan,
+        highlight: payload.highlight.clone(),
+        filter: payload.filter.clone().unwrap_or_default(),
+    };
+
+    let cursor = match &payload.cursor {
+        Some(token) => {
+            let digest = options.digest(&payload.query, scope);
+            Some(service.validate_cursor(namespace, token, &digest)?)
+        },
+        None => None,
+    };
+
+    Ok((options, cursor))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -262,6 +624,146 @@ mod tests {
         assert_eq!(response.data.version, env!("CARGO_PKG_VERSION"));
     }
 
+    #[tokio::test]
+    async fn test_health() {
+        let app = setup().await;
+        let response = app.get("/health").await;
+
+        response.assert_status_ok();
+        let health: HealthResponse = response.json();
+        assert!(health.queue);
+    }
+
+    #[tokio::test]
+    async fn test_list_workers() {
+        let (app, id) = setup_worker().await;
+        let response = app.get("/workers").authorization_bearer(BEARER).await;
+
+        let workers: Vec<Worker> = response.json();
+        let worker = workers.iter().find(|w| w.id == id).unwrap();
+        assert_eq!(worker.status, WorkerStatus::Idle);
+        assert!(!worker.draining);
+    }
+
+    #[tokio::test]
+    async fn test_drain_worker() {
+        let (app, id) = setup_worker().await;
+        let response = app
+            .post(&format!("/workers/{id}/drain"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        let worker: Worker = response.json();
+        assert!(worker.draining);
+
+        let id = Uuid::new_v4();
+        let response = app
+            .post(&format!("/workers/{id}/drain"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        response.assert_status_not_found();
+    }
+
+    #[tokio::test]
+    async fn test_remove_worker() {
+        dotenv().ok();
+
+        let config = Configuration::default();
+        let state = Arc::new(Service::new(&config).await);
+
+        let request = protos::RegisterWorkerRequest {
+            id: Uuid::new_v4().to_string(),
+            address: "[::]:2510".to_string(),
+        };
+
+        let worker = Worker::try_from(request).unwrap();
+        state.add_worker(&worker).await.unwrap();
+
+        // Workers that may still be running can't be removed.
+        let id = worker.id;
+        let app = TestServer::new(create_router(state.clone())).unwrap();
+        let response = app
+            .delete(&format!("/workers/{id}"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        response.assert_status(StatusCode::CONFLICT);
+
+        sqlx::query(
+            "UPDATE workers
+            SET last_seen_at = now() - interval '5 minutes'
+            WHERE id = $1;",
+        )
+        .bind(id)
+        .execute(&state.database)
+        .await
+        .unwrap();
+
+        let response = app
+            .delete(&format!("/workers/{id}"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        let worker: Worker = response.json();
+        assert_eq!(worker.id, id);
+
+        let response = app
+            .delete(&format!("/workers/{id}"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        response.assert_status_not_found();
+    }
+
+    #[tokio::test]
+    async fn test_list_dead_letters() {
+        let (app, id) = setup_dead_letter().await;
+        let response =
+            app.get("/dead-letters").authorization_bearer(BEARER).await;
+
+        let letters: Vec<DeadLetter> = response.json();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].task.document_id, id);
+
+        // The dead-lettered task is kept in the queue after listing.
+        let response = app
+            .get(&format!("/dead-letters/{id}"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        let letter: DeadLetter = response.json();
+        assert_eq!(letter.error.unwrap().code, "extraction_failed");
+    }
+
+    #[tokio::test]
+    async fn test_replay_dead_letter() {
+        let (app, id) = setup_dead_letter().await;
+        let response = app
+            .post(&format!("/dead-letters/{id}/replay"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        let letter: DeadLetter = response.json();
+        assert_eq!(letter.task.document_id, id);
+
+        let document: Document = app
+            .get(&format!("/namespaces/existing_ns/documents/{id}"))
+            .authorization_bearer(BEARER)
+            .await
+            .json();
+
+        assert_eq!(document.status, DocumentStatus::Pending);
+        assert_eq!(document.attempts, 0);
+
+        let response = app
+            .post(&format!("/dead-letters/{id}/replay"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        response.assert_status_not_found();
+    }
+
     #[tokio::test]
     async fn test_create_namespace() {
         let app = setup().await;
@@ -298,18 +800,129 @@ mod tests {
 
         let namespace: Namespace = response.json();
         assert_eq!(namespace.name, "test_ns");
-        assert_eq!(namespace.config.index.m, 16);
+        match namespace.config.index {
+            IndexConfig::Hnsw(config) => assert_eq!(config.m, 16),
+            _ => panic!("The index type should be HNSW."),
+        }
     }
 
     #[tokio::test]
-    async fn test_remove_namespace() {
+    async fn test_create_namespace_large_dimension() {
         let app = setup().await;
+        let mut payload = json!({
+            "name": "test_ns",
+            "config": {
+                "index": { "type": "IvfFlat", "lists": 10, "probes": 2 },
+                "embedding": {
+                    "provider": "OpenAI",
+                    "model": "text-embedding-3-large"
+                }
+            }
+        });
+
         let response = app
-            .delete("/namespaces/existing_ns")
+            .post("/namespaces")
             .authorization_bearer(BEARER)
+            .json(&payload)
             .await;
 
-        let namespace: Option<Namespace> = response.json();
+        response.assert_status_bad_request();
+
+        payload["config"]["vector"] = json!({
+            "storage": "Halfvec",
+            "metric": "InnerProduct",
+            "quantization": "Binary"
+        });
+
+        let response = app
+            .post("/namespaces")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        let namespace: Namespace = response.json();
+        assert!(matches!(namespace.config.index, IndexConfig::IvfFlat(_)));
+    }
+
+    #[tokio::test]
+    async fn test_maintain_namespace() {
+        let app = setup().await;
+        let payload = json!({
+            "operation": "Rebuild",
+            "index": { "type": "IvfFlat", "lists": 10, "probes": 20 }
+        });
+
+        let response = app
+            .post("/namespaces/existing_ns/maintenance")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        response.assert_status_bad_request();
+
+        let response = app
+            .post("/namespaces/existing_ns/maintenance")
+            .authorization_bearer(BEARER)
+            .json(&json!({ "operation": "Vacuum" }))
+            .await;
+
+        response.assert_status(StatusCode::ACCEPTED);
+
+        let response = app
+            .get("/namespaces/existing_ns/maintenance")
+            .authorization_bearer(BEARER)
+            .await;
+
+        response.assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_maintain_namespace_locked() {
+        dotenv().ok();
+
+        let config = Configuration::default();
+        let state = Arc::new(Service::new(&config).await);
+        teardown(state.clone()).await;
+
+        let namespace = state
+            .create_namespace("existing_ns", &NamespaceConfig::default())
+            .await
+            .unwrap();
+
+        let app = TestServer::new(create_router(state.clone())).unwrap();
+        let connection = state.lock_maintenance(&namespace).await.unwrap();
+
+        let response = app
+            .post("/namespaces/existing_ns/maintenance")
+            .authorization_bearer(BEARER)
+            .json(&json!({ "operation": "Vacuum" }))
+            .await;
+
+        response.assert_status(StatusCode::CONFLICT);
+
+        state
+            .maintain_namespace(connection, &namespace, &Maintenance::Vacuum)
+            .await
+            .unwrap();
+
+        let response = app
+            .post("/namespaces/existing_ns/maintenance")
+            .authorization_bearer(BEARER)
+            .json(&json!({ "operation": "Vacuum" }))
+            .await;
+
+        response.assert_status(StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn test_remove_namespace() {
+        let app = setup().await;
+        let response = app
+            .delete("/namespaces/existing_ns")
+            .authorization_bearer(BEARER)
+            .await;
+
+        let namespace: Option<Namespace> = response.json();
         assert_eq!(namespace.unwrap().name, "existing_ns");
     }
 
@@ -350,16 +963,18 @@ mod tests {
         // Check if after the document is uploaded, a task is queued up.
 
         let config = Configuration::default();
-        let url = config.queue_url.as_str();
+        let url = config.queue_url.unwrap();
         let properties = ConnectionProperties::default();
-        let connection = Connection::connect(url, properties).await.unwrap();
+        let connection =
+            Connection::connect(url.as_str(), properties).await.unwrap();
 
         let channel = connection.create_channel().await.unwrap();
 
+        let queue = format!("{QUEUE_NAME}.namespace.existing_ns");
         let options = BasicConsumeOptions::default();
         let table = FieldTable::default();
         let mut consumer = channel
-            .basic_consume(QUEUE_NAME, "consumer", options, table)
+            .basic_consume(&queue, "consumer", options, table)
             .await
             .unwrap();
 
@@ -368,6 +983,62 @@ mod tests {
         assert_eq!(document.id, task.document_id);
     }
 
+    #[tokio::test]
+    async fn test_upload_document_invalid_priority() {
+        let form = MultipartForm::new()
+            .add_text("priority", (MAX_PRIORITY + 1).to_string())
+            .add_part("file", Part::bytes(b"%PDF-1.4".to_vec()));
+
+        let app = setup().await;
+        let response = app
+            .post("/namespaces/existing_ns/documents")
+            .authorization_bearer(BEARER)
+            .multipart(form)
+            .await;
+
+        response.assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_list_documents() {
+        let app = setup_populated().await;
+        let documents: Vec<Document> = app
+            .get("/namespaces/existing_ns/documents")
+            .authorization_bearer(BEARER)
+            .await
+            .json();
+
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].status, DocumentStatus::Completed);
+
+        let documents: Vec<Document> = app
+            .get("/namespaces/existing_ns/documents?status=Failed")
+            .authorization_bearer(BEARER)
+            .await
+            .json();
+
+        assert!(documents.is_empty());
+
+        let response = app
+            .get("/namespaces/existing_ns/documents?limit=0")
+            .authorization_bearer(BEARER)
+            .await;
+
+        response.assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_get_document() {
+        let app = setup().await;
+        let id = Uuid::new_v4();
+        let response = app
+            .get(&format!("/namespaces/existing_ns/documents/{id}"))
+            .authorization_bearer(BEARER)
+            .await;
+
+        response.assert_status_not_found();
+    }
+
     #[tokio::test]
     async fn test_create_query() {
         let app = setup_populated().await;
@@ -378,10 +1049,318 @@ mod tests {
             .json(&payload)
             .await;
 
-        let chunks: Vec<Chunk> = response.json();
-        assert_eq!(chunks.len(), 2);
-        assert!(chunks[0].content.contains("Bananas"));
-        assert!(chunks[1].content.contains("Oranges"));
+        let chunks: QueryResults<Chunk> = response.json();
+        assert_eq!(chunks.results.len(), 2);
+        assert!(chunks.results[0].content.contains("Bananas"));
+        assert!(chunks.results[1].content.contains("Oranges"));
+        assert!(chunks.next_cursor.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_create_query_with_cursor() {
+        let app = setup_populated().await;
+        let payload = json!({ "query": "Do you like banana?", "k": 3 });
+        let page: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        // The cursor can't be used to page through another query.
+        let cursor = page.next_cursor.unwrap();
+        let payload = json!({
+            "query": "Do you like oranges?",
+            "k": 3,
+            "cursor": cursor
+        });
+
+        let response = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        response.assert_status_bad_request();
+
+        let payload = json!({
+            "query": "Do you like banana?",
+            "k": 3,
+            "cursor": cursor
+        });
+
+        let next_page: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        assert_eq!(next_page.results.len(), 2);
+        assert!(next_page.next_cursor.is_none());
+        assert!(next_page
+            .results
+            .iter()
+            .all(|chunk| page.results.iter().all(|c| c.id != chunk.id)));
+    }
+
+    #[tokio::test]
+    async fn test_create_query_with_highlight() {
+        let app = setup_populated().await;
+
+        // Chunks without a matching term get a snippet either way.
+        for semantic in [false, true] {
+            let payload = json!({
+                "query": "Bananas and potassium",
+                "k": 2,
+                "highlight": {
+                    "start_tag": "<em>",
+                    "stop_tag": "</em>",
+                    "semantic": semantic
+                }
+            });
+
+            let chunks: QueryResults<Chunk> = app
+                .post("/namespaces/existing_ns/queries")
+                .authorization_bearer(BEARER)
+                .json(&payload)
+                .await
+                .json();
+
+            let snippet = chunks.results[0].snippet.clone().unwrap();
+            assert!(snippet.contains("<em>Bananas</em>"));
+            assert!(chunks.results[1].snippet.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_query_websearch_syntax() {
+        let app = setup_populated().await;
+        let payload = json!({
+            "query": "\"nearest neighbor\" -bananas",
+            "query_syntax": "Websearch",
+            "highlight": {}
+        });
+
+        let chunks: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        let snippet = chunks.results[0].snippet.clone().unwrap();
+        assert!(snippet.contains("<b>nearest</b> <b>neighbor</b>"));
+    }
+
+    #[tokio::test]
+    async fn test_create_query_invalid_raw_syntax() {
+        let app = setup_populated().await;
+        let payload = json!({ "query": "banana &", "query_syntax": "Raw" });
+        let response = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        response.assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_create_query_fuzzy() {
+        let app = setup_populated().await;
+        let payload = json!({ "query": "potasium", "k": 1, "fuzzy": true });
+        let chunks: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        assert!(chunks.results[0].content.contains("potassium"));
+    }
+
+    #[tokio::test]
+    async fn test_create_query_ef_search() {
+        let app = setup_populated().await;
+        let payload = json!({
+            "query": "Do you like banana?",
+            "k": 5,
+            "ef_search": 100,
+            "iterative_scan": "RelaxedOrder"
+        });
+
+        let chunks: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        assert_eq!(chunks.results.len(), 5);
+
+        let payload = json!({ "query": "Do you like banana?", "ef_search": 0 });
+        let response = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        response.assert_status_bad_request();
+
+        // Probes don't apply to the HNSW index of the namespace.
+        let payload = json!({ "query": "Do you like banana?", "probes": 5 });
+        let response = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        response.assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_create_query_beyond_ef_search() {
+        dotenv().ok();
+
+        let config = Configuration::default();
+        let state = Arc::new(Service::new(&config).await);
+        teardown(state.clone()).await;
+
+        let namespace = state
+            .create_namespace("existing_ns", &NamespaceConfig::default())
+            .await
+            .unwrap();
+
+        let document =
+            state.create_document(&namespace, &json!({})).await.unwrap();
+
+        // The chunks don't contain the query terms, so the results only come
+        // from the semantic search.
+        let schema = namespace.schema();
+        sqlx::query(&format!(
+            "INSERT INTO {schema}.chunks
+                (document_id, sequence, content, semantic_vector, text_vector)
+            SELECT $1, n, 'Filler chunk', ARRAY(
+                SELECT random() + n * 0 FROM generate_series(1, $2)
+            )::vector, to_tsvector('filler')
+            FROM generate_series(0, 499) AS n;",
+        ))
+        .bind(document.id)
+        .bind(namespace.config.embedding.dimension() as i32)
+        .execute(&state.database)
+        .await
+        .unwrap();
+
+        let app = TestServer::new(create_router(state)).unwrap();
+        let payload = json!({ "query": "Do you like banana?", "k": 100 });
+        let chunks: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        // The semantic search is not cut to the default ef_search of 40.
+        assert_eq!(chunks.results.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_create_query_with_filter() {
+        let app = setup_populated().await;
+        let payload = json!({
+            "query": "Do you like banana?",
+            "filter": { "section_path": ["Fruits"], "page_start": 2 }
+        });
+
+        let chunks: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        assert_eq!(chunks.results.len(), 2);
+        assert!(chunks.results.iter().all(|chunk| chunk.page == 2));
+
+        let payload = json!({
+            "query": "Do you like banana?",
+            "filter": { "metadata": { "topic": "ANNS" } }
+        });
+
+        let chunks: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        assert_eq!(chunks.results.len(), 3);
+
+        // The document has no extracted properties yet.
+        let payload = json!({
+            "query": "Do you like banana?",
+            "filter": { "document": { "language": "en" } }
+        });
+
+        let chunks: QueryResults<Chunk> = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await
+            .json();
+
+        assert!(chunks.results.is_empty());
+
+        let payload = json!({
+            "query": "Do you like banana?",
+            "filter": { "page_start": 2, "page_end": 1 }
+        });
+
+        let response = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        response.assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_create_query_invalid_k() {
+        let app = setup().await;
+        let payload = json!({ "query": "Do you like banana?", "k": 1000 });
+        let response = app
+            .post("/namespaces/existing_ns/queries")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        response.assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_create_document_query() {
+        let app = setup_populated().await;
+        let payload = json!({
+            "query": "Do you like banana?",
+            "k": 1,
+            "passages": 2,
+            "aggregation": "Sum"
+        });
+
+        let response = app
+            .post("/namespaces/existing_ns/queries/documents")
+            .authorization_bearer(BEARER)
+            .json(&payload)
+            .await;
+
+        let results: QueryResults<DocumentResult> = response.json();
+        let results = results.results;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].chunks.len(), 2);
+        assert!(results[0].chunks[0].content.contains("Bananas"));
+        assert_eq!(results[0].document.metadata, json!({ "key": "value" }));
     }
 
     async fn setup() -> TestServer {
@@ -416,22 +1395,32 @@ mod tests {
             .await
             .unwrap();
 
-        let sentences = vec![
-            "Approximate nearest neighbor finds similar items fast.",
-            "ANNS balances speed over perfect accuracy.",
-            "Popular ANNS methods include hashing and graphs.",
-            "Bananas are packed with potassium and energy.",
-            "Oranges are juicy and full of vitamin C.",
+        // The sentences about ANNS are on the first page and the sentences
+        // about fruits are on the second page.
+        let sentences = [
+            (
+                "ANNS",
+                "Approximate nearest neighbor finds similar items fast.",
+            ),
+            ("ANNS", "ANNS balances speed over perfect accuracy."),
+            ("ANNS", "Popular ANNS methods include hashing and graphs."),
+            ("Fruits", "Bananas are packed with potassium and energy."),
+            ("Fruits", "Oranges are juicy and full of vitamin C."),
         ];
 
+        let worker_id = setup_lease(&state, &namespace, &document).await;
         let request = protos::CreateChunkRequest {
             namespace: namespace.name.clone(),
             document_id: document.id.to_string(),
+            worker_id: worker_id.to_string(),
             chunks: sentences
                 .iter()
-                .map(|sentence| protos::Chunk {
-                    page: 1,
+                .map(|(section, sentence)| protos::Chunk {
+                    page: if *section == "ANNS" { 1 } else { 2 },
                     content: sentence.to_string(),
+                    section_path: vec![section.to_string()],
+                    metadata: json!({ "topic": section }).to_string(),
+                    ..Default::default()
                 })
                 .collect(),
         };
@@ -440,6 +1429,90 @@ mod tests {
         TestServer::new(create_router(state)).unwrap()
     }
 
+    async fn setup_worker() -> (TestServer, WorkerID) {
+        dotenv().ok();
+
+        let config = Configuration::default();
+        let state = Arc::new(Service::new(&config).await);
+
+        let request = protos::RegisterWorkerRequest {
+            id: Uuid::new_v4().to_string(),
+            address: "[::]:2510".to_string(),
+        };
+
+        let worker = Worker::try_from(request).unwrap();
+        state.add_worker(&worker).await.unwrap();
+
+        let app = TestServer::new(create_router(state)).unwrap();
+        (app, worker.id)
+    }
+
+    async fn setup_dead_letter() -> (TestServer, DocumentID) {
+        dotenv().ok();
+
+        let config = Configuration::default();
+        let state = Arc::new(Service::new(&config).await);
+        teardown(state.clone()).await;
+
+        let namespace = state
+            .create_namespace("existing_ns", &NamespaceConfig::default())
+            .await
+            .unwrap();
+
+        let document =
+            state.create_document(&namespace, &json!({})).await.unwrap();
+
+        // Failures are dead-lettered once the attempts are used up.
+        let schema = namespace.schema();
+        sqlx::query(&format!(
+            "UPDATE {schema}.documents SET attempts = $2 WHERE id = $1;"
+        ))
+        .bind(document.id)
+        .bind(state.config.max_attempts as i32)
+        .execute(&state.database)
+        .await
+        .unwrap();
+
+        let worker_id = setup_lease(&state, &namespace, &document).await;
+        let request = protos::UpdateDocumentRequest {
+            namespace: namespace.name.clone(),
+            document_id: document.id.to_string(),
+            status: protos::DocumentStatus::Failed as i32,
+            error: Some(protos::DocumentError {
+                code: "extraction_failed".to_string(),
+                message: "The document is encrypted.".to_string(),
+            }),
+            worker_id: worker_id.to_string(),
+            ..Default::default()
+        };
+
+        state.update_document(Request::new(request)).await.unwrap();
+        (TestServer::new(create_router(state)).unwrap(), document.id)
+    }
+
+    async fn setup_lease(
+        service: &Service,
+        namespace: &Namespace,
+        document: &Document,
+    ) -> WorkerID {
+        let worker_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO leases (
+                document_id, namespace, document_key, worker_id, expires_at
+            )
+            VALUES ($1, $2, $3, $4, now() + interval '1 minute');",
+        )
+        .bind(document.id)
+        .bind(&namespace.name)
+        .bind(document.key(namespace))
+        .bind(worker_id)
+        .execute(&service.database)
+        .await
+        .unwrap();
+
+        worker_id
+    }
+
     async fn teardown(service: Arc<Service>) {
         service.queue.purge().await.unwrap();
 
@@ -1,30 +1,86 @@
 mod coordinator;
 pub mod interface;
 
-use crate::apis::{QueueAPI, StorageAPI};
+use crate::apis::{QueueAPI, QueueBackend, RetryPolicy, StorageAPI};
+use crate::apis::{QueuePostgres, QueueRabbitMQ};
+use crate::embeddings::DenseVector;
 use crate::protos;
 use crate::types::*;
-use crate::utils::Reranker;
+use crate::utils::{most_overlapping, most_similar, sentence_windows};
+use crate::utils::{Cursor, CursorEntry, Reranker};
 use axum::http::StatusCode;
 use interface::ErrorResponse;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sqlx::pool::PoolConnection;
 use sqlx::postgres::PgPoolOptions;
-use sqlx::PgPool;
+use sqlx::{Connection, Executor, PgConnection, PgPool, Postgres, Transaction};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 use url::Url;
 use uuid::Uuid;
 
 const QUEUE_NAME: &str = "tasks";
 
+// Number of chunk candidates to retrieve per document in document queries.
+const DOCUMENT_CANDIDATES: usize = 10;
+
+// Text search configuration used for full-text search.
+const LANGUAGE: &str = "english";
+
+// Postgres error code raised when a raw keyword query is malformed.
+const SYNTAX_ERROR: &str = "42601";
+
+// Scopes of the query digests kept in the cursors.
+const CHUNK_SCOPE: &str = "chunks";
+const DOCUMENT_SCOPE: &str = "documents";
+
+// Number of documents to list per request by default and at most.
+const DEFAULT_DOCUMENT_LIMIT: usize = 20;
+const MAX_DOCUMENT_LIMIT: usize = 100;
+
+// Time a claim waits for a task when the queue is empty.
+const CLAIM_WAIT: Duration = Duration::from_secs(10);
+
+// Seconds a relay claims an outbox task while sending it to the queue.
+const OUTBOX_CLAIM: u64 = 60;
+
+// Number of dead-lettered tasks to list per request by default and at most.
+const DEFAULT_DEAD_LETTER_LIMIT: usize = 20;
+const MAX_DEAD_LETTER_LIMIT: usize = 100;
+
+// Number of top chunks without a matching query term whose snippets are
+// picked semantically when enabled by the highlight options.
+const SEMANTIC_SNIPPETS: usize = 5;
+
+// Minimum number of chunks to rank for a query.
+// The ranking is kept in the cursor to serve the following pages, so this
+// also caps how deep the pagination of a query can go.
+const QUERY_WINDOW: usize = 200;
+
 #[derive(Debug, Clone)]
 pub struct Configuration {
     pub secret: String,
+    pub worker_token: String,
     pub bucket: String,
-    pub queue_url: Url,
+    pub queue_backend: QueueBackend,
+    /// URL of the RabbitMQ broker required by the RabbitMQ backend.
+    pub queue_url: Option<Url>,
     pub database_url: Url,
     pub pool_size: u16,
+    pub max_query_k: usize,
+    /// Seconds without heartbeats after which a worker is expired.
+    pub worker_timeout: u64,
+    /// Seconds until a task lease expires unless renewed by the worker.
+    pub lease_timeout: u64,
+    /// Seconds after which a document in processing is considered stuck.
+    pub processing_timeout: u64,
+    /// Number of processing attempts before a document is marked failed.
+    pub max_attempts: u32,
+    /// Seconds before a task is retried, doubled with each attempt.
+    pub retry_delay: u64,
 }
 
 #[cfg(test)]
@@ -35,10 +91,18 @@ impl Default for Configuration {
 
         Configuration {
             secret: "secretkey".to_string(),
+            worker_token: "workertoken".to_string(),
             bucket: "dl-9799a9487ced".to_string(),
-            queue_url: Url::parse(queue).unwrap(),
+            queue_backend: QueueBackend::RabbitMQ,
+            queue_url: Some(Url::parse(queue).unwrap()),
             database_url: Url::parse(database).unwrap(),
             pool_size: 2,
+            max_query_k: 100,
+            worker_timeout: 60,
+            lease_timeout: 300,
+            processing_timeout: 3600,
+            max_attempts: 3,
+            retry_delay: 30,
         }
     }
 }
@@ -46,9 +110,8 @@ impl Default for Configuration {
 #[derive(Debug)]
 pub struct Service {
     config: Configuration,
-    workers: Mutex<Vec<Worker>>,
     storage: StorageAPI,
-    queue: QueueAPI,
+    queue: Box<dyn QueueAPI>,
     database: PgPool,
 }
 
@@ -61,11 +124,28 @@ impl Service {
             .await
             .expect("Failed to connect to the database");
 
+        let policy = RetryPolicy {
+            max_attempts: config.max_attempts,
+            delay: config.retry_delay,
+        };
+
+        let queue: Box<dyn QueueAPI> = match config.queue_backend {
+            QueueBackend::RabbitMQ => {
+                let url = config.queue_url.as_ref().map(|url| url.as_str());
+                let url = url.expect("The RabbitMQ backend requires a URL");
+                let pool = pool.clone();
+                let queue = QueueRabbitMQ::new(QUEUE_NAME, url, pool, policy);
+                Box::new(queue.await)
+            },
+            QueueBackend::Postgres => {
+                Box::new(QueuePostgres::new(pool.clone(), policy).await)
+            },
+        };
+
         Service {
             config: config.clone(),
-            workers: Mutex::new(Vec::new()),
             storage: StorageAPI::new(&config.bucket).await,
-            queue: QueueAPI::new(QUEUE_NAME, config.queue_url.as_str()).await,
+            queue,
             database: pool,
         }
     }
@@ -99,25 +179,679 @@ impl Service {
         })
     }
 
-    /// Adds a worker to the list of workers if it doesn't already exist.
-    pub async fn add_worker(&self, worker: &Worker) {
-        let mut workers = self.workers.lock().await;
-        if workers.iter().any(|w| w.id == worker.id) {
-            return;
+    /// Validates that k is within the allowed range for a query.
+    pub fn validate_k(&self, k: usize) -> Result<usize, ErrorResponse> {
+        let max = self.config.max_query_k;
+        if k == 0 || k > max {
+            return Err(ErrorResponse {
+                code: StatusCode::BAD_REQUEST,
+                message: format!("The value of k must be between 1 and {max}."),
+                solution: Some(String::from(
+                    "Please use the cursor to retrieve more results.",
+                )),
+            });
+        }
+
+        Ok(k)
+    }
+
+    /// Validates that the token is a valid cursor for the query.
+    ///
+    /// The digest identifies the query and its options, so a cursor of
+    /// another query is rejected instead of silently ignoring the query.
+    pub fn validate_cursor(
+        &self,
+        namespace: &Namespace,
+        token: impl AsRef<str>,
+        digest: &[u8; 32],
+    ) -> Result<Cursor, ErrorResponse> {
+        let key = self.config.secret.as_bytes();
+        match Cursor::decode(token, key) {
+            Some(cursor)
+                if cursor.namespace == namespace.id
+                    && cursor.query == *digest =>
+            {
+                Ok(cursor)
+            },
+            _ => Err(ErrorResponse {
+                code: StatusCode::BAD_REQUEST,
+                message: String::from("Please provide a valid cursor."),
+                solution: Some(String::from(
+                    "Use the cursor returned by the previous query.",
+                )),
+            }),
+        }
+    }
+
+    /// Registers a worker or renews the registration of an existing one.
+    pub async fn add_worker(
+        &self,
+        worker: &Worker,
+    ) -> Result<(), ErrorResponse> {
+        sqlx::query(
+            "INSERT INTO workers (id, address)
+            VALUES ($1, $2)
+            ON CONFLICT (id) DO UPDATE
+            SET address = EXCLUDED.address,
+                status = 'idle',
+                namespace = NULL,
+                document_id = NULL,
+                last_seen_at = now();",
+        )
+        .bind(worker.id)
+        .bind(worker.address.to_string())
+        .execute(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to register the worker: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to register the worker.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Records a heartbeat with the current status of a worker.
+    ///
+    /// Returns none if the worker is not registered, for example because
+    /// it expired, in which case the worker must register again.
+    pub async fn record_heartbeat(
+        &self,
+        heartbeat: &WorkerHeartbeat,
+    ) -> Result<Option<Worker>, ErrorResponse> {
+        let task = heartbeat.task.as_ref();
+        sqlx::query_as(
+            "UPDATE workers
+            SET status = $2,
+                namespace = $3,
+                document_id = $4,
+                completed = $5,
+                failed = $6,
+                last_seen_at = now()
+            WHERE id = $1
+            RETURNING *;",
+        )
+        .bind(heartbeat.id)
+        .bind(heartbeat.status)
+        .bind(task.map(|task| &task.namespace))
+        .bind(task.map(|task| task.document_id))
+        .bind(heartbeat.completed)
+        .bind(heartbeat.failed)
+        .fetch_optional(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to record the heartbeat: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to record the heartbeat.".to_string(),
+                solution: None,
+            }
+        })
+    }
+
+    /// Claims the next task from the queue and leases it to the worker.
+    ///
+    /// The lease is persisted before the task is acknowledged, so a task is
+    /// never lost if the coordinator fails in between. Draining workers
+    /// don't receive new tasks.
+    pub async fn lease_task(
+        &self,
+        worker_id: &WorkerID,
+    ) -> Result<Option<Lease>, ErrorResponse> {
+        let draining: Option<bool> =
+            sqlx::query_scalar("SELECT draining FROM workers WHERE id = $1;")
+                .bind(worker_id)
+                .fetch_optional(&self.database)
+                .await
+                .map_err(|_e| {
+                    #[cfg(test)]
+                    eprintln!("Failed to retrieve the worker: {_e:?}");
+                    ErrorResponse {
+                        code: StatusCode::INTERNAL_SERVER_ERROR,
+                        message: "Failed to retrieve the worker.".to_string(),
+                        solution: None,
+                    }
+                })?;
+
+        match draining {
+            Some(false) => {},
+            Some(true) => return Ok(None),
+            None => {
+                return Err(ErrorResponse {
+                    code: StatusCode::NOT_FOUND,
+                    message: "The specified worker is not found".to_string(),
+                    solution: Some("Register the worker first.".to_string()),
+                })
+            },
+        }
+
+        // Claims wait for a task to be published when the queue is empty.
+        let namespaces = self.fair_namespaces().await?;
+        let delivery = match self.queue.get(&namespaces).await? {
+            Some(delivery) => delivery,
+            None => {
+                self.queue.wait(CLAIM_WAIT).await;
+                let namespaces = self.fair_namespaces().await?;
+                match self.queue.get(&namespaces).await? {
+                    Some(delivery) => delivery,
+                    None => return Ok(None),
+                }
+            },
+        };
+
+        // A task delivered again replaces the lease of the previous worker.
+        let task = &delivery.task;
+        let lease = sqlx::query_as(
+            "INSERT INTO leases (
+                document_id, namespace, document_key, priority,
+                worker_id, expires_at
+            )
+            VALUES ($1, $2, $3, $4, $5, now() + make_interval(secs => $6))
+            ON CONFLICT (document_id) DO UPDATE
+            SET worker_id = EXCLUDED.worker_id,
+                expires_at = EXCLUDED.expires_at,
+                claimed_at = now()
+            RETURNING *;",
+        )
+        .bind(task.document_id)
+        .bind(&task.namespace)
+        .bind(&task.document_key)
+        .bind(task.priority)
+        .bind(worker_id)
+        .bind(self.config.lease_timeout as f64)
+        .fetch_one(&self.database)
+        .await;
+
+        let lease: Lease = match lease {
+            Ok(lease) => lease,
+            Err(_e) => {
+                #[cfg(test)]
+                eprintln!("Failed to lease the task: {_e:?}");
+                delivery.requeue().await?;
+                return Err(ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: "Failed to lease the task.".to_string(),
+                    solution: None,
+                });
+            },
+        };
+
+        delivery.ack().await?;
+        Ok(Some(lease))
+    }
+
+    /// Returns the names of the namespaces in the order to claim tasks from.
+    ///
+    /// Namespaces with the fewest leased tasks come first, so every
+    /// namespace with pending tasks gets a fair share of the workers no
+    /// matter how many tasks the others have queued up. Only namespaces with
+    /// tasks ready to be delivered are returned when the queue can list them.
+    async fn fair_namespaces(&self) -> Result<Vec<String>, ErrorResponse> {
+        let ready = self.queue.ready_namespaces().await?;
+        if ready.as_ref().is_some_and(|ready| ready.is_empty()) {
+            return Ok(Vec::new());
+        }
+
+        sqlx::query_scalar(
+            "SELECT namespaces.name
+            FROM namespaces
+            LEFT JOIN leases ON leases.namespace = namespaces.name
+            WHERE $1::text[] IS NULL OR namespaces.name = ANY($1)
+            GROUP BY namespaces.name
+            ORDER BY COUNT(leases.document_id), random();",
+        )
+        .bind(ready)
+        .fetch_all(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to retrieve the namespaces: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to retrieve the namespaces.".to_string(),
+                solution: None,
+            }
+        })
+    }
+
+    /// Extends the lease of a task claimed by the worker.
+    ///
+    /// Fails if the lease belongs to another worker or it expired and the
+    /// task was requeued.
+    pub async fn extend_lease(
+        &self,
+        worker_id: &WorkerID,
+        document_id: &DocumentID,
+    ) -> Result<Lease, ErrorResponse> {
+        let lease: Option<Lease> = sqlx::query_as(
+            "UPDATE leases
+            SET expires_at = now() + make_interval(secs => $3)
+            WHERE document_id = $1 AND worker_id = $2
+            RETURNING *;",
+        )
+        .bind(document_id)
+        .bind(worker_id)
+        .bind(self.config.lease_timeout as f64)
+        .fetch_optional(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to renew the lease: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to renew the lease.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        lease.ok_or_else(|| ErrorResponse {
+            code: StatusCode::NOT_FOUND,
+            message: "The lease of the task is not found".to_string(),
+            solution: Some("Stop processing the task.".to_string()),
+        })
+    }
+
+    /// Requeues the tasks of the expired leases.
+    ///
+    /// The leases are removed in the same transaction as the tasks are
+    /// written to the outbox, so replicas don't requeue the same lease twice
+    /// and the tasks are published by the outbox relay once committed.
+    /// Documents that reached the maximum attempts are marked as failed
+    /// instead.
+    pub async fn requeue_expired_leases(
+        &self,
+    ) -> Result<Vec<Lease>, ErrorResponse> {
+        let error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to requeue the expired leases: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to requeue the expired leases.".to_string(),
+                solution: None,
+            }
+        };
+
+        let mut tx = self.database.begin().await.map_err(error)?;
+        let leases: Vec<Lease> = sqlx::query_as(
+            "DELETE FROM leases
+            WHERE document_id IN (
+                SELECT document_id FROM leases
+                WHERE expires_at < now()
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *;",
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(error)?;
+
+        for lease in leases.iter() {
+            // Documents of removed namespaces are skipped.
+            let task = &lease.task;
+            let namespace = match self.get_namespace(&task.namespace).await {
+                Ok(namespace) => namespace,
+                Err(_) => continue,
+            };
+
+            let ids = [task.document_id];
+            let reason = "lease_expired";
+            self.recover_documents(&mut tx, &namespace, &ids, reason)
+                .await?;
+        }
+
+        tx.commit().await.map_err(error)?;
+        Ok(leases)
+    }
+
+    /// Recovers the documents stuck in processing past the timeout.
+    ///
+    /// Documents leased to a worker are left to the lease expiry. Returns
+    /// the recovered documents which are either pending again or failed.
+    pub async fn recover_stuck_documents(
+        &self,
+    ) -> Result<Vec<Document>, ErrorResponse> {
+        let error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to recover the stuck documents: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to recover the stuck documents.".to_string(),
+                solution: None,
+            }
+        };
+
+        let namespaces: Vec<Namespace> =
+            sqlx::query_as("SELECT * FROM namespaces")
+                .fetch_all(&self.database)
+                .await
+                .map_err(error)?;
+
+        let mut documents = Vec::new();
+        for namespace in namespaces.iter() {
+            let schema = namespace.schema();
+            let mut tx = self.database.begin().await.map_err(error)?;
+            let ids: Vec<DocumentID> = sqlx::query_scalar(&format!(
+                "SELECT id FROM {schema}.documents
+                WHERE status = 'processing'
+                AND updated_at < NOW() - make_interval(secs => $1)
+                AND id NOT IN (
+                    SELECT document_id FROM leases
+                    WHERE expires_at > NOW()
+                )
+                FOR UPDATE SKIP LOCKED;",
+            ))
+            .bind(self.config.processing_timeout as f64)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(error)?;
+
+            if ids.is_empty() {
+                continue;
+            }
+
+            let reason = "processing_timeout";
+            let recovered = self
+                .recover_documents(&mut tx, namespace, &ids, reason)
+                .await?;
+
+            tx.commit().await.map_err(error)?;
+            documents.extend(recovered);
+        }
+
+        Ok(documents)
+    }
+
+    /// Retries the unfinished documents within the transaction.
+    ///
+    /// Documents that reached the maximum attempts are marked as failed
+    /// with the reason and their tasks are dead-lettered instead. The tasks
+    /// go through the outbox so nothing is sent to the queue unless the
+    /// transaction is committed.
+    async fn recover_documents(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        namespace: &Namespace,
+        ids: &[DocumentID],
+        reason: &str,
+    ) -> Result<Vec<Document>, ErrorResponse> {
+        let max_attempts = self.config.max_attempts;
+        let message = format!(
+            "The document was not processed after {max_attempts} attempt(s)."
+        );
+
+        let schema = namespace.schema();
+        let documents: Vec<Document> = sqlx::query_as(&format!(
+            "UPDATE {schema}.documents
+            SET status = CASE
+                WHEN attempts < $2 THEN 'pending'
+                ELSE 'failed' END::doc_status,
+            error_code = CASE
+                WHEN attempts < $2 THEN error_code ELSE $3 END,
+            error_message = CASE
+                WHEN attempts < $2 THEN error_message ELSE $4 END,
+            failed_at = CASE
+                WHEN attempts < $2 THEN failed_at ELSE NOW() END,
+            updated_at = NOW()
+            WHERE id = ANY($1)
+            AND status IN ('pending', 'processing')
+            RETURNING *;",
+        ))
+        .bind(ids)
+        .bind(max_attempts as i32)
+        .bind(reason)
+        .bind(&message)
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to recover the documents: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to recover the documents.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        for document in documents.iter() {
+            let task = ExtractionTask {
+                namespace: namespace.name.clone(),
+                document_key: document.key(namespace),
+                document_id: document.id,
+                priority: document.priority,
+            };
+
+            let error = match document.status {
+                DocumentStatus::Pending => None,
+                _ => Some((reason, message.as_str())),
+            };
+
+            let attempts = document.attempts as u32;
+            self.write_outbox(&mut **tx, &task, attempts, error).await?;
+        }
+
+        Ok(documents)
+    }
+
+    /// Returns a list of all registered workers.
+    pub async fn workers(&self) -> Result<Vec<Worker>, ErrorResponse> {
+        sqlx::query_as("SELECT * FROM workers ORDER BY registered_at;")
+            .fetch_all(&self.database)
+            .await
+            .map_err(|_e| {
+                #[cfg(test)]
+                eprintln!("Failed to retrieve the workers: {_e:?}");
+                ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: "Failed to retrieve the workers.".to_string(),
+                    solution: None,
+                }
+            })
+    }
+
+    /// Marks a worker as draining so it stops taking new tasks.
+    ///
+    /// The worker is notified with the response to its next heartbeat and
+    /// finishes its current task before becoming idle.
+    pub async fn drain_worker(
+        &self,
+        id: &WorkerID,
+    ) -> Result<Worker, ErrorResponse> {
+        let worker: Option<Worker> = sqlx::query_as(
+            "UPDATE workers
+            SET draining = true
+            WHERE id = $1
+            RETURNING *;",
+        )
+        .bind(id)
+        .fetch_optional(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to drain the worker: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to drain the worker.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        worker.ok_or_else(|| ErrorResponse {
+            code: StatusCode::NOT_FOUND,
+            message: "The specified worker is not found".to_string(),
+            solution: None,
+        })
+    }
+
+    /// Removes a worker from the registry.
+    ///
+    /// A worker that is still running registers again with its next
+    /// heartbeat, so workers seen within the worker timeout can't be removed.
+    /// Running workers should be drained and stopped first.
+    pub async fn remove_worker(
+        &self,
+        id: &WorkerID,
+    ) -> Result<Worker, ErrorResponse> {
+        let remove_error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to remove the worker: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to remove the worker.".to_string(),
+                solution: None,
+            }
+        };
+
+        let worker: Option<Worker> = sqlx::query_as(
+            "DELETE FROM workers
+            WHERE id = $1
+            AND last_seen_at < now() - make_interval(secs => $2)
+            RETURNING *;",
+        )
+        .bind(id)
+        .bind(self.config.worker_timeout as f64)
+        .fetch_optional(&self.database)
+        .await
+        .map_err(remove_error)?;
+
+        if let Some(worker) = worker {
+            return Ok(worker);
         }
 
-        workers.push(worker.clone());
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM workers WHERE id = $1);",
+        )
+        .bind(id)
+        .fetch_one(&self.database)
+        .await
+        .map_err(remove_error)?;
+
+        match exists {
+            true => Err(ErrorResponse {
+                code: StatusCode::CONFLICT,
+                message: "The worker is still running.".to_string(),
+                solution: Some(
+                    "Drain and stop the worker before removing it.".into(),
+                ),
+            }),
+            false => Err(ErrorResponse {
+                code: StatusCode::NOT_FOUND,
+                message: "The specified worker is not found".to_string(),
+                solution: None,
+            }),
+        }
+    }
+
+    /// Removes the workers that missed heartbeats for the worker timeout.
+    ///
+    /// Returns the IDs of the expired workers.
+    pub async fn expire_workers(&self) -> Result<Vec<WorkerID>, ErrorResponse> {
+        sqlx::query_scalar(
+            "DELETE FROM workers
+            WHERE last_seen_at < now() - make_interval(secs => $1)
+            RETURNING id;",
+        )
+        .bind(self.config.worker_timeout as f64)
+        .fetch_all(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to expire the workers: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to expire the workers.".to_string(),
+                solution: None,
+            }
+        })
+    }
+
+    /// Reconnects to the queue if the connection was lost.
+    ///
+    /// Returns true if the queue was reconnected.
+    pub async fn recover_queue(&self) -> Result<bool, ErrorResponse> {
+        self.queue.recover().await
+    }
+
+    /// Returns the tasks in the dead-letter queue.
+    pub async fn dead_letters(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, ErrorResponse> {
+        self.queue.dead_letters(limit).await
+    }
+
+    /// Returns the dead-lettered task of the document.
+    pub async fn get_dead_letter(
+        &self,
+        id: &DocumentID,
+    ) -> Result<DeadLetter, ErrorResponse> {
+        let letter = self.queue.dead_letter_of(id).await?;
+        letter.ok_or_else(|| ErrorResponse {
+            code: StatusCode::NOT_FOUND,
+            message: "The document has no dead-lettered task".to_string(),
+            solution: None,
+        })
     }
 
-    /// Removes multiple workers from the list of workers by their IDs.
-    pub async fn remove_workers(&self, ids: &[WorkerID]) {
-        let mut workers = self.workers.lock().await;
-        workers.retain(|worker| !ids.contains(&worker.id));
+    /// Replays the dead-lettered task of the document.
+    ///
+    /// The failed document is reset to pending with no attempts before
+    /// its task is published again, so the replay gets the full retries.
+    pub async fn replay_dead_letter(
+        &self,
+        id: &DocumentID,
+    ) -> Result<DeadLetter, ErrorResponse> {
+        let letter = self.get_dead_letter(id).await?;
+
+        // The task of a removed namespace is still replayed and dropped
+        // by the worker like any task of a removed document.
+        match self.get_namespace(&letter.task.namespace).await {
+            Ok(namespace) => self.reset_document(&namespace, id).await?,
+            Err(e) if e.code == StatusCode::NOT_FOUND => {},
+            Err(e) => return Err(e),
+        }
+
+        let letter = self.queue.replay(id).await?;
+        letter.ok_or_else(|| ErrorResponse {
+            code: StatusCode::NOT_FOUND,
+            message: "The document has no dead-lettered task".to_string(),
+            solution: None,
+        })
     }
 
-    /// Returns a list of all workers.
-    pub async fn workers(&self) -> Vec<Worker> {
-        self.workers.lock().await.clone()
+    /// Resets a failed document to pending with no attempts.
+    async fn reset_document(
+        &self,
+        namespace: &Namespace,
+        id: &DocumentID,
+    ) -> Result<(), ErrorResponse> {
+        let schema = namespace.schema();
+        sqlx::query(&format!(
+            "UPDATE {schema}.documents
+            SET status = 'pending',
+            attempts = 0,
+            updated_at = NOW()
+            WHERE id = $1
+            AND status = 'failed';",
+        ))
+        .bind(id)
+        .execute(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to reset the document: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to reset the document.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        Ok(())
     }
 
     /// Creates a new namespace with the given name.
@@ -213,16 +947,255 @@ impl Service {
         Ok(namespace.unwrap())
     }
 
-    /// Creates a new document record within the given namespace.
-    pub async fn create_document(
+    /// Takes the maintenance lock of the namespace.
+    ///
+    /// The lock is a session advisory lock held by the returned connection so
+    /// only one maintenance runs on a namespace across the replicas.
+    pub async fn lock_maintenance(
         &self,
         namespace: &Namespace,
-        metadata: &Value,
-    ) -> Result<Document, ErrorResponse> {
-        let schema = namespace.schema();
-        let document: Document = sqlx::query_as(&format!(
-            "INSERT INTO {schema}.documents (metadata)
-            VALUES ($1)
+    ) -> Result<PoolConnection<Postgres>, ErrorResponse> {
+        let lock_error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to lock the namespace: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to lock the namespace.".to_string(),
+                solution: None,
+            }
+        };
+
+        let mut connection =
+            self.database.acquire().await.map_err(lock_error)?;
+
+        let locked: bool = sqlx::query_scalar(
+            "SELECT pg_try_advisory_lock(hashtextextended($1, 0));",
+        )
+        .bind(maintenance_lock(namespace))
+        .fetch_one(&mut *connection)
+        .await
+        .map_err(lock_error)?;
+
+        if !locked {
+            return Err(ErrorResponse {
+                code: StatusCode::CONFLICT,
+                message: "A maintenance is already running.".to_string(),
+                solution: Some(
+                    "Wait for the running maintenance to complete.".into(),
+                ),
+            });
+        }
+
+        Ok(connection)
+    }
+
+    /// Runs a maintenance operation on the namespace tables and indexes.
+    ///
+    /// The operations can take a long time on large namespaces and can't run
+    /// in a transaction, so the caller should run this in the background. The
+    /// connection must hold the maintenance lock which is released after.
+    pub async fn maintain_namespace(
+        &self,
+        mut connection: PoolConnection<Postgres>,
+        namespace: &Namespace,
+        maintenance: &Maintenance,
+    ) -> Result<(), ErrorResponse> {
+        let result = self
+            .run_maintenance(&mut connection, namespace, maintenance)
+            .await;
+
+        let unlocked =
+            sqlx::query("SELECT pg_advisory_unlock(hashtextextended($1, 0));")
+                .bind(maintenance_lock(namespace))
+                .execute(&mut *connection)
+                .await;
+
+        // Closing the connection releases the lock if unlocking fails so the
+        // lock doesn't outlive the maintenance in the pool.
+        if unlocked.is_err() {
+            let _ = connection.detach().close().await;
+        }
+
+        result
+    }
+
+    async fn run_maintenance(
+        &self,
+        connection: &mut PgConnection,
+        namespace: &Namespace,
+        maintenance: &Maintenance,
+    ) -> Result<(), ErrorResponse> {
+        let schema = namespace.schema();
+        let maintenance_error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to maintain the namespace: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to maintain the namespace.".to_string(),
+                solution: None,
+            }
+        };
+
+        let statements = match maintenance {
+            Maintenance::Reindex => {
+                vec![format!("REINDEX TABLE CONCURRENTLY {schema}.chunks")]
+            },
+            Maintenance::Vacuum => vec![format!(
+                "VACUUM ANALYZE {schema}.documents, {schema}.chunks"
+            )],
+            Maintenance::Rebuild { index } => {
+                return self
+                    .rebuild_semantic_index(connection, namespace, index)
+                    .await;
+            },
+        };
+
+        for statement in statements.iter() {
+            sqlx::query(statement)
+                .execute(&mut *connection)
+                .await
+                .map_err(maintenance_error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Swaps the semantic index of the namespace with a new index built
+    /// concurrently with the given configuration.
+    async fn rebuild_semantic_index(
+        &self,
+        connection: &mut PgConnection,
+        namespace: &Namespace,
+        index: &IndexConfig,
+    ) -> Result<(), ErrorResponse> {
+        let schema = namespace.schema();
+        let rebuild_error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to rebuild the semantic index: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to rebuild the semantic index.".to_string(),
+                solution: None,
+            }
+        };
+
+        let new_index = format!("{SEMANTIC_INDEX}_new");
+        let old_index = format!("{SEMANTIC_INDEX}_old");
+
+        // A failed concurrent build leaves an invalid index behind which we
+        // need to drop before building the index again.
+        let statements = [
+            format!("DROP INDEX CONCURRENTLY IF EXISTS {schema}.{new_index}"),
+            format!("DROP INDEX CONCURRENTLY IF EXISTS {schema}.{old_index}"),
+            namespace.semantic_index_sql(&new_index, index, true),
+        ];
+
+        for statement in statements.iter() {
+            sqlx::query(statement)
+                .execute(&mut *connection)
+                .await
+                .map_err(rebuild_error)?;
+        }
+
+        let mut config = namespace.config.clone();
+        config.index = index.clone();
+        let config = serde_json::to_value(&config).unwrap();
+
+        // Renaming the indexes only holds the locks briefly so the swap
+        // doesn't block the queries on the namespace.
+        let mut tx = connection.begin().await.map_err(rebuild_error)?;
+        let statements = [
+            format!(
+                "ALTER INDEX {schema}.{SEMANTIC_INDEX}
+                RENAME TO {old_index}"
+            ),
+            format!(
+                "ALTER INDEX {schema}.{new_index}
+                RENAME TO {SEMANTIC_INDEX}"
+            ),
+        ];
+
+        for statement in statements.iter() {
+            sqlx::query(statement)
+                .execute(&mut *tx)
+                .await
+                .map_err(rebuild_error)?;
+        }
+
+        sqlx::query(
+            "UPDATE namespaces
+            SET config = $1
+            WHERE id = $2;",
+        )
+        .bind(&config)
+        .bind(namespace.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(rebuild_error)?;
+
+        tx.commit().await.map_err(rebuild_error)?;
+
+        sqlx::query(&format!("DROP INDEX CONCURRENTLY {schema}.{old_index}"))
+            .execute(&mut *connection)
+            .await
+            .map_err(rebuild_error)?;
+
+        Ok(())
+    }
+
+    /// Returns the progress of the indexes being built in the namespace.
+    pub async fn maintenance_progress(
+        &self,
+        namespace: &Namespace,
+    ) -> Result<Vec<IndexProgress>, ErrorResponse> {
+        let progress: Vec<IndexProgress> = sqlx::query_as(
+            "SELECT
+                tables.relname::TEXT AS table,
+                indexes.relname::TEXT AS index,
+                progress.command,
+                progress.phase,
+                progress.blocks_done,
+                progress.blocks_total,
+                progress.tuples_done,
+                progress.tuples_total
+            FROM pg_stat_progress_create_index AS progress
+            JOIN pg_class AS tables ON tables.oid = progress.relid
+            JOIN pg_namespace AS schemas
+            ON schemas.oid = tables.relnamespace
+            LEFT JOIN pg_class AS indexes
+            ON indexes.oid = progress.index_relid
+            WHERE schemas.nspname = $1;",
+        )
+        .bind(namespace.schema())
+        .fetch_all(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to retrieve the maintenance progress: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to retrieve the progress.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        Ok(progress)
+    }
+
+    /// Creates a new document record within the given namespace.
+    ///
+    /// Uploads go through the outbox, so this only sets up documents
+    /// without a task for the tests.
+    #[cfg(test)]
+    pub async fn create_document(
+        &self,
+        namespace: &Namespace,
+        metadata: &Value,
+    ) -> Result<Document, ErrorResponse> {
+        let schema = namespace.schema();
+        let document: Document = sqlx::query_as(&format!(
+            "INSERT INTO {schema}.documents (metadata)
+            VALUES ($1)
             RETURNING *;",
         ))
         .bind(metadata)
@@ -241,6 +1214,228 @@ impl Service {
         Ok(document)
     }
 
+    /// Uploads a document and queues up its extraction task.
+    ///
+    /// The document and its task in the outbox are written in one
+    /// transaction after the file is stored, and the file is removed if the
+    /// transaction fails, so an upload never leaves a partial document.
+    /// The task is published right away, or by the outbox relay if the
+    /// queue is unavailable.
+    pub async fn upload_document(
+        &self,
+        namespace: &Namespace,
+        metadata: &Value,
+        priority: i32,
+        data: Vec<u8>,
+    ) -> Result<Document, ErrorResponse> {
+        let id = Uuid::new_v4();
+        let key = namespace.document_key(&id);
+        self.storage.upload(&key, data).await?;
+
+        let document = self.insert_document(namespace, &id, metadata, priority);
+        let document = match document.await {
+            Ok(document) => document,
+            Err(e) => {
+                if let Err(e) = self.storage.remove(&key).await {
+                    tracing::error!("Failed to remove {key}: {}", e.message);
+                }
+
+                return Err(e);
+            },
+        };
+
+        if let Err(e) = self.relay_outbox(Some(&id)).await {
+            tracing::warn!("Task of {id} is left to the relay: {}", e.message);
+        }
+
+        Ok(document)
+    }
+
+    /// Creates a document record with its extraction task in the outbox.
+    async fn insert_document(
+        &self,
+        namespace: &Namespace,
+        id: &DocumentID,
+        metadata: &Value,
+        priority: i32,
+    ) -> Result<Document, ErrorResponse> {
+        let error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to create a new document: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to create a new document."),
+                solution: None,
+            }
+        };
+
+        let mut tx = self.database.begin().await.map_err(error)?;
+
+        let schema = namespace.schema();
+        let document: Document = sqlx::query_as(&format!(
+            "INSERT INTO {schema}.documents (id, metadata, priority)
+            VALUES ($1, $2, $3)
+            RETURNING *;",
+        ))
+        .bind(id)
+        .bind(metadata)
+        .bind(priority)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(error)?;
+
+        let task = ExtractionTask {
+            namespace: namespace.name.clone(),
+            document_key: document.key(namespace),
+            document_id: document.id,
+            priority,
+        };
+
+        self.write_outbox(&mut *tx, &task, 0, None).await?;
+        tx.commit().await.map_err(error)?;
+        Ok(document)
+    }
+
+    /// Writes the task to the outbox to be sent once the writes commit.
+    /// - attempts: Processing attempts of the task, zero for a new task.
+    /// - error: Code and message to dead-letter the task with.
+    async fn write_outbox(
+        &self,
+        executor: impl Executor<'_, Database = Postgres>,
+        task: &ExtractionTask,
+        attempts: u32,
+        error: Option<(&str, &str)>,
+    ) -> Result<(), ErrorResponse> {
+        sqlx::query(
+            "INSERT INTO outbox (
+                document_id, namespace, document_key, priority,
+                attempts, error_code, error_message
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (document_id) DO UPDATE
+            SET priority = EXCLUDED.priority,
+                attempts = EXCLUDED.attempts,
+                error_code = EXCLUDED.error_code,
+                error_message = EXCLUDED.error_message,
+                claimed_until = NULL;",
+        )
+        .bind(task.document_id)
+        .bind(&task.namespace)
+        .bind(&task.document_key)
+        .bind(task.priority)
+        .bind(attempts as i32)
+        .bind(error.map(|(code, _)| code))
+        .bind(error.map(|(_, message)| message))
+        .execute(executor)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to write the task to the outbox: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from(
+                    "Failed to write the task to the outbox.",
+                ),
+                solution: None,
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Publishes the tasks in the outbox, or the task of the document.
+    ///
+    /// A task is claimed before it's sent to the queue, which retries or
+    /// dead-letters the recovered tasks, and removed once sent. No
+    /// connection is held while sending, since the queue may need one from
+    /// the same pool. If the task isn't sent, the claim is released. If the
+    /// removal fails, the task is sent again once the claim expires, so
+    /// tasks are delivered at least once. Returns the number of tasks.
+    pub async fn relay_outbox(
+        &self,
+        document_id: Option<&DocumentID>,
+    ) -> Result<usize, ErrorResponse> {
+        let error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to relay the outbox: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to relay the outbox."),
+                solution: None,
+            }
+        };
+
+        let mut published = 0;
+        loop {
+            // Tasks rewritten while they are sent have their claim reset, so
+            // they are sent again.
+            let entry: Option<OutboxTask> = sqlx::query_as(
+                "UPDATE outbox
+                SET claimed_until = now() + make_interval(secs => $2)
+                WHERE document_id = (
+                    SELECT document_id FROM outbox
+                    WHERE ($1::uuid IS NULL OR document_id = $1)
+                    AND (claimed_until IS NULL OR claimed_until < now())
+                    ORDER BY created_at
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING namespace, document_key, document_id, priority,
+                    attempts, error_code, error_message, claimed_until;",
+            )
+            .bind(document_id)
+            .bind(OUTBOX_CLAIM as f64)
+            .fetch_optional(&self.database)
+            .await
+            .map_err(error)?;
+
+            let entry = match entry {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            let task = &entry.task;
+            let attempts = entry.attempts as u32;
+            let sent = match &entry.error_code {
+                Some(code) => {
+                    let message = entry.error_message.as_deref();
+                    let message = message.unwrap_or_default();
+                    let queue = &self.queue;
+                    queue.dead_letter(task, attempts, code, message).await
+                },
+                None if attempts > 0 => self.queue.retry(task, attempts).await,
+                None => self.queue.publish(task).await,
+            };
+
+            let settle = match sent {
+                Ok(_) => {
+                    "DELETE FROM outbox
+                    WHERE document_id = $1 AND claimed_until = $2;"
+                },
+                Err(_) => {
+                    "UPDATE outbox SET claimed_until = NULL
+                    WHERE document_id = $1 AND claimed_until = $2;"
+                },
+            };
+
+            sqlx::query(settle)
+                .bind(task.document_id)
+                .bind(entry.claimed_until)
+                .execute(&self.database)
+                .await
+                .map_err(error)?;
+
+            sent?;
+            published += 1;
+
+            if document_id.is_some() {
+                break;
+            }
+        }
+
+        Ok(published)
+    }
+
     /// Removes a document record from the database.
     pub async fn remove_document(
         &self,
@@ -269,66 +1464,568 @@ impl Service {
         Ok(document)
     }
 
+    /// Returns a document in the namespace given its ID if it exists.
+    pub async fn get_document(
+        &self,
+        namespace: &Namespace,
+        id: &DocumentID,
+    ) -> Result<Document, ErrorResponse> {
+        let schema = namespace.schema();
+        let document: Option<Document> = sqlx::query_as(&format!(
+            "SELECT * FROM {schema}.documents
+            WHERE id = $1;",
+        ))
+        .bind(id)
+        .fetch_optional(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to retrieve the document: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to retrieve the document.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        document.ok_or_else(|| ErrorResponse {
+            code: StatusCode::NOT_FOUND,
+            message: "The specified document is not found".to_string(),
+            solution: None,
+        })
+    }
+
+    /// Lists the documents in the namespace from the most recent one.
+    /// - status: Only list documents with the status.
+    pub async fn list_documents(
+        &self,
+        namespace: &Namespace,
+        status: Option<DocumentStatus>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Document>, ErrorResponse> {
+        let schema = namespace.schema();
+        let documents: Vec<Document> = sqlx::query_as(&format!(
+            "SELECT * FROM {schema}.documents
+            WHERE $1::doc_status IS NULL OR status = $1
+            ORDER BY created_at DESC, id
+            LIMIT $2 OFFSET $3;",
+        ))
+        .bind(status)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.database)
+        .await
+        .map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to list the documents: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to list the documents.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        Ok(documents)
+    }
+
     /// Queries the database for chunks similar to the given query.
+    ///
+    /// When a cursor is provided, the query is skipped and the next page is
+    /// served from the ranking snapshot stored in the cursor instead.
     pub async fn create_query(
         &self,
         namespace: &Namespace,
         query: impl AsRef<str>,
-        k: u8,
-    ) -> Result<Vec<Chunk>, ErrorResponse> {
+        options: &QueryOptions,
+        cursor: Option<Cursor>,
+    ) -> Result<QueryResults<Chunk>, ErrorResponse> {
         let query = query.as_ref();
-        let model = namespace.config.embedding.model()?;
-        let embedding = model.generate(query).await?;
+        let k = options.k;
+        let (mut ranking, embedding) = match cursor {
+            Some(cursor) => (cursor.ranking, None),
+            None => {
+                let limit = QUERY_WINDOW.max(k);
+                let (ranking, embedding) =
+                    self.rank_chunks(namespace, query, limit, options).await?;
+
+                let ranking = ranking
+                    .into_iter()
+                    .map(|(id, score)| CursorEntry {
+                        id,
+                        score,
+                        chunks: Vec::new(),
+                    })
+                    .collect();
+
+                (ranking, Some(embedding))
+            },
+        };
+
+        let remaining = ranking.split_off(k.min(ranking.len()));
+        let digest = options.digest(query, CHUNK_SCOPE);
+        let next_cursor = self.next_cursor(namespace, digest, remaining);
+
+        let ids: Vec<ChunkID> = ranking.iter().map(|entry| entry.id).collect();
+        let mut results = self.retrieve_chunks(namespace, &ids).await?;
+        let chunks = results.iter_mut().collect();
+        let embedding = embedding.as_ref();
+        self.highlight_chunks(namespace, query, embedding, chunks, options)
+            .await?;
+
+        Ok(QueryResults {
+            results,
+            next_cursor,
+        })
+    }
+
+    /// Queries the database for documents relevant to the given query.
+    ///
+    /// The chunks matching the query are grouped by their document and the
+    /// document score is aggregated from the scores of its chunks. This means
+    /// that pagination with k and the cursor applies to documents, not chunks.
+    pub async fn create_document_query(
+        &self,
+        namespace: &Namespace,
+        query: impl AsRef<str>,
+        options: &QueryOptions,
+        document_options: &DocumentQueryOptions,
+        cursor: Option<Cursor>,
+    ) -> Result<QueryResults<DocumentResult>, ErrorResponse> {
+        let query = query.as_ref();
+        let k = options.k;
+        let (mut ranking, embedding) = match cursor {
+            Some(cursor) => (cursor.ranking, None),
+            None => {
+                // A document usually matches with multiple chunks, so we
+                // rank more chunks than the number of documents requested.
+                let limit = QUERY_WINDOW.max(k * DOCUMENT_CANDIDATES);
+                let (ranking, embedding) =
+                    self.rank_chunks(namespace, query, limit, options).await?;
+
+                let ranking = self
+                    .group_chunks(namespace, &ranking, document_options)
+                    .await?;
+
+                (ranking, Some(embedding))
+            },
+        };
+
+        let remaining = ranking.split_off(k.min(ranking.len()));
+        let digest = options.digest(query, &document_scope(document_options));
+        let next_cursor = self.next_cursor(namespace, digest, remaining);
+
+        // Only the passages of the documents on this page are retrieved.
+        let ids: Vec<ChunkID> = ranking
+            .iter()
+            .flat_map(|entry| entry.chunks.iter().copied())
+            .collect();
+
+        let mut chunks = self.retrieve_chunks(namespace, &ids).await?;
+        let passages = chunks.iter_mut().collect();
+        let embedding = embedding.as_ref();
+        self.highlight_chunks(namespace, query, embedding, passages, options)
+            .await?;
+
+        let mut chunks: HashMap<ChunkID, Chunk> =
+            chunks.into_iter().map(|chunk| (chunk.id, chunk)).collect();
 
         let schema = namespace.schema();
-        let semantic_results: Vec<ChunkID> = sqlx::query_scalar(&format!(
-            "SELECT id FROM {schema}.chunks
-            ORDER BY semantic_vector <=> $1::vector
-            LIMIT $2;",
+        let ids: Vec<DocumentID> =
+            ranking.iter().map(|entry| entry.id).collect();
+        let documents: Vec<Document> = sqlx::query_as(&format!(
+            "SELECT * FROM {schema}.documents
+            WHERE id = ANY($1);",
         ))
-        .bind(&embedding)
-        .bind(k as i32)
+        .bind(&ids)
         .fetch_all(&self.database)
         .await
         .map_err(|_e| {
             #[cfg(test)]
-            eprintln!("Failed to execute semantic search: {_e:?}");
+            eprintln!("Failed to retrieve the documents: {_e:?}");
             ErrorResponse {
                 code: StatusCode::INTERNAL_SERVER_ERROR,
-                message: "Failed to execute semantic search.".to_string(),
+                message: "Failed to retrieve the query results.".to_string(),
                 solution: None,
             }
         })?;
 
-        let language = "english";
-        let text_results: Vec<ChunkID> = sqlx::query_scalar(&format!(
-            "SELECT id FROM {schema}.chunks
-            WHERE text_vector @@ plainto_tsquery('{language}', $1)
-            ORDER BY ts_rank_cd(text_vector, plainto_tsquery('{language}', $1))
-            DESC LIMIT $2;",
+        let mut documents: HashMap<DocumentID, Document> = documents
+            .into_iter()
+            .map(|document| (document.id, document))
+            .collect();
+
+        let results = ranking
+            .into_iter()
+            .filter_map(|entry| {
+                let document = documents.remove(&entry.id)?;
+                let chunks = entry
+                    .chunks
+                    .iter()
+                    .filter_map(|id| chunks.remove(id))
+                    .collect();
+
+                Some(DocumentResult {
+                    document,
+                    score: entry.score,
+                    chunks,
+                })
+            })
+            .collect();
+
+        Ok(QueryResults {
+            results,
+            next_cursor,
+        })
+    }
+
+    /// Groups the ranked chunks by their document.
+    ///
+    /// The documents are sorted by their aggregated score and each of them
+    /// keeps its best ranked chunks, up to the number of passages, in order.
+    async fn group_chunks(
+        &self,
+        namespace: &Namespace,
+        ranking: &[(ChunkID, f32)],
+        document_options: &DocumentQueryOptions,
+    ) -> Result<Vec<CursorEntry>, ErrorResponse> {
+        let schema = namespace.schema();
+        let ids: Vec<ChunkID> = ranking.iter().map(|(id, _)| *id).collect();
+        let documents: Vec<(ChunkID, DocumentID)> = sqlx::query_as(&format!(
+            "SELECT id, document_id FROM {schema}.chunks
+            WHERE id = ANY($1);",
         ))
-        .bind(query)
-        .bind(k as i32)
+        .bind(&ids)
         .fetch_all(&self.database)
         .await
         .map_err(|_e| {
             #[cfg(test)]
-            eprintln!("Failed when performing full-text search: {_e:?}");
+            eprintln!("Failed to group the query results: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: "Failed to retrieve the query results.".to_string(),
+                solution: None,
+            }
+        })?;
+
+        let documents: HashMap<ChunkID, DocumentID> =
+            documents.into_iter().collect();
+
+        let mut groups: Vec<DocumentGroup> = Vec::new();
+        let mut indices: HashMap<DocumentID, usize> = HashMap::new();
+        for (rank, (id, score)) in ranking.iter().enumerate() {
+            let document_id = match documents.get(id) {
+                Some(document_id) => *document_id,
+                None => continue,
+            };
+
+            let index = *indices.entry(document_id).or_insert_with(|| {
+                groups.push(DocumentGroup::new(document_id));
+                groups.len() - 1
+            });
+
+            let group = &mut groups[index];
+            group.hits.push((rank, *score));
+            if group.chunks.len() < document_options.passages {
+                group.chunks.push(*id);
+            }
+        }
+
+        for group in groups.iter_mut() {
+            let aggregation = document_options.aggregation;
+            group.score = aggregation.aggregate(&group.hits);
+        }
+
+        groups.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+        });
+
+        let groups = groups
+            .into_iter()
+            .map(|group| CursorEntry {
+                id: group.id,
+                score: group.score,
+                chunks: group.chunks,
+            })
+            .collect();
+
+        Ok(groups)
+    }
+
+    /// Creates the cursor token for the remaining results of a query.
+    ///
+    /// Returns None when there are no results left to page through.
+    fn next_cursor(
+        &self,
+        namespace: &Namespace,
+        query: [u8; 32],
+        ranking: Vec<CursorEntry>,
+    ) -> Option<String> {
+        if ranking.is_empty() {
+            return None;
+        }
+
+        let cursor = Cursor {
+            namespace: namespace.id,
+            query,
+            ranking,
+        };
+
+        Some(cursor.encode(self.config.secret.as_bytes()))
+    }
+
+    /// Ranks the chunks relevant to the query using hybrid search.
+    ///
+    /// This method runs the semantic search and the full-text search with the
+    /// given limit each and fuses the results with RRF. When fuzzy matching is
+    /// enabled, trigram search is fused as well. The returned chunk IDs are
+    /// sorted by their fused score in descending order and returned with the
+    /// embedding of the query.
+    async fn rank_chunks(
+        &self,
+        namespace: &Namespace,
+        query: impl AsRef<str>,
+        limit: usize,
+        options: &QueryOptions,
+    ) -> Result<(Vec<(ChunkID, f32)>, DenseVector), ErrorResponse> {
+        let query = query.as_ref();
+        let model = namespace.config.embedding.model()?;
+        let embedding = model.generate(query).await?;
+
+        let schema = namespace.schema();
+        let semantic_error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to execute semantic search: {_e:?}");
             ErrorResponse {
                 code: StatusCode::INTERNAL_SERVER_ERROR,
-                message: "Failed when performing full-text search.".to_string(),
+                message: "Failed to execute semantic search.".to_string(),
                 solution: None,
             }
+        };
+
+        // The query-time parameters of the index are set locally so they
+        // only apply to the semantic search within this transaction.
+        let candidates = namespace.semantic_candidates(limit);
+        let index = &namespace.config.index;
+        let parameters = index.search_parameters(options, candidates)?;
+
+        let mut tx = self.database.begin().await.map_err(semantic_error)?;
+        for parameter in parameters.iter() {
+            sqlx::query(parameter)
+                .execute(&mut *tx)
+                .await
+                .map_err(semantic_error)?;
+        }
+
+        let filter = &options.filter;
+        let condition = filter.condition(&schema, 3);
+        let semantic_sql = namespace.semantic_search_sql(&condition);
+        let semantic_query = sqlx::query_scalar(&semantic_sql)
+            .bind(&embedding)
+            .bind(limit as i32);
+
+        let semantic_results: Vec<ChunkID> = filter
+            .bind(semantic_query)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(semantic_error)?;
+
+        tx.commit().await.map_err(semantic_error)?;
+
+        let tsquery = options.syntax.tsquery(LANGUAGE, "$1", options.prefix);
+        let text_sql = format!(
+            "SELECT id FROM {schema}.chunks
+            WHERE text_vector @@ {tsquery} AND {condition}
+            ORDER BY ts_rank_cd(text_vector, {tsquery})
+            DESC LIMIT $2;",
+        );
+
+        let text_query =
+            sqlx::query_scalar(&text_sql).bind(query).bind(limit as i32);
+
+        let text_results: Vec<ChunkID> = filter
+            .bind(text_query)
+            .fetch_all(&self.database)
+            .await
+            .map_err(|e| {
+                #[cfg(test)]
+                eprintln!("Failed when performing full-text search: {e:?}");
+                keyword_query_error(
+                    &e,
+                    "Failed when performing full-text search.",
+                )
+            })?;
+
+        let mut lists = vec![semantic_results, text_results];
+        if options.fuzzy {
+            let results =
+                self.trigram_search(namespace, query, limit, filter).await?;
+            lists.push(results);
+        }
+
+        let reranker = Reranker::new(lists);
+        Ok((reranker.rrf(RRF_CONSTANT, limit), embedding))
+    }
+
+    /// Searches for chunks containing words similar to the query terms.
+    ///
+    /// This is useful to match misspelled terms, OCR errors, and identifiers
+    /// which are not handled well by the full-text and semantic search.
+    async fn trigram_search(
+        &self,
+        namespace: &Namespace,
+        query: &str,
+        limit: usize,
+        filter: &ChunkFilter,
+    ) -> Result<Vec<ChunkID>, ErrorResponse> {
+        let schema = namespace.schema();
+        let condition = filter.condition(&schema, 3);
+        let sql = format!(
+            "SELECT id FROM {schema}.chunks
+            WHERE $1 <% content AND {condition}
+            ORDER BY word_similarity($1, content) DESC
+            LIMIT $2;",
+        );
+
+        let trigram_query =
+            sqlx::query_scalar(&sql).bind(query).bind(limit as i32);
+
+        let results: Vec<ChunkID> = filter
+            .bind(trigram_query)
+            .fetch_all(&self.database)
+            .await
+            .map_err(|_e| {
+                #[cfg(test)]
+                eprintln!("Failed to execute trigram search: {_e:?}");
+                ErrorResponse {
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                    message: "Failed to execute trigram search.".to_string(),
+                    solution: None,
+                }
+            })?;
+
+        Ok(results)
+    }
+
+    /// Adds snippets with the matching query terms highlighted to the chunks.
+    ///
+    /// For chunks matching the full-text query, the snippet is generated by
+    /// Postgres. Otherwise, the snippet is the sentence window sharing the
+    /// most words with the query. If semantic snippets are enabled, the top
+    /// chunks use the sentence window most similar to the query instead.
+    /// - embedding: Embedding of the query if it was already generated.
+    async fn highlight_chunks(
+        &self,
+        namespace: &Namespace,
+        query: &str,
+        embedding: Option<&DenseVector>,
+        mut chunks: Vec<&mut Chunk>,
+        options: &QueryOptions,
+    ) -> Result<(), ErrorResponse> {
+        let highlight = match &options.highlight {
+            Some(highlight) => highlight,
+            None => return Ok(()),
+        };
+
+        let schema = namespace.schema();
+        let tsquery = options.syntax.tsquery(LANGUAGE, "$2", options.prefix);
+        let ids: Vec<ChunkID> = chunks.iter().map(|chunk| chunk.id).collect();
+        let snippets: Vec<(ChunkID, Option<String>)> = sqlx::query_as(
+            &format!(
+                "SELECT id,
+            CASE WHEN text_vector @@ {tsquery}
+            THEN ts_headline('{LANGUAGE}', content, {tsquery}, $3)
+            END AS snippet
+            FROM {schema}.chunks
+            WHERE id = ANY($1);",
+            ),
+        )
+        .bind(&ids)
+        .bind(query)
+        .bind(highlight.headline_options())
+        .fetch_all(&self.database)
+        .await
+        .map_err(|e| {
+            #[cfg(test)]
+            eprintln!("Failed to highlight the query results: {e:?}");
+            keyword_query_error(&e, "Failed to highlight the query results.")
         })?;
 
-        let reranker = Reranker::new(vec![semantic_results, text_results]);
-        let results = reranker.rrf(60, k);
+        let mut snippets: HashMap<ChunkID, String> = snippets
+            .into_iter()
+            .filter_map(|(id, snippet)| Some((id, snippet?)))
+            .collect();
+
+        let max_words = highlight.fragment_words.max(1) as usize
+            * highlight.max_fragments.max(1) as usize;
+
+        // Sentence windows of the top chunks without a matching query term
+        // to be embedded. Their order follows the ranking of the chunks.
+        let mut windows: Vec<(usize, Vec<String>)> = Vec::new();
+        for (index, chunk) in chunks.iter_mut().enumerate() {
+            if let Some(snippet) = snippets.remove(&chunk.id) {
+                chunk.snippet = Some(snippet);
+                continue;
+            }
+
+            let chunk_windows = sentence_windows(&chunk.content, max_words);
+            if highlight.semantic && windows.len() < SEMANTIC_SNIPPETS {
+                windows.push((index, chunk_windows));
+                continue;
+            }
+
+            let best = most_overlapping(query, &chunk_windows);
+            chunk.snippet =
+                best.and_then(|best| chunk_windows.into_iter().nth(best));
+        }
+
+        if windows.is_empty() {
+            return Ok(());
+        }
 
+        // The windows are embedded in one request along with the query if
+        // it wasn't embedded by the search.
+        let mut texts = Vec::new();
+        if embedding.is_none() {
+            texts.push(query.to_string());
+        }
+
+        for (_, windows) in windows.iter() {
+            texts.extend(windows.iter().cloned());
+        }
+
+        let model = namespace.config.embedding.model()?;
+        let mut embeddings = model.generate_batch(&texts).await?.into_iter();
+        let query_embedding = match embedding {
+            Some(embedding) => embedding.clone(),
+            None => embeddings.next().unwrap_or_default(),
+        };
+
+        for (index, windows) in windows {
+            let window_embeddings: Vec<DenseVector> =
+                embeddings.by_ref().take(windows.len()).collect();
+
+            let best = most_similar(&query_embedding, &window_embeddings);
+            chunks[index].snippet =
+                best.and_then(|best| windows.into_iter().nth(best));
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the chunks with the given IDs in the same order.
+    async fn retrieve_chunks(
+        &self,
+        namespace: &Namespace,
+        ids: &[ChunkID],
+    ) -> Result<Vec<Chunk>, ErrorResponse> {
+        let schema = namespace.schema();
+        let columns = Chunk::COLUMNS;
         let chunks: Vec<Chunk> = sqlx::query_as(&format!(
-            "SELECT * FROM {schema}.chunks
+            "SELECT {columns} FROM {schema}.chunks
             WHERE id = ANY($1);",
         ))
-        .bind(&results)
+        .bind(ids)
         .fetch_all(&self.database)
         .await
         .map_err(|_e| {
@@ -341,6 +2038,67 @@ impl Service {
             }
         })?;
 
+        // The database doesn't preserve the order of the IDs in the query.
+        let mut chunks: HashMap<ChunkID, Chunk> =
+            chunks.into_iter().map(|chunk| (chunk.id, chunk)).collect();
+        let chunks = ids.iter().filter_map(|id| chunks.remove(id)).collect();
+
         Ok(chunks)
     }
 }
+
+/// Returns the key of the advisory lock held by maintenance operations.
+fn maintenance_lock(namespace: &Namespace) -> String {
+    format!("maintenance:{}", namespace.id)
+}
+
+/// Returns the cursor scope of a document query.
+///
+/// The cursor keeps the aggregated documents with their passages, so the
+/// options shaping them are part of the scope.
+fn document_scope(options: &DocumentQueryOptions) -> String {
+    let aggregation = &options.aggregation;
+    format!("{DOCUMENT_SCOPE}:{}:{aggregation:?}", options.passages)
+}
+
+/// Maps the error of a statement parsing the keyword query.
+///
+/// Only raw queries can fail to be parsed by Postgres, which is reported as
+/// a bad request. Other errors are reported with the given message.
+fn keyword_query_error(error: &sqlx::Error, message: &str) -> ErrorResponse {
+    let code = error.as_database_error().and_then(|e| e.code());
+    if code.as_deref() == Some(SYNTAX_ERROR) {
+        return ErrorResponse {
+            code: StatusCode::BAD_REQUEST,
+            message: "Please provide a valid keyword query.".to_string(),
+            solution: Some(String::from(
+                "Check the query syntax of the to_tsquery function.",
+            )),
+        };
+    }
+
+    ErrorResponse {
+        code: StatusCode::INTERNAL_SERVER_ERROR,
+        message: message.to_string(),
+        solution: None,
+    }
+}
+
+/// Chunks of a document matched by a query before aggregation.
+struct DocumentGroup {
+    id: DocumentID,
+    score: f32,
+    hits: Vec<(usize, f32)>,
+    chunks: Vec<ChunkID>,
+}
+
+impl DocumentGroup {
+    fn new(id: DocumentID) -> Self {
+        DocumentGroup {
+            id,
+            score: 0.0,
+            hits: Vec::new(),
+            chunks: Vec::new(),
+        }
+    }
+}
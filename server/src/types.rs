@@ -3,11 +3,13 @@ use crate::protos;
 use crate::services::interface::ErrorResponse;
 use axum::http::StatusCode;
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_json::Value;
-use sqlx::postgres::PgRow;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::query::QueryScalar;
 use sqlx::Type;
-use sqlx::{FromRow, PgPool, Row};
+use sqlx::{FromRow, PgPool, Postgres, Row};
 use std::net::SocketAddr;
 use tonic::Status;
 use uuid::Uuid;
@@ -17,10 +19,41 @@ pub type WorkerID = Uuid;
 pub type DocumentID = Uuid;
 pub type ChunkID = Uuid;
 
-#[derive(Debug, Clone)]
+/// Constant added to the ranks when fusing them with RRF.
+pub const RRF_CONSTANT: usize = 60;
+
+/// Number of candidates per result to re-rank with binary quantization.
+pub const RERANK_CANDIDATES: usize = 4;
+
+/// Name of the index of the semantic vectors in the namespace schema.
+pub const SEMANTIC_INDEX: &str = "chunks_semantic_vector_idx";
+
+// Maximum ef_search supported by pgvector.
+const MAX_EF_SEARCH: u16 = 1000;
+
+// Number of documents whose chunks are backfilled per statement when a
+// namespace is upgraded.
+const BACKFILL_DOCUMENTS: i64 = 100;
+
+/// Highest priority of an extraction task.
+pub const MAX_PRIORITY: i32 = 9;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Worker {
     pub id: WorkerID,
     pub address: SocketAddr,
+    pub status: WorkerStatus,
+    /// Task that the worker reported in its last heartbeat.
+    pub task: Option<WorkerTask>,
+    /// Whether the worker stops taking new tasks.
+    pub draining: bool,
+    /// Number of documents processed since the worker started.
+    pub completed: i64,
+    pub failed: i64,
+    /// Documents completed per hour since the worker registered.
+    pub throughput: f64,
+    pub registered_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
 }
 
 impl TryFrom<protos::RegisterWorkerRequest> for Worker {
@@ -37,15 +70,185 @@ impl TryFrom<protos::RegisterWorkerRequest> for Worker {
             Status::invalid_argument(message)
         })?;
 
-        Ok(Worker { id, address })
+        Ok(Worker {
+            id,
+            address,
+            status: WorkerStatus::Idle,
+            task: None,
+            draining: false,
+            completed: 0,
+            failed: 0,
+            throughput: 0.0,
+            registered_at: Utc::now(),
+            last_seen_at: Utc::now(),
+        })
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl<'r> FromRow<'r, PgRow> for Worker {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        let address: String = row.try_get("address")?;
+        let address =
+            address.parse().map_err(|e| sqlx::Error::ColumnDecode {
+                index: String::from("address"),
+                source: Box::new(e),
+            })?;
+
+        let namespace: Option<String> = row.try_get("namespace")?;
+        let document_id: Option<DocumentID> = row.try_get("document_id")?;
+        let task = match (namespace, document_id) {
+            (Some(namespace), Some(document_id)) => Some(WorkerTask {
+                namespace,
+                document_id,
+            }),
+            _ => None,
+        };
+
+        let completed: i64 = row.try_get("completed")?;
+        let registered_at: DateTime<Utc> = row.try_get("registered_at")?;
+        let last_seen_at: DateTime<Utc> = row.try_get("last_seen_at")?;
+
+        // Workers registered less than a minute ago have a throughput based
+        // on a minute to avoid inflated values.
+        let seconds = (last_seen_at - registered_at).num_seconds().max(60);
+        let throughput = completed as f64 * 3600.0 / seconds as f64;
+
+        Ok(Worker {
+            id: row.try_get("id")?,
+            address,
+            status: row.try_get("status")?,
+            task,
+            draining: row.try_get("draining")?,
+            completed,
+            failed: row.try_get("failed")?,
+            throughput,
+            registered_at,
+            last_seen_at,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Type)]
+#[sqlx(type_name = "worker_status", rename_all = "lowercase")]
+pub enum WorkerStatus {
+    Idle,
+    Busy,
+}
+
+impl From<protos::WorkerStatus> for WorkerStatus {
+    fn from(value: protos::WorkerStatus) -> Self {
+        match value {
+            protos::WorkerStatus::Idle => Self::Idle,
+            protos::WorkerStatus::Busy => Self::Busy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerTask {
+    pub namespace: String,
+    pub document_id: DocumentID,
+}
+
+/// Status of a worker reported with a heartbeat.
+#[derive(Debug, Clone)]
+pub struct WorkerHeartbeat {
+    pub id: WorkerID,
+    pub status: WorkerStatus,
+    pub task: Option<WorkerTask>,
+    pub completed: i64,
+    pub failed: i64,
+}
+
+impl TryFrom<protos::HeartbeatRequest> for WorkerHeartbeat {
+    type Error = Status;
+    fn try_from(value: protos::HeartbeatRequest) -> Result<Self, Self::Error> {
+        let id = Uuid::parse_str(&value.worker_id).map_err(|_| {
+            Status::invalid_argument("Worker ID must be a valid UUID.")
+        })?;
+
+        let status = WorkerStatus::from(value.status());
+        let task = match value.task {
+            Some(task) => {
+                let document_id =
+                    Uuid::parse_str(&task.document_id).map_err(|_| {
+                        let message = "Document ID must be a valid UUID.";
+                        Status::invalid_argument(message)
+                    })?;
+
+                Some(WorkerTask {
+                    namespace: task.namespace,
+                    document_id,
+                })
+            },
+            None => None,
+        };
+
+        Ok(WorkerHeartbeat {
+            id,
+            status,
+            task,
+            completed: value.completed as i64,
+            failed: value.failed as i64,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct ExtractionTask {
     pub namespace: String,
     pub document_key: String,
     pub document_id: DocumentID,
+    /// Priority among the tasks of the namespace, higher first.
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl From<ExtractionTask> for protos::Task {
+    fn from(value: ExtractionTask) -> Self {
+        protos::Task {
+            namespace: value.namespace,
+            document_key: value.document_key,
+            document_id: value.document_id.to_string(),
+        }
+    }
+}
+
+/// Extraction task leased to a worker until the lease expires.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Lease {
+    #[sqlx(flatten)]
+    pub task: ExtractionTask,
+    pub worker_id: WorkerID,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Extraction task waiting in the outbox to be sent to the queue.
+///
+/// New tasks are published, tasks with attempts are retried and tasks with
+/// an error code are dead-lettered.
+#[derive(Debug, Clone, FromRow)]
+pub struct OutboxTask {
+    #[sqlx(flatten)]
+    pub task: ExtractionTask,
+    pub attempts: i32,
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+    pub claimed_until: Option<DateTime<Utc>>,
+}
+
+/// Extraction task moved to the dead-letter queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    #[serde(flatten)]
+    pub task: ExtractionTask,
+    /// Number of processing attempts before the task was dead-lettered.
+    pub attempts: u32,
+    /// Reason the task was dead-lettered.
+    ///
+    /// Tasks rejected from the queue by a consumer have no reason.
+    pub error: Option<DocumentError>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -93,27 +296,343 @@ impl EmbeddingConfig {
     }
 }
 
+/// Mode of the iterative index scans of pgvector.
+///
+/// With iterative scans, the index is scanned further when the results are
+/// not enough after filtering. Strict order preserves the exact distance
+/// order while relaxed order allows slightly out-of-order results.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum IterativeScan {
+    #[default]
+    Off,
+    StrictOrder,
+    RelaxedOrder,
+}
+
+impl From<IterativeScan> for &str {
+    fn from(value: IterativeScan) -> Self {
+        match value {
+            IterativeScan::Off => "off",
+            IterativeScan::StrictOrder => "strict_order",
+            IterativeScan::RelaxedOrder => "relaxed_order",
+        }
+    }
+}
+
+/// Configuration of the HNSW index.
+/// - m, ef_construction: Build-time parameters of the index.
+/// - ef_search, iterative_scan: Default query-time parameters.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct IndexConfig {
+#[serde(default)]
+pub struct HnswConfig {
     pub m: u8,
     pub ef_construction: u16,
+    pub ef_search: u16,
+    pub iterative_scan: IterativeScan,
 }
 
-impl Default for IndexConfig {
+impl Default for HnswConfig {
     fn default() -> Self {
-        IndexConfig {
+        HnswConfig {
             m: 32,
             ef_construction: 128,
+            ef_search: 40,
+            iterative_scan: IterativeScan::default(),
+        }
+    }
+}
+
+/// Configuration of the IVFFlat index.
+/// - lists: Build-time number of inverted lists of the index.
+/// - probes, iterative_scan: Default query-time parameters.
+///
+/// IVFFlat only supports the relaxed order for iterative index scans.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IvfFlatConfig {
+    pub lists: u32,
+    pub probes: u32,
+    pub iterative_scan: IterativeScan,
+}
+
+impl Default for IvfFlatConfig {
+    fn default() -> Self {
+        IvfFlatConfig {
+            lists: 100,
+            probes: 10,
+            iterative_scan: IterativeScan::default(),
+        }
+    }
+}
+
+/// Configuration of the index for the semantic vectors.
+///
+/// Configurations without a type are HNSW, as they were before the index
+/// type could be chosen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", remote = "Self")]
+pub enum IndexConfig {
+    Hnsw(HnswConfig),
+    IvfFlat(IvfFlatConfig),
+}
+
+impl Serialize for IndexConfig {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        IndexConfig::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for IndexConfig {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        if let Value::Object(map) = &mut value {
+            map.entry("type").or_insert_with(|| Value::from("Hnsw"));
+        }
+
+        IndexConfig::deserialize(value).map_err(de::Error::custom)
+    }
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        IndexConfig::Hnsw(HnswConfig::default())
+    }
+}
+
+impl IndexConfig {
+    /// Validates the index configuration.
+    pub fn validate(&self) -> Result<(), ErrorResponse> {
+        let options = QueryOptions::default();
+        self.search_parameters(&options, options.k).map(|_| ())
+    }
+
+    /// Returns the index method and its build-time parameters for SQL.
+    pub fn method(&self) -> (&str, String) {
+        match self {
+            Self::Hnsw(config) => {
+                let HnswConfig {
+                    m, ef_construction, ..
+                } = config;
+                (
+                    "HNSW",
+                    format!("m = {m}, ef_construction = {ef_construction}"),
+                )
+            },
+            Self::IvfFlat(config) => {
+                ("IVFFLAT", format!("lists = {}", config.lists))
+            },
+        }
+    }
+
+    /// Returns the statements to set the query-time parameters of the index.
+    /// - limit: Number of rows the query reads from the index.
+    ///
+    /// The parameters from the query options override the default parameters
+    /// of the index. Providing parameters that don't apply to the index type
+    /// is considered invalid.
+    pub fn search_parameters(
+        &self,
+        options: &QueryOptions,
+        limit: usize,
+    ) -> Result<Vec<String>, ErrorResponse> {
+        let invalid = |message: &str| ErrorResponse {
+            code: StatusCode::BAD_REQUEST,
+            message: message.to_string(),
+            solution: Some(String::from(
+                "Check the index type of the namespace configuration.",
+            )),
+        };
+
+        let (method, iterative_scan, mut parameters) = match self {
+            Self::Hnsw(config) => {
+                if options.probes.is_some() {
+                    return Err(invalid("Probes only apply to IVFFlat index."));
+                }
+
+                let ef_search = options.ef_search.unwrap_or(config.ef_search);
+                if !(1..=MAX_EF_SEARCH).contains(&ef_search) {
+                    return Err(ErrorResponse {
+                        code: StatusCode::BAD_REQUEST,
+                        message: "The ef_search must be between 1 and 1000."
+                            .to_string(),
+                        solution: Some(String::from(
+                            "Use a higher ef_search to retrieve more results.",
+                        )),
+                    });
+                }
+
+                // HNSW returns at most ef_search rows from a scan, so it's
+                // raised to the limit. Beyond the maximum ef_search, only an
+                // iterative scan enabled by the namespace or the query can
+                // reach the limit.
+                let limit = limit.min(u16::MAX as usize) as u16;
+                let ef_search = ef_search.max(limit).min(MAX_EF_SEARCH);
+                let scan = options.iterative_scan(config.iterative_scan);
+
+                let parameter =
+                    format!("SET LOCAL hnsw.ef_search = {ef_search}");
+                ("hnsw", scan, vec![parameter])
+            },
+            Self::IvfFlat(config) => {
+                if options.ef_search.is_some() {
+                    return Err(invalid("The ef_search only applies to HNSW."));
+                }
+
+                let probes = options.probes.unwrap_or(config.probes);
+                if probes == 0 || probes > config.lists {
+                    return Err(ErrorResponse {
+                        code: StatusCode::BAD_REQUEST,
+                        message: "Probes must be between 1 and the lists."
+                            .to_string(),
+                        solution: None,
+                    });
+                }
+
+                let scan = options.iterative_scan(config.iterative_scan);
+                if matches!(scan, IterativeScan::StrictOrder) {
+                    return Err(invalid(
+                        "IVFFlat doesn't support strict order.",
+                    ));
+                }
+
+                let parameter = format!("SET LOCAL ivfflat.probes = {probes}");
+                ("ivfflat", scan, vec![parameter])
+            },
+        };
+
+        // Older versions of pgvector don't support iterative index scans, so
+        // we only set the parameter when it's enabled.
+        if !matches!(iterative_scan, IterativeScan::Off) {
+            let mode: &str = iterative_scan.into();
+            let parameter =
+                format!("SET LOCAL {method}.iterative_scan = {mode}");
+            parameters.push(parameter);
+        }
+
+        Ok(parameters)
+    }
+}
+
+/// Type used to store the semantic vectors.
+/// - Vector: Single-precision floats up to 2,000 indexed dimensions.
+/// - Halfvec: Half-precision floats up to 4,000 indexed dimensions.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum VectorStorage {
+    #[default]
+    Vector,
+    Halfvec,
+}
+
+impl From<VectorStorage> for &str {
+    fn from(value: VectorStorage) -> Self {
+        match value {
+            VectorStorage::Vector => "vector",
+            VectorStorage::Halfvec => "halfvec",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum DistanceMetric {
+    #[default]
+    Cosine,
+    InnerProduct,
+    L2,
+}
+
+/// Quantization of the semantic vectors in the index.
+///
+/// With binary quantization, the index is built on the binary representation
+/// of the vectors which supports up to 64,000 dimensions. The candidates from
+/// the index are re-ranked with the full vectors.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Quantization {
+    #[default]
+    None,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VectorConfig {
+    pub storage: VectorStorage,
+    pub metric: DistanceMetric,
+    pub quantization: Quantization,
+}
+
+impl VectorConfig {
+    /// Validates that the vectors with the dimension can be indexed.
+    pub fn validate(&self, dimension: usize) -> Result<(), ErrorResponse> {
+        let max_dimension = match (self.quantization, self.storage) {
+            (Quantization::Binary, _) => 64000,
+            (Quantization::None, VectorStorage::Vector) => 2000,
+            (Quantization::None, VectorStorage::Halfvec) => 4000,
+        };
+
+        if dimension > max_dimension {
+            return Err(ErrorResponse {
+                code: StatusCode::BAD_REQUEST,
+                message: format!(
+                    "The index supports up to {max_dimension} dimensions."
+                ),
+                solution: Some(String::from(
+                    "Please use the halfvec storage or binary quantization.",
+                )),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the distance operator of the metric.
+    pub fn operator(&self) -> &str {
+        match self.metric {
+            DistanceMetric::Cosine => "<=>",
+            DistanceMetric::InnerProduct => "<#>",
+            DistanceMetric::L2 => "<->",
+        }
+    }
+
+    /// Returns the expression and operator class to index the vectors.
+    pub fn index_expression(&self, dimension: usize) -> String {
+        if let Quantization::Binary = self.quantization {
+            let column = "binary_quantize(semantic_vector)";
+            return format!("({column}::bit({dimension})) bit_hamming_ops");
         }
+
+        let storage: &str = self.storage.into();
+        let metric = match self.metric {
+            DistanceMetric::Cosine => "cosine",
+            DistanceMetric::InnerProduct => "ip",
+            DistanceMetric::L2 => "l2",
+        };
+
+        format!("semantic_vector {storage}_{metric}_ops")
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct NamespaceConfig {
     pub index: IndexConfig,
+    #[serde(default)]
+    pub vector: VectorConfig,
     pub embedding: EmbeddingConfig,
 }
 
+impl NamespaceConfig {
+    /// Validates the namespace configuration.
+    pub fn validate(&self) -> Result<(), ErrorResponse> {
+        self.embedding.model()?;
+        self.index.validate()?;
+        self.vector.validate(self.embedding.dimension())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Namespace {
     pub id: NamespaceID,
@@ -151,14 +670,174 @@ impl Namespace {
         format!("ns_{schema}")
     }
 
-    /// Provisions the namespace with the required schema tables and indexes.
-    pub async fn provision(&self, pool: &PgPool) -> Result<(), ErrorResponse> {
-        let m = self.config.index.m;
-        let ef_construction = self.config.index.ef_construction;
-        let dimension = self.config.embedding.dimension();
+    /// Returns the key for a document of the namespace in the S3 bucket.
+    pub fn document_key(&self, id: &DocumentID) -> String {
+        format!("{}/{}.pdf", self.schema(), id)
+    }
 
+    /// Provisions a new namespace with the required schema tables and indexes.
+    ///
+    /// The indexes are built in the same transaction as the tables, which is
+    /// only cheap while the tables are empty. Existing namespaces are brought
+    /// up to date with the upgrade method instead.
+    pub async fn provision(&self, pool: &PgPool) -> Result<(), ErrorResponse> {
         let schema = self.schema();
+        let tables = self.tables_sql();
+        let indexes: Vec<String> = self
+            .indexes_sql(false)
+            .into_iter()
+            .map(|(_, sql)| sql)
+            .collect();
+
+        let indexes = indexes.join(";\n\n");
         let query = format!(
+            "{tables}
+
+            ALTER TABLE {schema}.chunks
+            ALTER COLUMN sequence SET NOT NULL;
+
+            {indexes};"
+        );
+
+        sqlx::raw_sql(&query).execute(pool).await.map_err(|_e| {
+            #[cfg(test)]
+            eprintln!("Failed to provision the namespace: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to provision the namespace"),
+                solution: None,
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Upgrades an existing namespace to the tables and indexes of the
+    /// current version.
+    ///
+    /// New columns have constant defaults so adding them doesn't rewrite the
+    /// tables. The chunks are backfilled in batches and the indexes are built
+    /// concurrently, so the namespace stays writable during the upgrade.
+    pub async fn upgrade(&self, pool: &PgPool) -> Result<(), ErrorResponse> {
+        let error = |_e: sqlx::Error| {
+            #[cfg(test)]
+            eprintln!("Failed to upgrade the namespace: {_e:?}");
+            ErrorResponse {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                message: String::from("Failed to upgrade the namespace"),
+                solution: None,
+            }
+        };
+
+        let schema = self.schema();
+        sqlx::raw_sql(&self.tables_sql())
+            .execute(pool)
+            .await
+            .map_err(error)?;
+
+        // Chunks created before the sequence was introduced are numbered
+        // by their page so every chunk has a sequence within its document.
+        let backfill = format!(
+            "UPDATE {schema}.chunks AS chunks
+            SET sequence = numbered.sequence
+            FROM (
+                SELECT id, ROW_NUMBER() OVER (
+                    PARTITION BY document_id ORDER BY page, id
+                ) - 1 AS sequence
+                FROM {schema}.chunks
+                WHERE sequence IS NULL
+                AND document_id IN (
+                    SELECT DISTINCT document_id FROM {schema}.chunks
+                    WHERE sequence IS NULL
+                    LIMIT $1
+                )
+            ) AS numbered
+            WHERE chunks.id = numbered.id;"
+        );
+
+        loop {
+            let result = sqlx::query(&backfill)
+                .bind(BACKFILL_DOCUMENTS)
+                .execute(pool)
+                .await
+                .map_err(error)?;
+
+            if result.rows_affected() == 0 {
+                break;
+            }
+        }
+
+        let not_null: bool = sqlx::query_scalar(
+            "SELECT attnotnull FROM pg_attribute
+            WHERE attrelid = $1::regclass
+            AND attname = 'sequence';",
+        )
+        .bind(format!("{schema}.chunks"))
+        .fetch_one(pool)
+        .await
+        .map_err(error)?;
+
+        // Validating a check constraint doesn't block writes and lets
+        // Postgres set the column as not null without scanning the table.
+        if !not_null {
+            let statements = [
+                "DROP CONSTRAINT IF EXISTS chunks_sequence_not_null",
+                "ADD CONSTRAINT chunks_sequence_not_null
+                CHECK (sequence IS NOT NULL) NOT VALID",
+                "VALIDATE CONSTRAINT chunks_sequence_not_null",
+                "ALTER COLUMN sequence SET NOT NULL",
+                "DROP CONSTRAINT chunks_sequence_not_null",
+            ];
+
+            for statement in statements {
+                let query = format!("ALTER TABLE {schema}.chunks {statement};");
+                sqlx::raw_sql(&query).execute(pool).await.map_err(error)?;
+            }
+        }
+
+        let indexes = self.indexes_sql(true);
+        let names: Vec<&str> = indexes.iter().map(|(name, _)| *name).collect();
+
+        // A concurrent build that failed leaves an invalid index behind
+        // which would be skipped by IF NOT EXISTS.
+        let invalid: Vec<String> = sqlx::query_scalar(
+            "SELECT class.relname FROM pg_index AS index
+            JOIN pg_class AS class ON class.oid = index.indexrelid
+            JOIN pg_namespace AS namespace
+            ON namespace.oid = class.relnamespace
+            WHERE namespace.nspname = $1
+            AND class.relname = ANY($2)
+            AND NOT index.indisvalid;",
+        )
+        .bind(&schema)
+        .bind(&names)
+        .fetch_all(pool)
+        .await
+        .map_err(error)?;
+
+        for name in invalid {
+            let query = format!("DROP INDEX CONCURRENTLY {schema}.{name};");
+            sqlx::raw_sql(&query).execute(pool).await.map_err(error)?;
+        }
+
+        // Concurrent builds can't run in a transaction, so each index is
+        // built with its own statement.
+        for (_, sql) in indexes {
+            sqlx::raw_sql(&sql).execute(pool).await.map_err(error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the SQL statements to create the tables of the namespace.
+    ///
+    /// Columns added after the tables are created are added separately so
+    /// that existing namespaces get them when they are upgraded.
+    fn tables_sql(&self) -> String {
+        let dimension = self.config.embedding.dimension();
+        let storage: &str = self.config.vector.storage.into();
+        let schema = self.schema();
+        format!(
             "CREATE SCHEMA IF NOT EXISTS {schema};
 
             CREATE TABLE IF NOT EXISTS {schema}.documents (
@@ -169,15 +848,12 @@ impl Namespace {
                 created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
             );
 
-            CREATE INDEX IF NOT EXISTS documents_status_idx
-            ON {schema}.documents (status);
-
             CREATE TABLE IF NOT EXISTS {schema}.chunks (
                 id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
                 document_id UUID NOT NULL,
                 page INTEGER,
                 content TEXT NOT NULL,
-                semantic_vector VECTOR({dimension}) NOT NULL,
+                semantic_vector {storage}({dimension}) NOT NULL,
                 text_vector TSVECTOR NOT NULL,
 
                 FOREIGN KEY (document_id)
@@ -185,25 +861,161 @@ impl Namespace {
                 ON DELETE CASCADE
             );
 
-            CREATE INDEX IF NOT EXISTS chunks_semantic_vector_idx
-            ON {schema}.chunks USING HNSW (semantic_vector vector_cosine_ops)
-            WITH (m = {m}, ef_construction = {ef_construction});
+            ALTER TABLE {schema}.documents
+            ADD COLUMN IF NOT EXISTS title TEXT,
+            ADD COLUMN IF NOT EXISTS author TEXT,
+            ADD COLUMN IF NOT EXISTS page_count INTEGER,
+            ADD COLUMN IF NOT EXISTS language TEXT,
+            ADD COLUMN IF NOT EXISTS ocr BOOLEAN,
+            ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0,
+            ADD COLUMN IF NOT EXISTS started_at TIMESTAMPTZ,
+            ADD COLUMN IF NOT EXISTS error_code TEXT,
+            ADD COLUMN IF NOT EXISTS error_message TEXT,
+            ADD COLUMN IF NOT EXISTS failed_at TIMESTAMPTZ,
+            ADD COLUMN IF NOT EXISTS priority INTEGER NOT NULL DEFAULT 0;
 
-            CREATE INDEX IF NOT EXISTS chunks_text_vector_idx
-            ON {schema}.chunks USING GIN (text_vector);"
-        );
+            ALTER TABLE {schema}.chunks
+            ADD COLUMN IF NOT EXISTS page_end INTEGER,
+            ADD COLUMN IF NOT EXISTS section_path TEXT[] NOT NULL DEFAULT '{{}}',
+            ADD COLUMN IF NOT EXISTS element_type chunk_element
+            NOT NULL DEFAULT 'text',
+            ADD COLUMN IF NOT EXISTS bounding_boxes JSONB
+            NOT NULL DEFAULT '[]',
+            ADD COLUMN IF NOT EXISTS metadata JSONB NOT NULL DEFAULT '{{}}',
+            ADD COLUMN IF NOT EXISTS sequence INTEGER;
 
-        sqlx::raw_sql(&query).execute(pool).await.map_err(|_e| {
-            #[cfg(test)]
-            eprintln!("Failed to provision the namespace: {_e:?}");
-            ErrorResponse {
-                code: StatusCode::INTERNAL_SERVER_ERROR,
-                message: String::from("Failed to provision the namespace"),
-                solution: None,
-            }
-        })?;
+            CREATE TABLE IF NOT EXISTS {schema}.staged_chunks (
+                LIKE {schema}.chunks INCLUDING DEFAULTS,
 
-        Ok(())
+                FOREIGN KEY (document_id)
+                REFERENCES {schema}.documents (id)
+                ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS staged_chunks_document_idx
+            ON {schema}.staged_chunks (document_id);"
+        )
+    }
+
+    /// Returns the names of the indexes of the namespace with the SQL
+    /// statements to create them.
+    /// - concurrently: Builds the indexes without locking out writes.
+    fn indexes_sql(&self, concurrently: bool) -> Vec<(&str, String)> {
+        let schema = self.schema();
+        let index = &self.config.index;
+        let semantic_index =
+            self.semantic_index_sql(SEMANTIC_INDEX, index, concurrently);
+        let concurrently = if concurrently { "CONCURRENTLY " } else { "" };
+
+        vec![
+            (
+                "documents_status_idx",
+                format!("ON {schema}.documents (status)"),
+            ),
+            (
+                "chunks_document_sequence_idx",
+                format!("ON {schema}.chunks (document_id, sequence)"),
+            ),
+            (
+                "chunks_text_vector_idx",
+                format!("ON {schema}.chunks USING GIN (text_vector)"),
+            ),
+            (
+                "chunks_content_trgm_idx",
+                format!("ON {schema}.chunks USING GIN (content gin_trgm_ops)"),
+            ),
+            (
+                "chunks_metadata_idx",
+                format!(
+                    "ON {schema}.chunks USING GIN (metadata jsonb_path_ops)"
+                ),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, target)| {
+            let unique = match name {
+                "chunks_document_sequence_idx" => "UNIQUE ",
+                _ => "",
+            };
+
+            let sql = format!(
+                "CREATE {unique}INDEX {concurrently}IF NOT EXISTS {name}
+                {target}"
+            );
+
+            (name, sql)
+        })
+        .chain([(SEMANTIC_INDEX, semantic_index)])
+        .collect()
+    }
+
+    /// Returns the SQL statement to create the index of the semantic vectors.
+    /// - name: Name of the index within the namespace schema.
+    /// - concurrently: Builds the index without locking out writes.
+    pub fn semantic_index_sql(
+        &self,
+        name: &str,
+        index: &IndexConfig,
+        concurrently: bool,
+    ) -> String {
+        let schema = self.schema();
+        let dimension = self.config.embedding.dimension();
+        let expression = self.config.vector.index_expression(dimension);
+        let (method, parameters) = index.method();
+        let concurrently = if concurrently { "CONCURRENTLY " } else { "" };
+
+        format!(
+            "CREATE INDEX {concurrently}IF NOT EXISTS {name}
+            ON {schema}.chunks USING {method} ({expression})
+            WITH ({parameters})"
+        )
+    }
+
+    /// Returns the number of rows the semantic search reads from the index
+    /// to return the given number of results.
+    pub fn semantic_candidates(&self, limit: usize) -> usize {
+        match self.config.vector.quantization {
+            Quantization::None => limit,
+            Quantization::Binary => limit * RERANK_CANDIDATES,
+        }
+    }
+
+    /// Returns the SQL query for semantic search in the namespace.
+    /// - $1: Query embedding.
+    /// - $2: Number of results to return.
+    /// - condition: SQL condition to filter the chunks.
+    ///
+    /// With binary quantization, we retrieve more candidates from the index
+    /// and re-rank them using the distance between the full vectors.
+    pub fn semantic_search_sql(&self, condition: &str) -> String {
+        let schema = self.schema();
+        let vector = &self.config.vector;
+        let storage: &str = vector.storage.into();
+        let operator = vector.operator();
+
+        match vector.quantization {
+            Quantization::None => format!(
+                "SELECT id FROM {schema}.chunks
+                WHERE {condition}
+                ORDER BY semantic_vector {operator} $1::{storage}
+                LIMIT $2;"
+            ),
+            Quantization::Binary => {
+                let dimension = self.config.embedding.dimension();
+                format!(
+                    "SELECT id FROM (
+                        SELECT id, semantic_vector FROM {schema}.chunks
+                        WHERE {condition}
+                        ORDER BY
+                        binary_quantize(semantic_vector)::bit({dimension})
+                        <~> binary_quantize($1::{storage})
+                        LIMIT $2 * {RERANK_CANDIDATES}
+                    ) AS candidates
+                    ORDER BY semantic_vector {operator} $1::{storage}
+                    LIMIT $2;"
+                )
+            },
+        }
     }
 
     /// Teardown the namespace by dropping the schema and all its tables.
@@ -222,6 +1034,34 @@ impl Namespace {
     }
 }
 
+/// Maintenance operation on the tables and indexes of a namespace.
+/// - Reindex: Rebuilds the existing indexes of the chunks.
+/// - Rebuild: Swaps the semantic index with one built with a new config.
+/// - Vacuum: Reclaims dead rows and refreshes the planner statistics.
+///
+/// The indexes are built concurrently so the namespace remains available for
+/// queries and ingestion during the operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "operation")]
+pub enum Maintenance {
+    Reindex,
+    Rebuild { index: IndexConfig },
+    Vacuum,
+}
+
+/// Progress of an index being built in a namespace.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct IndexProgress {
+    pub table: String,
+    pub index: Option<String>,
+    pub command: String,
+    pub phase: String,
+    pub blocks_done: i64,
+    pub blocks_total: i64,
+    pub tuples_done: i64,
+    pub tuples_total: i64,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[derive(Serialize, Deserialize, Type)]
 #[sqlx(type_name = "doc_status", rename_all = "lowercase")]
@@ -260,26 +1100,138 @@ pub struct Document {
     pub id: DocumentID,
     pub status: DocumentStatus,
     pub metadata: Value,
+    pub properties: DocumentProperties,
+    /// Number of times a worker started processing the document.
+    pub attempts: i32,
+    /// Time when the last processing attempt started.
+    pub started_at: Option<DateTime<Utc>>,
+    /// Reason of the last failure until the document is completed.
+    pub error: Option<DocumentError>,
+    /// Priority of the extraction task among the tasks of the namespace.
+    pub priority: i32,
     pub updated_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
 }
 
 impl<'r> FromRow<'r, PgRow> for Document {
     fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        let code: Option<String> = row.try_get("error_code")?;
+        let error = match code {
+            Some(code) => Some(DocumentError {
+                code,
+                message: row.try_get("error_message")?,
+                failed_at: row.try_get("failed_at")?,
+            }),
+            None => None,
+        };
+
         Ok(Document {
             id: row.try_get("id")?,
             status: row.try_get("status")?,
             metadata: row.try_get("metadata")?,
+            properties: DocumentProperties::from_row(row)?,
+            attempts: row.try_get("attempts")?,
+            started_at: row.try_get("started_at")?,
+            error,
+            priority: row.try_get("priority")?,
             created_at: row.try_get("created_at")?,
             updated_at: row.try_get("updated_at")?,
         })
     }
 }
 
+/// Failure reason of a document reported by the worker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentError {
+    pub code: String,
+    pub message: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Properties of a document reported by the worker after extraction.
+///
+/// Unlike the metadata which is provided by the user when uploading the
+/// document, these properties are extracted from the document itself.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct DocumentProperties {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub page_count: Option<i32>,
+    pub language: Option<String>,
+    pub ocr: Option<bool>,
+}
+
+impl From<protos::DocumentProperties> for DocumentProperties {
+    fn from(value: protos::DocumentProperties) -> Self {
+        DocumentProperties {
+            title: value.title,
+            author: value.author,
+            page_count: value.page_count.map(|count| count as i32),
+            language: value.language,
+            ocr: value.ocr,
+        }
+    }
+}
+
 impl Document {
     /// Returns the key for the document in the S3 bucket.
     pub fn key(&self, namespace: &Namespace) -> String {
-        format!("{}/{}.pdf", namespace.schema(), self.id)
+        namespace.document_key(&self.id)
+    }
+}
+
+/// Type of the document element a chunk is extracted from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Type)]
+#[sqlx(type_name = "chunk_element", rename_all = "lowercase")]
+pub enum ElementType {
+    #[default]
+    Text,
+    Heading,
+    List,
+    Table,
+    Caption,
+    Code,
+    Formula,
+}
+
+impl From<protos::ElementType> for ElementType {
+    fn from(value: protos::ElementType) -> Self {
+        type Element = protos::ElementType;
+        match value {
+            Element::Text => Self::Text,
+            Element::Heading => Self::Heading,
+            Element::List => Self::List,
+            Element::Table => Self::Table,
+            Element::Caption => Self::Caption,
+            Element::Code => Self::Code,
+            Element::Formula => Self::Formula,
+        }
+    }
+}
+
+/// Region of a chunk on a page in PDF points.
+///
+/// The coordinates are relative to the top-left corner of the page so they
+/// can be used to highlight the chunk on the rendered page.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub page: u32,
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl From<&protos::BoundingBox> for BoundingBox {
+    fn from(value: &protos::BoundingBox) -> Self {
+        BoundingBox {
+            page: value.page,
+            left: value.left,
+            top: value.top,
+            right: value.right,
+            bottom: value.bottom,
+        }
     }
 }
 
@@ -288,12 +1240,511 @@ impl Document {
 /// When querying the database, we exclude retrieving the vector columns as
 /// they are not needed for most operations. That's why when querying as this
 /// type, we only specify the columns listed in the struct.
+///
+/// The snippet is only available in query results when highlighting is
+/// requested. It contains the part of the content that matches the query.
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Chunk {
     pub id: ChunkID,
     pub document_id: DocumentID,
     pub page: i32,
+    pub page_end: Option<i32>,
+    /// Position of the chunk within the document.
+    pub sequence: i32,
     pub content: String,
+    pub section_path: Vec<String>,
+    pub element_type: ElementType,
+    #[sqlx(json)]
+    pub bounding_boxes: Vec<BoundingBox>,
+    pub metadata: Value,
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+impl Chunk {
+    /// Columns to select when querying the chunks as this type.
+    pub const COLUMNS: &str = "id, document_id, page, page_end, sequence, \
+        content, section_path, element_type, bounding_boxes, metadata";
+}
+
+/// Filter on the chunk metadata to narrow down a query.
+/// - element_types: Chunks extracted from any of the element types.
+/// - section_path: Chunks within the section with the heading path.
+/// - page_start, page_end: Chunks overlapping the inclusive page range.
+/// - metadata: Chunks whose metadata contains the JSON object.
+/// - document: Chunks of documents matching the document filter.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkFilter {
+    pub element_types: Option<Vec<ElementType>>,
+    pub section_path: Option<Vec<String>>,
+    pub page_start: Option<i32>,
+    pub page_end: Option<i32>,
+    pub metadata: Option<Value>,
+    pub document: Option<DocumentFilter>,
+}
+
+/// Filter on the extracted properties of the documents.
+/// - title: Documents whose title contains the text, case-insensitive.
+/// - min_page_count, max_page_count: Inclusive range of the page count.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentFilter {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub language: Option<String>,
+    pub ocr: Option<bool>,
+    pub min_page_count: Option<i32>,
+    pub max_page_count: Option<i32>,
+}
+
+impl DocumentFilter {
+    /// Returns true if the filter has no properties to match.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.author.is_none()
+            && self.language.is_none()
+            && self.ocr.is_none()
+            && self.min_page_count.is_none()
+            && self.max_page_count.is_none()
+    }
+}
+
+impl ChunkFilter {
+    /// Validates the filter values.
+    pub fn validate(&self) -> Result<(), ErrorResponse> {
+        let invalid = |message: &str| ErrorResponse {
+            code: StatusCode::BAD_REQUEST,
+            message: message.to_string(),
+            solution: None,
+        };
+
+        if let (Some(start), Some(end)) = (self.page_start, self.page_end) {
+            if start > end {
+                return Err(invalid("The page start must not exceed the end."));
+            }
+        }
+
+        if let Some(metadata) = &self.metadata {
+            if !metadata.is_object() {
+                return Err(invalid("The metadata filter must be an object."));
+            }
+        }
+
+        if let Some(document) = &self.document {
+            let min = document.min_page_count;
+            if let (Some(min), Some(max)) = (min, document.max_page_count) {
+                if min > max {
+                    return Err(invalid("The min page count exceeds the max."));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the metadata filter unless it's an empty object.
+    ///
+    /// Empty metadata and document filters are treated as no filter.
+    fn metadata(&self) -> Option<&Value> {
+        self.metadata.as_ref().filter(|metadata| {
+            !metadata.as_object().is_some_and(|object| object.is_empty())
+        })
+    }
+
+    /// Returns the document filter unless it has no properties.
+    fn document(&self) -> Option<&DocumentFilter> {
+        self.document
+            .as_ref()
+            .filter(|document| !document.is_empty())
+    }
+
+    /// Returns the SQL condition of the filter.
+    /// - schema: Schema of the namespace to filter the documents.
+    /// - offset: Position of the first parameter of the filter.
+    ///
+    /// The parameters must be bound in the same order with the bind method.
+    pub fn condition(&self, schema: &str, offset: usize) -> String {
+        let mut conditions = Vec::new();
+        let mut param = offset;
+        let mut next = || {
+            param += 1;
+            format!("${}", param - 1)
+        };
+
+        if self.element_types.is_some() {
+            conditions.push(format!("element_type = ANY({})", next()));
+        }
+
+        if self.section_path.is_some() {
+            let path = format!("{}::TEXT[]", next());
+            conditions
+                .push(format!("section_path[1:cardinality({path})] = {path}"));
+        }
+
+        if self.page_start.is_some() {
+            let page = next();
+            conditions.push(format!("COALESCE(page_end, page) >= {page}"));
+        }
+
+        if self.page_end.is_some() {
+            conditions.push(format!("page <= {}", next()));
+        }
+
+        if self.metadata().is_some() {
+            conditions.push(format!("metadata @> {}", next()));
+        }
+
+        if let Some(document) = self.document() {
+            let mut properties = Vec::new();
+            if document.title.is_some() {
+                let title = format!("lower({})", next());
+                properties
+                    .push(format!("position({title} IN lower(title)) > 0"));
+            }
+
+            if document.author.is_some() {
+                properties.push(format!("author = {}", next()));
+            }
+
+            if document.language.is_some() {
+                properties.push(format!("language = {}", next()));
+            }
+
+            if document.ocr.is_some() {
+                properties.push(format!("ocr = {}", next()));
+            }
+
+            if document.min_page_count.is_some() {
+                properties.push(format!("page_count >= {}", next()));
+            }
+
+            if document.max_page_count.is_some() {
+                properties.push(format!("page_count <= {}", next()));
+            }
+
+            let properties = properties.join(" AND ");
+            conditions.push(format!(
+                "document_id IN (SELECT id FROM {schema}.documents \
+                WHERE {properties})"
+            ));
+        }
+
+        match conditions.is_empty() {
+            true => "TRUE".to_string(),
+            false => conditions.join(" AND "),
+        }
+    }
+
+    /// Binds the parameters of the filter condition to the query.
+    pub fn bind<'q, O>(
+        &'q self,
+        mut query: QueryScalar<'q, Postgres, O, PgArguments>,
+    ) -> QueryScalar<'q, Postgres, O, PgArguments> {
+        if let Some(element_types) = &self.element_types {
+            query = query.bind(element_types);
+        }
+
+        if let Some(section_path) = &self.section_path {
+            query = query.bind(section_path);
+        }
+
+        if let Some(page_start) = self.page_start {
+            query = query.bind(page_start);
+        }
+
+        if let Some(page_end) = self.page_end {
+            query = query.bind(page_end);
+        }
+
+        if let Some(metadata) = self.metadata() {
+            query = query.bind(metadata);
+        }
+
+        if let Some(document) = self.document() {
+            if let Some(title) = &document.title {
+                query = query.bind(title);
+            }
+
+            if let Some(author) = &document.author {
+                query = query.bind(author);
+            }
+
+            if let Some(language) = &document.language {
+                query = query.bind(language);
+            }
+
+            if let Some(ocr) = document.ocr {
+                query = query.bind(ocr);
+            }
+
+            if let Some(min_page_count) = document.min_page_count {
+                query = query.bind(min_page_count);
+            }
+
+            if let Some(max_page_count) = document.max_page_count {
+                query = query.bind(max_page_count);
+            }
+        }
+
+        query
+    }
+}
+
+/// Strategy to combine the chunk scores of a document into one score.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum ScoreAggregation {
+    /// Uses the score of the best matching chunk.
+    #[default]
+    Max,
+    /// Adds up the scores of all matching chunks.
+    Sum,
+    /// Fuses the ranks of the matching chunks with RRF.
+    Rrf,
+}
+
+impl ScoreAggregation {
+    /// Aggregates the chunk scores of a document into a single score.
+    /// - hits: Pairs of the chunk rank in the results and its score.
+    pub fn aggregate(&self, hits: &[(usize, f32)]) -> f32 {
+        match self {
+            Self::Max => {
+                hits.iter().map(|(_, score)| *score).fold(0.0, f32::max)
+            },
+            Self::Sum => hits.iter().map(|(_, score)| score).sum(),
+            Self::Rrf => hits
+                .iter()
+                .map(|(rank, _)| 1.0 / ((rank + 1) + RRF_CONSTANT) as f32)
+                .sum(),
+        }
+    }
+}
+
+/// Syntax used to parse the query for full-text search.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum QuerySyntax {
+    /// Treats the query as plain text where all terms must match.
+    #[default]
+    Plain,
+    /// Supports quoted phrases, exclusions with -term, and OR operators.
+    Websearch,
+    /// Uses the tsquery syntax of Postgres as it is.
+    Raw,
+}
+
+impl QuerySyntax {
+    /// Returns the SQL expression that parses the query into a tsquery.
+    /// - language: Text search configuration to normalize the terms.
+    /// - param: Placeholder of the query parameter, for example, $1.
+    /// - prefix: Whether to match the terms as prefixes of the words.
+    ///
+    /// Prefix matching only applies to plain and websearch queries. With the
+    /// raw syntax, prefixes can be specified with the :* operator.
+    pub fn tsquery(&self, language: &str, param: &str, prefix: bool) -> String {
+        let tsquery = match self {
+            Self::Plain => format!("plainto_tsquery('{language}', {param})"),
+            Self::Websearch => {
+                format!("websearch_to_tsquery('{language}', {param})")
+            },
+            Self::Raw => return format!("to_tsquery('{language}', {param})"),
+        };
+
+        if !prefix {
+            return tsquery;
+        }
+
+        // The terms of the parsed query are already normalized, so we use the
+        // simple configuration to avoid normalizing them again.
+        format!(
+            r"to_tsquery('simple', regexp_replace(
+                {tsquery}::text,
+                '''((?:[^'']|'''')+)''',
+                '''\1'':*',
+                'g'
+            ))"
+        )
+    }
+}
+
+/// Options to query chunks from a namespace.
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    /// Number of results to return per page.
+    pub k: usize,
+    pub syntax: QuerySyntax,
+    /// Whether to match the query terms as prefixes in full-text search.
+    pub prefix: bool,
+    /// Whether to include trigram search to match misspelled terms.
+    pub fuzzy: bool,
+    /// Overrides the default ef_search of the HNSW index.
+    ///
+    /// The ef_search is raised to the number of rows the query reads from
+    /// the index when it's lower.
+    pub ef_search: Option<u16>,
+    /// Overrides the default probes of the IVFFlat index.
+    pub probes: Option<u32>,
+    /// Overrides the default iterative scan mode of the namespace index.
+    pub iterative_scan: Option<IterativeScan>,
+    pub highlight: Option<HighlightOptions>,
+    pub filter: ChunkFilter,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions {
+            k: 10,
+            syntax: QuerySyntax::default(),
+            prefix: false,
+            fuzzy: false,
+            ef_search: None,
+            probes: None,
+            iterative_scan: None,
+            highlight: None,
+            filter: ChunkFilter::default(),
+        }
+    }
+}
+
+impl QueryOptions {
+    /// Returns the digest of the query and the options affecting its ranking.
+    ///
+    /// The scope tells apart the queries ranking chunks and documents since
+    /// their cursors page through different results.
+    pub fn digest(&self, query: &str, scope: &str) -> [u8; 32] {
+        // Unwrapping is safe because the options are serializable.
+        let options = serde_json::to_vec(&serde_json::json!({
+            "scope": scope,
+            "query": query,
+            "syntax": self.syntax,
+            "prefix": self.prefix,
+            "fuzzy": self.fuzzy,
+            "ef_search": self.ef_search,
+            "probes": self.probes,
+            "iterative_scan": self.iterative_scan,
+            "filter": self.filter,
+        }))
+        .unwrap();
+
+        Sha256::digest(options).into()
+    }
+
+    /// Returns the iterative scan mode given the default mode of the index.
+    ///
+    /// Filters are applied after the index scan which can leave us with too
+    /// few results, but iterative scans require pgvector 0.8. So, they are
+    /// only enabled when the namespace or the query asks for them.
+    pub fn iterative_scan(&self, default: IterativeScan) -> IterativeScan {
+        self.iterative_scan.unwrap_or(default)
+    }
+}
+
+/// Options to query documents instead of individual chunks.
+#[derive(Debug, Clone)]
+pub struct DocumentQueryOptions {
+    /// Maximum number of chunks to return per document.
+    pub passages: usize,
+    pub aggregation: ScoreAggregation,
+}
+
+impl Default for DocumentQueryOptions {
+    fn default() -> Self {
+        DocumentQueryOptions {
+            passages: 3,
+            aggregation: ScoreAggregation::default(),
+        }
+    }
+}
+
+/// Document matched by a query along with its top matching chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentResult {
+    pub document: Document,
+    pub score: f32,
+    pub chunks: Vec<Chunk>,
+}
+
+/// Options to highlight the matching query terms in the chunk snippets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighlightOptions {
+    pub start_tag: String,
+    pub stop_tag: String,
+    /// Maximum number of fragments in a snippet.
+    /// If set to 0, the snippet is a single fragment without delimiters.
+    pub max_fragments: u16,
+    /// Maximum number of words in a fragment.
+    pub fragment_words: u16,
+    /// Whether to pick the snippets of the top chunks without a matching
+    /// query term semantically, which embeds their sentence windows.
+    pub semantic: bool,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> Self {
+        HighlightOptions {
+            start_tag: "<b>".to_string(),
+            stop_tag: "</b>".to_string(),
+            max_fragments: 2,
+            fragment_words: 30,
+            semantic: false,
+        }
+    }
+}
+
+impl HighlightOptions {
+    /// Validates the options to be usable with Postgres ts_headline.
+    pub fn validate(&self) -> Result<(), ErrorResponse> {
+        let tags = [&self.start_tag, &self.stop_tag];
+        if tags.iter().any(|tag| tag.is_empty() || tag.contains('"')) {
+            return Err(ErrorResponse {
+                code: StatusCode::BAD_REQUEST,
+                message: "Please provide valid highlight tags.".to_string(),
+                solution: Some(String::from(
+                    "Highlight tags must be non-empty without double quotes.",
+                )),
+            });
+        }
+
+        if self.fragment_words < 2 {
+            return Err(ErrorResponse {
+                code: StatusCode::BAD_REQUEST,
+                message: "Fragments must be at least 2 words long.".to_string(),
+                solution: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the options string for the ts_headline function.
+    pub fn headline_options(&self) -> String {
+        format!(
+            "StartSel=\"{}\", StopSel=\"{}\", MaxFragments={}, \
+            MaxWords={}, MinWords={}",
+            self.start_tag,
+            self.stop_tag,
+            self.max_fragments,
+            self.fragment_words,
+            self.fragment_words / 2,
+        )
+    }
+}
+
+/// Page of results from a query.
+///
+/// The next cursor is only provided when there are more results available.
+/// It can be used in the following query to retrieve the next page.
+///
+/// The pages are served from the ranking of the first query which holds at
+/// most 200 chunks, or k chunks if k is larger. Document queries rank up to
+/// 10 chunks per requested document if that's more than 200 chunks. Paging
+/// stops at the end of this ranking even if more chunks match the query, so
+/// a larger k should be used to go deeper into the results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryResults<T> {
+    pub results: Vec<T>,
+    pub next_cursor: Option<String>,
 }
 
 #[cfg(test)]
@@ -313,4 +1764,194 @@ mod tests {
 
         assert_eq!(namespace.schema(), "ns_f47ac10b58cc");
     }
+
+    #[test]
+    fn test_semantic_index_sql() {
+        let id = "f47ac10b-58cc-4372-a567-0e02b2c3d479";
+        let namespace = Namespace {
+            id: Uuid::from_str(id).unwrap(),
+            name: "default".to_string(),
+            config: NamespaceConfig::default(),
+            created_at: Utc::now(),
+        };
+
+        let index = IndexConfig::IvfFlat(IvfFlatConfig::default());
+        let sql = namespace.semantic_index_sql("new_idx", &index, true);
+        assert!(sql.starts_with("CREATE INDEX CONCURRENTLY IF NOT EXISTS"));
+        assert!(
+            sql.contains("USING IVFFLAT (semantic_vector vector_cosine_ops)")
+        );
+        assert!(sql.ends_with("WITH (lists = 100)"));
+    }
+
+    #[test]
+    fn test_chunk_filter_condition() {
+        let filter = ChunkFilter::default();
+        assert_eq!(filter.condition("ns", 3), "TRUE");
+
+        let filter = ChunkFilter {
+            element_types: Some(vec![ElementType::Table]),
+            page_start: Some(2),
+            metadata: Some(serde_json::json!({ "key": "value" })),
+            document: Some(DocumentFilter {
+                language: Some("en".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            filter.condition("ns", 3),
+            "element_type = ANY($3) AND COALESCE(page_end, page) >= $4 \
+            AND metadata @> $5 AND document_id IN \
+            (SELECT id FROM ns.documents WHERE language = $6)"
+        );
+
+        let options = QueryOptions {
+            filter,
+            ..Default::default()
+        };
+
+        // Filtered queries don't enable iterative scans on their own.
+        let scan = options.iterative_scan(IterativeScan::Off);
+        assert!(matches!(scan, IterativeScan::Off));
+
+        let filter = ChunkFilter {
+            metadata: Some(serde_json::json!({})),
+            document: Some(DocumentFilter::default()),
+            ..Default::default()
+        };
+
+        assert_eq!(filter.condition("ns", 3), "TRUE");
+    }
+
+    #[test]
+    fn test_index_config_deserialize() {
+        // Configurations without a type are HNSW.
+        let value = serde_json::json!({ "m": 8, "ef_construction": 32 });
+        let config: IndexConfig = serde_json::from_value(value).unwrap();
+        match config {
+            IndexConfig::Hnsw(config) => assert_eq!(config.m, 8),
+            _ => panic!("The index type should be HNSW."),
+        }
+
+        let value = serde_json::json!({ "type": "IvfFlat", "lists": 50 });
+        let config: IndexConfig = serde_json::from_value(value).unwrap();
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["type"], "IvfFlat");
+        assert_eq!(value["lists"], 50);
+
+        let value = serde_json::json!({ "type": "Flat" });
+        assert!(serde_json::from_value::<IndexConfig>(value).is_err());
+    }
+
+    #[test]
+    fn test_index_config_validate() {
+        let mut config = HnswConfig::default();
+        assert!(IndexConfig::Hnsw(config.clone()).validate().is_ok());
+
+        config.ef_search = 0;
+        assert!(IndexConfig::Hnsw(config).validate().is_err());
+
+        let options = QueryOptions {
+            ef_search: Some(100),
+            ..Default::default()
+        };
+
+        let config = IndexConfig::IvfFlat(IvfFlatConfig::default());
+        assert!(config.search_parameters(&options, 10).is_err());
+    }
+
+    #[test]
+    fn test_index_config_search_parameters() {
+        let options = QueryOptions {
+            probes: Some(20),
+            iterative_scan: Some(IterativeScan::RelaxedOrder),
+            ..Default::default()
+        };
+
+        let config = IndexConfig::IvfFlat(IvfFlatConfig::default());
+        let parameters = config.search_parameters(&options, 10).unwrap();
+        assert_eq!(
+            parameters,
+            vec![
+                "SET LOCAL ivfflat.probes = 20",
+                "SET LOCAL ivfflat.iterative_scan = relaxed_order",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index_config_ef_search_limit() {
+        let options = QueryOptions::default();
+        let config = IndexConfig::Hnsw(HnswConfig::default());
+
+        let parameters = config.search_parameters(&options, 10).unwrap();
+        assert_eq!(parameters, vec!["SET LOCAL hnsw.ef_search = 40"]);
+
+        let parameters = config.search_parameters(&options, 200).unwrap();
+        assert_eq!(parameters, vec!["SET LOCAL hnsw.ef_search = 200"]);
+
+        let parameters = config.search_parameters(&options, 4000).unwrap();
+        assert_eq!(parameters, vec!["SET LOCAL hnsw.ef_search = 1000"]);
+    }
+
+    #[test]
+    fn test_vector_config_validate() {
+        let dimension = 3072;
+        let mut config = VectorConfig::default();
+        assert!(config.validate(dimension).is_err());
+
+        config.storage = VectorStorage::Halfvec;
+        assert!(config.validate(dimension).is_ok());
+        assert_eq!(
+            config.index_expression(dimension),
+            "semantic_vector halfvec_cosine_ops"
+        );
+
+        config.quantization = Quantization::Binary;
+        assert_eq!(
+            config.index_expression(dimension),
+            "(binary_quantize(semantic_vector)::bit(3072)) bit_hamming_ops"
+        );
+    }
+
+    #[test]
+    fn test_headline_options() {
+        let options = HighlightOptions {
+            start_tag: "<em class=match>".to_string(),
+            fragment_words: 10,
+            ..Default::default()
+        };
+
+        assert!(options.validate().is_ok());
+        assert_eq!(
+            options.headline_options(),
+            "StartSel=\"<em class=match>\", StopSel=\"</b>\", \
+            MaxFragments=2, MaxWords=10, MinWords=5"
+        );
+    }
+
+    #[test]
+    fn test_query_syntax_tsquery() {
+        let plain = QuerySyntax::Plain.tsquery("english", "$1", false);
+        assert_eq!(plain, "plainto_tsquery('english', $1)");
+
+        let raw = QuerySyntax::Raw.tsquery("english", "$1", true);
+        assert_eq!(raw, "to_tsquery('english', $1)");
+
+        let prefix = QuerySyntax::Websearch.tsquery("english", "$2", true);
+        assert!(prefix.starts_with("to_tsquery('simple', regexp_replace("));
+        assert!(prefix.contains("websearch_to_tsquery('english', $2)::text"));
+    }
+
+    #[test]
+    fn test_score_aggregation() {
+        let hits = vec![(0, 0.5), (3, 0.25)];
+        assert_eq!(ScoreAggregation::Max.aggregate(&hits), 0.5);
+        assert_eq!(ScoreAggregation::Sum.aggregate(&hits), 0.75);
+
+        let rrf = ScoreAggregation::Rrf.aggregate(&hits);
+        assert_eq!(rrf, 1.0 / 61.0 + 1.0 / 64.0);
+    }
 }
@@ -0,0 +1,182 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+// Version of the binary layout of the cursor.
+// We should bump this when changing the layout to reject outdated cursors.
+const VERSION: u8 = 3;
+
+// Size of the fixed part of a ranking entry: 16 bytes for the UUID, 4 bytes
+// for the score, and 4 bytes for the number of chunks that follow it.
+const ENTRY_SIZE: usize = 24;
+
+// Size of the header: version, namespace, query digest and length.
+const HEADER_SIZE: usize = 53;
+
+// Size of the HMAC-SHA256 signature appended to the cursor.
+const SIGNATURE_SIZE: usize = 32;
+
+/// Pagination cursor of a query.
+///
+/// The cursor holds a snapshot of the ranking from the first page of the
+/// query minus the results already served. This makes the following pages
+/// stable even when chunks are added to the namespace in between the
+/// requests and allows us to serve them without running the search again.
+///
+/// The token is signed so the ranking can't be tampered with, and it's tied
+/// to the query it was created for with the digest of the query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub namespace: Uuid,
+    /// Digest of the query and the options that affect its ranking.
+    pub query: [u8; 32],
+    /// Remaining results of the ranking in order.
+    pub ranking: Vec<CursorEntry>,
+}
+
+/// Result in the ranking snapshot of a cursor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorEntry {
+    /// ID of the chunk or the document.
+    pub id: Uuid,
+    pub score: f32,
+    /// IDs of the chunks returned as passages of a document.
+    pub chunks: Vec<Uuid>,
+}
+
+impl Cursor {
+    /// Encodes the cursor into an opaque URL-safe token signed with the key.
+    pub fn encode(&self, key: &[u8]) -> String {
+        let size = self.ranking.iter().fold(HEADER_SIZE, |size, entry| {
+            size + ENTRY_SIZE + entry.chunks.len() * 16
+        });
+
+        let mut bytes = Vec::with_capacity(size + SIGNATURE_SIZE);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(self.namespace.as_bytes());
+        bytes.extend_from_slice(&self.query);
+        bytes.extend_from_slice(&(self.ranking.len() as u32).to_be_bytes());
+        for entry in self.ranking.iter() {
+            bytes.extend_from_slice(entry.id.as_bytes());
+            bytes.extend_from_slice(&entry.score.to_be_bytes());
+            bytes.extend_from_slice(&(entry.chunks.len() as u32).to_be_bytes());
+            for chunk in entry.chunks.iter() {
+                bytes.extend_from_slice(chunk.as_bytes());
+            }
+        }
+
+        let signature = mac(key).chain_update(&bytes).finalize();
+        bytes.extend_from_slice(&signature.into_bytes());
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Decodes the cursor from a token created by the encode method.
+    ///
+    /// Returns None if the token was not signed with the same key.
+    pub fn decode(token: impl AsRef<str>, key: &[u8]) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(token.as_ref()).ok()?;
+        if bytes.len() < HEADER_SIZE + SIGNATURE_SIZE {
+            return None;
+        }
+
+        let (bytes, signature) = bytes.split_at(bytes.len() - SIGNATURE_SIZE);
+        mac(key).chain_update(bytes).verify_slice(signature).ok()?;
+
+        let (version, bytes) = bytes.split_first()?;
+        if *version != VERSION {
+            return None;
+        }
+
+        let (namespace, bytes) = bytes.split_at(16);
+        let (query, bytes) = bytes.split_at(32);
+        let (length, mut bytes) = bytes.split_at(4);
+        let length = u32::from_be_bytes(length.try_into().ok()?) as usize;
+
+        let mut ranking = Vec::new();
+        for _ in 0..length {
+            let entry = bytes.get(..ENTRY_SIZE)?;
+            let id = Uuid::from_slice(&entry[..16]).ok()?;
+            let score = f32::from_be_bytes(entry[16..20].try_into().ok()?);
+            let count = u32::from_be_bytes(entry[20..].try_into().ok()?);
+
+            let end =
+                ENTRY_SIZE.checked_add((count as usize).checked_mul(16)?)?;
+            let chunks = bytes
+                .get(ENTRY_SIZE..end)?
+                .chunks_exact(16)
+                .map(|chunk| Uuid::from_slice(chunk).ok())
+                .collect::<Option<Vec<Uuid>>>()?;
+
+            ranking.push(CursorEntry { id, score, chunks });
+            bytes = &bytes[end..];
+        }
+
+        if !bytes.is_empty() {
+            return None;
+        }
+
+        Some(Cursor {
+            namespace: Uuid::from_slice(namespace).ok()?,
+            query: query.try_into().ok()?,
+            ranking,
+        })
+    }
+}
+
+fn mac(key: &[u8]) -> Hmac<Sha256> {
+    // Unwrapping is safe because HMAC accepts keys of any size.
+    Hmac::new_from_slice(key).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"secretkey";
+
+    fn entry(chunks: usize) -> CursorEntry {
+        CursorEntry {
+            id: Uuid::new_v4(),
+            score: 0.5,
+            chunks: (0..chunks).map(|_| Uuid::new_v4()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let cursor = Cursor {
+            namespace: Uuid::new_v4(),
+            query: [7; 32],
+            ranking: vec![entry(0), entry(3), entry(1)],
+        };
+
+        let token = cursor.encode(KEY);
+        assert_eq!(Cursor::decode(&token, KEY), Some(cursor));
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(Cursor::decode("not a cursor", KEY), None);
+        let token = URL_SAFE_NO_PAD.encode([VERSION; 100]);
+        assert_eq!(Cursor::decode(token, KEY), None);
+    }
+
+    #[test]
+    fn test_decode_tampered() {
+        let cursor = Cursor {
+            namespace: Uuid::new_v4(),
+            query: [7; 32],
+            ranking: vec![entry(2)],
+        };
+
+        let token = cursor.encode(KEY);
+        assert_eq!(Cursor::decode(&token, b"otherkey"), None);
+
+        let mut bytes = URL_SAFE_NO_PAD.decode(&token).unwrap();
+        bytes[HEADER_SIZE - 1] ^= 1;
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        assert_eq!(Cursor::decode(token, KEY), None);
+    }
+}
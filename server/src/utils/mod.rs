@@ -1,6 +1,12 @@
+mod cursor;
 mod reranker;
+mod snippet;
+mod tls;
 
+pub use cursor::{Cursor, CursorEntry};
 pub use reranker::Reranker;
+pub use snippet::{most_overlapping, most_similar, sentence_windows};
+pub use tls::{coordinator_tls_config, TlsFiles};
 
 use std::cmp::Ordering;
 use std::collections::HashMap;
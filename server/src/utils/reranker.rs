@@ -22,7 +22,9 @@ where
     /// Reranks the items using the Reciprocal Rank Fusion algorithm.
     /// - constant: Number to add to the rank of each item.
     /// - k: Number of items to return.
-    pub fn rrf(&self, constant: usize, k: u8) -> Vec<T> {
+    ///
+    /// Returns the items with their fused score in descending order.
+    pub fn rrf(&self, constant: usize, k: usize) -> Vec<(T, f32)> {
         let mut scores: HashMap<T, f32> = HashMap::new();
 
         for ranking in self.lists.iter() {
@@ -37,11 +39,8 @@ where
             b.partial_cmp(a).unwrap_or(Ordering::Equal)
         });
 
+        items.truncate(k);
         items
-            .iter()
-            .take(k as usize)
-            .map(|(item, _)| item.clone())
-            .collect()
     }
 }
 
@@ -53,7 +52,9 @@ mod tests {
     fn test_rrf() {
         let reranker = setup();
         let ranked = reranker.rrf(60, 3);
-        assert_eq!(ranked, vec![4, 1, 3]);
+        let items: Vec<u8> = ranked.iter().map(|(item, _)| *item).collect();
+        assert_eq!(items, vec![4, 1, 3]);
+        assert!(ranked.windows(2).all(|pair| pair[0].1 >= pair[1].1));
     }
 
     fn setup() -> Reranker<u8> {
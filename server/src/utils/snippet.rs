@@ -0,0 +1,154 @@
+use super::*;
+use std::collections::HashSet;
+
+/// Returns the sentence windows of the text to pick a snippet from.
+/// - max_words: Maximum number of words in a window.
+///
+/// Each window starts at a sentence and spans the consecutive sentences that
+/// fit. This is used to pick a snippet for chunks that are matched
+/// semantically when none of the query terms match exactly.
+pub fn sentence_windows(text: &str, max_words: usize) -> Vec<String> {
+    let sentences = sentences(text);
+    let mut windows = Vec::with_capacity(sentences.len());
+    for start in 0..sentences.len() {
+        let mut count = 0;
+        let mut end = start;
+        while end < sentences.len() {
+            let length = words(sentences[end]).count();
+            if count + length > max_words && end > start {
+                break;
+            }
+
+            count += length;
+            end += 1;
+        }
+
+        // A single sentence may be longer than the window.
+        let window: Vec<&str> = sentences[start..end]
+            .iter()
+            .flat_map(|sentence| words(sentence))
+            .take(max_words)
+            .collect();
+
+        windows.push(window.join(" "));
+    }
+
+    windows
+}
+
+/// Returns the index of the embedding most similar to the query embedding.
+///
+/// The embeddings are compared by their cosine similarity.
+pub fn most_similar(query: &[f32], embeddings: &[Vec<f32>]) -> Option<usize> {
+    let norm =
+        |vector: &[f32]| vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let query_norm = norm(query);
+
+    let similarity = |embedding: &[f32]| {
+        let dot: f32 = query.iter().zip(embedding).map(|(a, b)| a * b).sum();
+        let norms = query_norm * norm(embedding);
+        if norms == 0.0 {
+            return 0.0;
+        }
+
+        dot / norms
+    };
+
+    embeddings
+        .iter()
+        .map(|embedding| similarity(embedding))
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        .map(|(index, _)| index)
+}
+
+/// Returns the index of the window sharing the most words with the query.
+///
+/// Words are compared by their lowercase letters and digits, and ties go to
+/// the earliest window. This picks a snippet without embedding the windows.
+pub fn most_overlapping(query: &str, windows: &[String]) -> Option<usize> {
+    let query: HashSet<String> = words(query).map(normalize).collect();
+    windows
+        .iter()
+        .map(|window| {
+            let words: HashSet<String> = words(window).map(normalize).collect();
+            words.iter().filter(|word| query.contains(*word)).count()
+        })
+        .enumerate()
+        .max_by(|(a, x), (b, y)| x.cmp(y).then(b.cmp(a)))
+        .map(|(index, _)| index)
+}
+
+/// Splits the text into trimmed sentences.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for (index, char) in text.char_indices() {
+        if matches!(char, '.' | '!' | '?' | '\n') {
+            let end = index + char.len_utf8();
+            sentences.push(text[start..end].trim());
+            start = end;
+        }
+    }
+
+    sentences.push(text[start..].trim());
+    sentences.retain(|sentence| !sentence.is_empty());
+    sentences
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split_whitespace()
+}
+
+/// Returns the lowercase letters and digits of the word.
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|char| char.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sentence_windows() {
+        let text = "ANNS balances speed over accuracy. Bananas are packed \
+            with potassium. Oranges are juicy and full of vitamin C.";
+
+        let windows = sentence_windows(text, 8);
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[0], "ANNS balances speed over accuracy.");
+        assert_eq!(windows[1], "Bananas are packed with potassium.");
+    }
+
+    #[test]
+    fn test_sentence_windows_truncate() {
+        let text = "Approximate nearest neighbor finds similar items fast";
+        let windows = sentence_windows(text, 3);
+        assert_eq!(windows, vec!["Approximate nearest neighbor"]);
+    }
+
+    #[test]
+    fn test_most_overlapping() {
+        let windows = vec![
+            "Bananas are packed with potassium.".to_string(),
+            "Nearest neighbor search trades accuracy for speed.".to_string(),
+            "Neighbor search is fast.".to_string(),
+        ];
+
+        let query = "Fast nearest neighbor SEARCH";
+        assert_eq!(most_overlapping(query, &windows), Some(1));
+        assert_eq!(most_overlapping("apples", &windows), Some(0));
+        assert_eq!(most_overlapping(query, &[]), None);
+    }
+
+    #[test]
+    fn test_most_similar() {
+        let query = [1.0, 0.0];
+        let embeddings = vec![vec![0.0, 1.0], vec![0.8, 0.2], vec![0.0, 0.0]];
+        assert_eq!(most_similar(&query, &embeddings), Some(1));
+        assert_eq!(most_similar(&query, &[]), None);
+    }
+}
@@ -0,0 +1,144 @@
+use axum_server::tls_rustls::RustlsConfig;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+/// Creates the TLS configuration of the coordinator server from PEM files.
+///
+/// When a client CA is provided, workers must present a certificate signed
+/// by the CA to connect to the coordinator server.
+pub fn coordinator_tls_config(
+    cert: impl AsRef<Path>,
+    key: impl AsRef<Path>,
+    client_ca: Option<impl AsRef<Path>>,
+) -> Result<ServerTlsConfig> {
+    let identity = Identity::from_pem(fs::read(cert)?, fs::read(key)?);
+    let mut config = ServerTlsConfig::new().identity(identity);
+
+    if let Some(client_ca) = client_ca {
+        let ca = Certificate::from_pem(fs::read(client_ca)?);
+        config = config.client_ca_root(ca);
+    }
+
+    Ok(config)
+}
+
+/// Certificate and private key PEM files of the interface server.
+///
+/// The files keep track of their last modification time so that the TLS
+/// configuration can be reloaded when the certificate is renewed.
+#[derive(Debug)]
+pub struct TlsFiles {
+    cert: PathBuf,
+    key: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl TlsFiles {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        let mut files = TlsFiles {
+            cert: cert.into(),
+            key: key.into(),
+            modified: None,
+        };
+
+        files.modified = files.last_modified();
+        files
+    }
+
+    /// Loads the TLS configuration from the files.
+    pub async fn load(&self) -> Result<RustlsConfig> {
+        RustlsConfig::from_pem_file(&self.cert, &self.key).await
+    }
+
+    /// Reloads the TLS configuration in place from the files.
+    ///
+    /// The current configuration is kept if the files are invalid.
+    pub async fn reload(&self, config: &RustlsConfig) -> Result<()> {
+        config.reload_from_pem_file(&self.cert, &self.key).await
+    }
+
+    /// Returns true if the files changed since the last check.
+    pub fn changed(&mut self) -> bool {
+        let modified = self.last_modified();
+        if modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        true
+    }
+
+    fn last_modified(&self) -> Option<SystemTime> {
+        let modified = |path: &Path| fs::metadata(path)?.modified();
+        let cert = modified(&self.cert).ok()?;
+        let key = modified(&self.key).ok()?;
+        Some(cert.max(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::CertifiedKey;
+    use std::env;
+    use std::fs::File;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_tls_files_reload() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        let id = Uuid::new_v4().simple().to_string();
+        let dir = env::temp_dir().join(format!("dl-tls-{id}"));
+        fs::create_dir_all(&dir).unwrap();
+
+        let cert = dir.join("interface.pem");
+        let key = dir.join("interface.key");
+        write_certificate(&cert, &key);
+
+        let mut files = TlsFiles::new(&cert, &key);
+        let config = files.load().await.unwrap();
+        assert!(!files.changed());
+
+        // Renew the certificate with a later modification time.
+        write_certificate(&cert, &key);
+        let modified = SystemTime::now() + Duration::from_secs(60);
+        File::options()
+            .write(true)
+            .open(&cert)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let inner = config.get_inner();
+        assert!(files.changed());
+        assert!(!files.changed());
+
+        files.reload(&config).await.unwrap();
+        assert!(!Arc::ptr_eq(&inner, &config.get_inner()));
+
+        // Invalid files keep the current configuration.
+        fs::write(&key, "invalid").unwrap();
+        let inner = config.get_inner();
+        assert!(files.reload(&config).await.is_err());
+        assert!(Arc::ptr_eq(&inner, &config.get_inner()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn write_certificate(cert: &Path, key: &Path) {
+        let names = vec!["localhost".to_string()];
+        let CertifiedKey {
+            cert: certificate,
+            key_pair,
+        } = rcgen::generate_simple_self_signed(names).unwrap();
+
+        fs::write(cert, certificate.pem()).unwrap();
+        fs::write(key, key_pair.serialize_pem()).unwrap();
+    }
+}